@@ -0,0 +1,366 @@
+// (C) 2017, Élisabeth Henry
+//
+// Licensed under either of
+// 
+// Apache License, Version 2.0: http://www.apache.org/licenses/LICENSE-2.0
+// MIT license: http://opensource.org/licenses/MIT
+// at your option.
+//
+// Unless you explicitly state otherwise, any contribution intentionally submitted
+// for inclusion in the work by you, as defined in the Apache-2.0 license, shall be
+// dual licensed as above, without any additional terms or conditions.
+
+use level::Level;
+use wall::Wall;
+use wall::WallPosition;
+use dungeon::Doorway;
+
+use std::cmp;
+use std::default::Default;
+use std::usize;
+
+/// Connected regions of a level (see `Level::regions`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Regions {
+    width: usize,
+    depth: usize,
+    labels: Vec<usize>,
+    sizes: Vec<usize>,
+}
+
+impl Regions {
+    /// Returns the number of regions
+    pub fn len(&self) -> usize {
+        self.sizes.len()
+    }
+
+    /// Returns true if there are no regions (i.e. the level is empty)
+    pub fn is_empty(&self) -> bool {
+        self.sizes.is_empty()
+    }
+
+    /// Returns the label of the region containing (x, y), between 0 and
+    /// `len() - 1`
+    pub fn label(&self, x: usize, y: usize) -> usize {
+        debug_assert!(x < self.width && y < self.depth, "x and y must be in level's bounds");
+        self.labels[y * self.width + x]
+    }
+
+    /// Returns the number of tiles of a region
+    pub fn size(&self, label: usize) -> usize {
+        self.sizes[label]
+    }
+
+    /// Returns the tiles of a region
+    pub fn tiles(&self, label: usize) -> Vec<(usize, usize)> {
+        let mut res = vec![];
+        for y in 0..self.depth {
+            for x in 0..self.width {
+                if self.labels[y * self.width + x] == label {
+                    res.push((x, y));
+                }
+            }
+        }
+        res
+    }
+
+    /// Returns the label of the largest region (the first one if there are
+    /// several), or `None` if there are none
+    pub fn largest(&self) -> Option<usize> {
+        let mut res: Option<usize> = None;
+        for (label, &size) in self.sizes.iter().enumerate() {
+            if res.map_or(true, |best| size > self.sizes[best]) {
+                res = Some(label);
+            }
+        }
+        res
+    }
+
+    /// Returns true if two tiles are in the same region
+    pub fn are_connected(&self, a: (usize, usize), b: (usize, usize)) -> bool {
+        self.label(a.0, a.1) == self.label(b.0, b.1)
+    }
+}
+
+/// Places where a level can be cut in two (see `Level::chokepoints`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chokepoints {
+    /// Tiles that disconnect their region if they are blocked
+    pub tiles: Vec<(usize, usize)>,
+    /// Sides of tiles that disconnect their region if a wall is added there,
+    /// always `Right` or `Top` of the tile (x, y)
+    pub edges: Vec<(usize, usize, WallPosition)>,
+}
+
+/// A room found by `Level::detect_rooms`
+#[derive(Debug, Clone, PartialEq)]
+pub struct DetectedRoom {
+    /// Tiles of the room
+    pub tiles: Vec<(usize, usize)>,
+    /// Doorways leading out of the room
+    pub doorways: Vec<Doorway>,
+    /// Indices of the rooms on the other side of the doorways
+    pub connections: Vec<usize>,
+}
+
+/// Returns true if a unit can move from a to b and back
+fn is_open<FT: Default + Clone, WT: Wall>(level: &Level<FT, WT>, a: (usize, usize), b: (usize, usize)) -> bool {
+    level.is_move_possible(a, b) && level.is_move_possible(b, a)
+}
+
+/// Returns the tiles adjacent to pos (not diagonally) that are open both ways
+fn links<FT: Default + Clone, WT: Wall>(level: &Level<FT, WT>, pos: (usize, usize)) -> Vec<(usize, usize)> {
+    let (x, y) = pos;
+    let mut res = vec![];
+    if x > 0 {
+        res.push((x - 1, y));
+    }
+    if y > 0 {
+        res.push((x, y - 1));
+    }
+    res.push((x + 1, y));
+    res.push((x, y + 1));
+    res.retain(|&next| is_open(level, pos, next));
+    res
+}
+
+/// Labels connected components, moves for which `blocked` returns true
+/// being ignored
+fn label<FT, WT, F>(level: &Level<FT, WT>, blocked: F) -> (Vec<usize>, Vec<usize>)
+    where FT: Default + Clone,
+          WT: Wall,
+          F: Fn((usize, usize), (usize, usize)) -> bool {
+    let width = level.width();
+    let mut labels = vec![usize::MAX; width * level.depth()];
+    let mut sizes = vec![];
+    for start in 0..labels.len() {
+        if labels[start] != usize::MAX {
+            continue;
+        }
+        let label = sizes.len();
+        labels[start] = label;
+        let mut todo = vec![(start % width, start / width)];
+        let mut size = 0;
+        while let Some(pos) = todo.pop() {
+            size += 1;
+            for next in links(level, pos) {
+                let i = next.1 * width + next.0;
+                if labels[i] == usize::MAX && !blocked(pos, next) {
+                    labels[i] = label;
+                    todo.push(next);
+                }
+            }
+        }
+        sizes.push(size);
+    }
+    (labels, sizes)
+}
+
+/// Returns the wall position between two tiles adjacent along an axis, as
+/// `Right` or `Top` of the bottom left one
+fn edge(a: (usize, usize), b: (usize, usize)) -> (usize, usize, WallPosition) {
+    if a.1 == b.1 {
+        (cmp::min(a.0, b.0), a.1, WallPosition::Right)
+    } else {
+        (a.0, cmp::min(a.1, b.1), WallPosition::Top)
+    }
+}
+
+pub fn regions<FT: Default + Clone, WT: Wall>(level: &Level<FT, WT>) -> Regions {
+    let (labels, sizes) = label(level, |_, _| false);
+    Regions {
+        width: level.width(),
+        depth: level.depth(),
+        labels,
+        sizes,
+    }
+}
+
+pub fn chokepoints<FT: Default + Clone, WT: Wall>(level: &Level<FT, WT>) -> Chokepoints {
+    let width = level.width();
+    let n = width * level.depth();
+    // Tarjan's algorithm, without recursion since levels can be big
+    let mut discovery = vec![0; n];
+    let mut low = vec![0; n];
+    let mut parent = vec![usize::MAX; n];
+    let mut is_cut = vec![false; n];
+    let mut edges = vec![];
+    let mut timer = 1;
+    for root in 0..n {
+        if discovery[root] != 0 {
+            continue;
+        }
+        discovery[root] = timer;
+        low[root] = timer;
+        timer += 1;
+        let mut root_children = 0;
+        let mut stack = vec![(root, links(level, (root % width, root / width)), 0)];
+        while !stack.is_empty() {
+            let top = stack.len() - 1;
+            let v = stack[top].0;
+            if stack[top].2 < stack[top].1.len() {
+                let next = stack[top].1[stack[top].2];
+                stack[top].2 += 1;
+                let w = next.1 * width + next.0;
+                if discovery[w] == 0 {
+                    parent[w] = v;
+                    discovery[w] = timer;
+                    low[w] = timer;
+                    timer += 1;
+                    if v == root {
+                        root_children += 1;
+                    }
+                    stack.push((w, links(level, next), 0));
+                } else if w != parent[v] {
+                    low[v] = cmp::min(low[v], discovery[w]);
+                }
+            } else {
+                stack.pop();
+                let p = parent[v];
+                if p == usize::MAX {
+                    continue;
+                }
+                low[p] = cmp::min(low[p], low[v]);
+                if low[v] > discovery[p] {
+                    edges.push(edge((p % width, p / width), (v % width, v / width)));
+                }
+                if p != root && low[v] >= discovery[p] {
+                    is_cut[p] = true;
+                }
+            }
+        }
+        if root_children >= 2 {
+            is_cut[root] = true;
+        }
+    }
+    edges.sort_by_key(|edge| (edge.1, edge.0));
+    Chokepoints {
+        tiles: (0..n).filter(|&i| is_cut[i]).map(|i| (i % width, i / width)).collect(),
+        edges,
+    }
+}
+
+/// Returns true if the side of the tile at (x, y) is closed, by a wall or by
+/// the level's border
+fn is_closed<FT: Default + Clone, WT: Wall>(level: &Level<FT, WT>, x: isize, y: isize,
+                                           position: WallPosition) -> bool {
+    x < 0 || y < 0 || x as usize >= level.width() || y as usize >= level.depth()
+        || level.wall(x as usize, y as usize, position).is_some()
+}
+
+/// Returns true if the opening between two adjacent tiles is a doorway, i.e.
+/// it is surrounded by walls on both sides
+fn is_doorway<FT: Default + Clone, WT: Wall>(level: &Level<FT, WT>, a: (usize, usize),
+                                             b: (usize, usize)) -> bool {
+    let (x, y, position) = edge(a, b);
+    let (x, y) = (x as isize, y as isize);
+    if position == WallPosition::Right {
+        is_closed(level, x, y - 1, WallPosition::Right) && is_closed(level, x, y + 1, WallPosition::Right)
+    } else {
+        is_closed(level, x - 1, y, WallPosition::Top) && is_closed(level, x + 1, y, WallPosition::Top)
+    }
+}
+
+pub fn detect_rooms<FT: Default + Clone, WT: Wall>(level: &Level<FT, WT>) -> Vec<DetectedRoom> {
+    let width = level.width();
+    let (labels, sizes) = label(level, |a, b| is_doorway(level, a, b));
+    let mut rooms: Vec<DetectedRoom> = sizes.iter()
+        .map(|&size| DetectedRoom {
+            tiles: Vec::with_capacity(size),
+            doorways: vec![],
+            connections: vec![],
+        })
+        .collect();
+    for y in 0..level.depth() {
+        for x in 0..width {
+            let label = labels[y * width + x];
+            rooms[label].tiles.push((x, y));
+            for &next in &[(x + 1, y), (x, y + 1)] {
+                if next.0 >= width || next.1 >= level.depth() || !is_open(level, (x, y), next) {
+                    continue;
+                }
+                let other = labels[next.1 * width + next.0];
+                if other == label {
+                    continue;
+                }
+                let (x, y, position) = edge((x, y), next);
+                let doorway = Doorway {
+                    x,
+                    y,
+                    position,
+                };
+                for &(a, b) in &[(label, other), (other, label)] {
+                    rooms[a].doorways.push(doorway);
+                    if !rooms[a].connections.contains(&b) {
+                        rooms[a].connections.push(b);
+                    }
+                }
+            }
+        }
+    }
+    rooms
+}
+
+
+#[test]
+fn analysis_regions_and_chokepoints() {
+    let mut level: Level = Level::new(7, 3, 0.0);
+    // Two 3x3 areas linked by a single tile, and a tile walled in
+    for y in 0..3 {
+        if y != 1 {
+            level.set_wall(3, y, WallPosition::Left, Some(()));
+            level.set_wall(3, y, WallPosition::Right, Some(()));
+        }
+    }
+    level.set_wall(3, 0, WallPosition::Top, Some(()));
+    level.set_wall(3, 2, WallPosition::Bottom, Some(()));
+    level.set_wall(0, 2, WallPosition::Right, Some(()));
+    level.set_wall(0, 2, WallPosition::Bottom, Some(()));
+
+    let regions = level.regions();
+    assert_eq!(regions.len(), 4);
+    assert!(regions.are_connected((0, 0), (6, 2)));
+    assert!(!regions.are_connected((0, 0), (3, 0)));
+    assert_eq!(regions.size(regions.label(0, 0)), 18);
+    assert_eq!(regions.largest(), Some(regions.label(6, 2)));
+    assert_eq!(regions.tiles(regions.label(0, 2)), vec![(0, 2)]);
+    assert_eq!(level.isolated_tiles(), vec![(3, 0), (0, 2), (3, 2)]);
+
+    let chokepoints = level.chokepoints();
+    assert_eq!(chokepoints.tiles, vec![(2, 1), (3, 1), (4, 1)]);
+    assert_eq!(chokepoints.edges, vec![(2, 1, WallPosition::Right), (3, 1, WallPosition::Right)]);
+
+    // Blocking a chokepoint really cuts the level
+    level.set_wall(3, 1, WallPosition::Right, Some(()));
+    assert!(!level.regions().are_connected((0, 0), (6, 2)));
+    assert_eq!(level.chokepoints().edges, vec![(2, 1, WallPosition::Right)]);
+}
+
+#[test]
+fn analysis_rooms() {
+    let mut level: Level = Level::new(9, 4, 0.0);
+    level.add_border_walls(());
+    // Three rooms side by side, the middle one with two doors
+    for y in 0..4 {
+        if y != 1 {
+            level.set_wall(2, y, WallPosition::Right, Some(()));
+        }
+        if y != 2 {
+            level.set_wall(5, y, WallPosition::Right, Some(()));
+        }
+    }
+    let rooms = level.detect_rooms();
+    assert_eq!(rooms.len(), 3);
+    assert_eq!(rooms[0].tiles.len(), 12);
+    assert_eq!(rooms[0].doorways, vec![Doorway { x: 2, y: 1, position: WallPosition::Right }]);
+    assert_eq!(rooms[0].connections, vec![1]);
+    assert_eq!(rooms[1].doorways.len(), 2);
+    assert_eq!(rooms[1].connections, vec![0, 2]);
+    assert_eq!(rooms[2].doorways, vec![Doorway { x: 5, y: 2, position: WallPosition::Right }]);
+
+    // A wide opening isn't a doorway
+    level.set_wall(5, 1, WallPosition::Right, None);
+    let rooms = level.detect_rooms();
+    assert_eq!(rooms.len(), 2);
+    assert_eq!(rooms[1].tiles.len(), 24);
+}
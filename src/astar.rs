@@ -0,0 +1,109 @@
+// (C) 2017, Élisabeth Henry
+//
+// Licensed under either of
+// 
+// Apache License, Version 2.0: http://www.apache.org/licenses/LICENSE-2.0
+// MIT license: http://opensource.org/licenses/MIT
+// at your option.
+//
+// Unless you explicitly state otherwise, any contribution intentionally submitted
+// for inclusion in the work by you, as defined in the Apache-2.0 license, shall be
+// dual licensed as above, without any additional terms or conditions.
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// Entry of the open list
+struct Open<N> {
+    estimate: f32,
+    cost: f32,
+    node: N,
+}
+
+impl<N> PartialEq for Open<N> {
+    fn eq(&self, other: &Open<N>) -> bool {
+        self.estimate == other.estimate
+    }
+}
+
+impl<N> Eq for Open<N> {}
+
+impl<N> PartialOrd for Open<N> {
+    fn partial_cmp(&self, other: &Open<N>) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<N> Ord for Open<N> {
+    fn cmp(&self, other: &Open<N>) -> Ordering {
+        // BinaryHeap is a max-heap, and we want the lowest estimate first
+        other.estimate.partial_cmp(&self.estimate).unwrap_or(Ordering::Equal)
+    }
+}
+
+/// Generic A* implementation: finds the shortest path between `start` and `goal`.
+///
+/// * `neighbours` returns the nodes reachable from a node, with the cost to get there
+/// * `heuristic` returns an estimation of the cost between a node and the goal,
+///   which must never be superior to the actual cost.
+///
+/// Returns the path (including `start` and `goal`) and its cost.
+pub fn search<N, FN, FH>(start: N,
+                         goal: N,
+                         neighbours: FN,
+                         heuristic: FH) -> Option<(Vec<N>, f32)>
+    where N: Copy + Eq + Hash,
+          FN: Fn(N) -> Vec<(N, f32)>,
+          FH: Fn(N) -> f32 {
+    let mut open = BinaryHeap::new();
+    let mut costs: HashMap<N, f32> = HashMap::new();
+    let mut parents: HashMap<N, N> = HashMap::new();
+
+    costs.insert(start, 0.0);
+    open.push(Open {
+        estimate: heuristic(start),
+        cost: 0.0,
+        node: start,
+    });
+
+    while let Some(Open { cost, node, .. }) = open.pop() {
+        if node == goal {
+            let mut path = vec![goal];
+            let mut current = goal;
+            while let Some(&parent) = parents.get(&current) {
+                path.push(parent);
+                current = parent;
+            }
+            path.reverse();
+            return Some((path, cost));
+        }
+        if costs.get(&node).map_or(false, |&c| c < cost) {
+            // Outdated entry
+            continue;
+        }
+        for (next, step) in neighbours(node) {
+            let next_cost = cost + step;
+            if costs.get(&next).map_or(true, |&c| next_cost < c) {
+                costs.insert(next, next_cost);
+                parents.insert(next, node);
+                open.push(Open {
+                    estimate: next_cost + heuristic(next),
+                    cost: next_cost,
+                    node: next,
+                });
+            }
+        }
+    }
+    None
+}
+
+/// Octile distance between two tiles, i.e. the cost of a path between them
+/// with no obstacle when diagonal moves cost sqrt(2)
+pub fn octile(a: (usize, usize), b: (usize, usize)) -> f32 {
+    let dx = (a.0 as f32 - b.0 as f32).abs();
+    let dy = (a.1 as f32 - b.1 as f32).abs();
+    let (min, max) = if dx < dy { (dx, dy) } else { (dy, dx) };
+    max + (2.0f32.sqrt() - 1.0) * min
+}
@@ -0,0 +1,52 @@
+// (C) 2017, Élisabeth Henry
+//
+// Licensed under either of
+// 
+// Apache License, Version 2.0: http://www.apache.org/licenses/LICENSE-2.0
+// MIT license: http://opensource.org/licenses/MIT
+// at your option.
+//
+// Unless you explicitly state otherwise, any contribution intentionally submitted
+// for inclusion in the work by you, as defined in the Apache-2.0 license, shall be
+// dual licensed as above, without any additional terms or conditions.
+
+/// Axis along which a bridge can be crossed
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum BridgeAxis {
+    /// Bridge goes from left to right
+    X,
+    /// Bridge goes from bottom to top
+    Y,
+}
+
+/// Upper walkable surface of a tile, above its floor (e.g. a bridge over a
+/// river or a chasm).
+///
+/// A bridge can only be entered or left at its ends, along its axis; units can
+/// still walk on the floor underneath.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Bridge {
+    /// Height of the deck
+    pub z: f32,
+    /// Axis along which the bridge can be crossed
+    pub axis: BridgeAxis,
+}
+
+impl Bridge {
+    /// Creates a new bridge deck at height `z`
+    pub fn new(z: f32, axis: BridgeAxis) -> Bridge {
+        Bridge {
+            z,
+            axis,
+        }
+    }
+}
+
+/// Walkable surface of a tile
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Surface {
+    /// The floor of the tile
+    Ground,
+    /// The deck of the bridge above the floor, if any
+    Bridge,
+}
@@ -0,0 +1,333 @@
+// (C) 2017, Élisabeth Henry
+//
+// Licensed under either of
+// 
+// Apache License, Version 2.0: http://www.apache.org/licenses/LICENSE-2.0
+// MIT license: http://opensource.org/licenses/MIT
+// at your option.
+//
+// Unless you explicitly state otherwise, any contribution intentionally submitted
+// for inclusion in the work by you, as defined in the Apache-2.0 license, shall be
+// dual licensed as above, without any additional terms or conditions.
+
+use level::Level;
+use wall::Wall;
+use wall::WallPosition;
+use rng::Rng;
+
+use std::default::Default;
+use std::usize;
+
+/// What to do with the parts of a cave that can't be reached from the largest one
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Connectivity {
+    /// Leave them as they are
+    Ignore,
+    /// Fill them with rock
+    Remove,
+    /// Dig tunnels to link them to the rest of the cave
+    Tunnel,
+}
+
+/// A generated cave: the level and which tiles are floor or rock.
+#[derive(Debug)]
+pub struct Cave<FT=(), WT=()> {
+    level: Level<FT, WT>,
+    floor: Vec<bool>,
+    wall: WT,
+}
+
+impl<FT: Default + Clone,
+     WT: Wall> Cave<FT, WT> {
+    /// Creates a new cave from the tiles that are floor (in the same order as
+    /// the tiles of a level: `y * width + x`). Walls follow the boundaries
+    /// between floor and rock, and the borders of the level.
+    pub fn new(width: usize, depth: usize, floor: Vec<bool>, wall: WT) -> Cave<FT, WT> {
+        debug_assert!(floor.len() == width * depth, "floor must have one value per tile");
+        let mut cave = Cave {
+            level: Level::new(width, depth, 0.0),
+            floor,
+            wall,
+        };
+        cave.build_walls();
+        cave
+    }
+
+    /// Get access to the level
+    pub fn level(&self) -> &Level<FT, WT> {
+        &self.level
+    }
+
+    /// Returns the level, dropping information about rock tiles
+    pub fn into_level(self) -> Level<FT, WT> {
+        self.level
+    }
+
+    /// Returns true if tile (x, y) is floor, false if it is rock
+    pub fn is_floor(&self, x: usize, y: usize) -> bool {
+        debug_assert!(x < self.level.width() && y < self.level.depth(), "x and y must be in level's bounds");
+        self.floor[y * self.level.width() + x]
+    }
+
+    /// Sets the walls of the level from the floor tiles
+    fn build_walls(&mut self) {
+        let width = self.level.width();
+        let depth = self.level.depth();
+        let wall = self.wall.clone();
+        let data = |is_wall: bool| if is_wall { Some(wall.clone()) } else { None };
+        for y in 0..depth {
+            for x in 0..width {
+                let floor = self.floor[y * width + x];
+                let right = if x + 1 < width { floor != self.floor[y * width + x + 1] } else { floor };
+                let top = if y + 1 < depth { floor != self.floor[(y + 1) * width + x] } else { floor };
+                self.level.set_wall(x, y, WallPosition::Right, data(right));
+                self.level.set_wall(x, y, WallPosition::Top, data(top));
+                if x == 0 {
+                    self.level.set_wall(x, y, WallPosition::Left, data(floor));
+                }
+                if y == 0 {
+                    self.level.set_wall(x, y, WallPosition::Bottom, data(floor));
+                }
+            }
+        }
+    }
+
+    /// Returns the connected parts of the cave, largest first.
+    pub fn regions(&self) -> Vec<Vec<(usize, usize)>> {
+        let width = self.level.width();
+        let mut seen = vec![false; self.floor.len()];
+        let mut regions = vec![];
+        for y in 0..self.level.depth() {
+            for x in 0..width {
+                if !self.floor[y * width + x] || seen[y * width + x] {
+                    continue;
+                }
+                let region = self.level.flood_fill((x, y));
+                for &(x, y) in &region {
+                    seen[y * width + x] = true;
+                }
+                regions.push(region);
+            }
+        }
+        // Largest first, with a stable sort: regions of the same size keep
+        // their order
+        regions.sort_by_key(|region| usize::MAX - region.len());
+        regions
+    }
+
+    /// Fills with rock every part of the cave that isn't connected to the
+    /// largest one
+    pub fn remove_pockets(&mut self) {
+        let width = self.level.width();
+        for region in self.regions().iter().skip(1) {
+            for &(x, y) in region {
+                self.floor[y * width + x] = false;
+            }
+        }
+        self.build_walls();
+    }
+
+    /// Digs tunnels so that every part of the cave is connected to the largest
+    /// one.
+    ///
+    /// Each pocket is linked by the shortest possible L-shaped tunnel to a part
+    /// of the cave that is already connected.
+    pub fn tunnel_pockets(&mut self) {
+        let width = self.level.width();
+        let mut regions = self.regions().into_iter();
+        let mut connected = match regions.next() {
+            Some(region) => region,
+            None => return,
+        };
+        for region in regions {
+            let mut best = None;
+            for &a in &region {
+                for &b in &connected {
+                    let dist = (a.0 as isize - b.0 as isize).abs() + (a.1 as isize - b.1 as isize).abs();
+                    if best.map_or(true, |(_, _, d)| dist < d) {
+                        best = Some((a, b, dist));
+                    }
+                }
+            }
+            let (mut pos, to, _) = best.unwrap();
+            while pos != to {
+                if pos.0 != to.0 {
+                    pos.0 = if pos.0 < to.0 { pos.0 + 1 } else { pos.0 - 1 };
+                } else {
+                    pos.1 = if pos.1 < to.1 { pos.1 + 1 } else { pos.1 - 1 };
+                }
+                if !self.floor[pos.1 * width + pos.0] {
+                    self.floor[pos.1 * width + pos.0] = true;
+                    connected.push(pos);
+                }
+            }
+            connected.extend(region);
+        }
+        self.build_walls();
+    }
+}
+
+/// Generates caves with a cellular automaton.
+///
+/// Tiles are first randomly set to rock or floor, then smoothed a few times: a
+/// tile becomes rock if at least 5 of its 8 neighbours are rock (tiles outside
+/// the level count as rock), or if it is already rock and 4 of them are. The
+/// output only depends on the settings and on the seed.
+///
+/// # Example
+///
+/// ```
+/// use isometric::{CaveGenerator, Connectivity};
+///
+/// let mut generator = CaveGenerator::new(60, 40, 7);
+/// generator.set_connectivity(Connectivity::Tunnel);
+/// let cave = generator.generate::<(), ()>(());
+///
+/// // Every floor tile can be reached from every other one
+/// assert_eq!(cave.regions().len(), 1);
+/// ```
+#[derive(Debug, Clone)]
+pub struct CaveGenerator {
+    width: usize,
+    depth: usize,
+    seed: u64,
+    fill_probability: f32,
+    iterations: usize,
+    connectivity: Connectivity,
+}
+
+impl CaveGenerator {
+    /// Creates a new generator for levels of the given dimensions.
+    ///
+    /// By default, 45% of the tiles are rock at first, there are 4 smoothing
+    /// iterations and pockets are removed.
+    pub fn new(width: usize, depth: usize, seed: u64) -> CaveGenerator {
+        CaveGenerator {
+            width,
+            depth,
+            seed,
+            fill_probability: 0.45,
+            iterations: 4,
+            connectivity: Connectivity::Remove,
+        }
+    }
+
+    /// Returns the seed
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Sets the seed
+    pub fn set_seed(&mut self, seed: u64) -> &mut Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Returns the probability for a tile to be rock before smoothing
+    pub fn fill_probability(&self) -> f32 {
+        self.fill_probability
+    }
+
+    /// Sets the probability for a tile to be rock before smoothing
+    pub fn set_fill_probability(&mut self, probability: f32) -> &mut Self {
+        self.fill_probability = probability;
+        self
+    }
+
+    /// Returns the number of smoothing iterations
+    pub fn iterations(&self) -> usize {
+        self.iterations
+    }
+
+    /// Sets the number of smoothing iterations
+    pub fn set_iterations(&mut self, iterations: usize) -> &mut Self {
+        self.iterations = iterations;
+        self
+    }
+
+    /// Returns what is done with unreachable parts of the cave
+    pub fn connectivity(&self) -> Connectivity {
+        self.connectivity
+    }
+
+    /// Sets what is done with unreachable parts of the cave
+    pub fn set_connectivity(&mut self, connectivity: Connectivity) -> &mut Self {
+        self.connectivity = connectivity;
+        self
+    }
+
+    /// Generates a cave, using `wall` for all the walls
+    pub fn generate<FT: Default + Clone, WT: Wall>(&self, wall: WT) -> Cave<FT, WT> {
+        let mut rng = Rng::new(self.seed);
+        let (width, depth) = (self.width, self.depth);
+        let mut rock: Vec<bool> = (0..width * depth)
+            .map(|_| rng.chance(self.fill_probability))
+            .collect();
+
+        for _ in 0..self.iterations {
+            let mut next = rock.clone();
+            for y in 0..depth {
+                for x in 0..width {
+                    let mut count = 0;
+                    for dx in -1..2 {
+                        for dy in -1..2 {
+                            if (dx, dy) == (0, 0) {
+                                continue;
+                            }
+                            let nx = x as isize + dx;
+                            let ny = y as isize + dy;
+                            if nx < 0 || ny < 0 || nx >= width as isize || ny >= depth as isize
+                                || rock[ny as usize * width + nx as usize] {
+                                count += 1;
+                            }
+                        }
+                    }
+                    let i = y * width + x;
+                    next[i] = count >= 5 || (rock[i] && count >= 4);
+                }
+            }
+            rock = next;
+        }
+
+        let mut cave = Cave::new(width, depth, rock.iter().map(|&rock| !rock).collect(), wall);
+        match self.connectivity {
+            Connectivity::Ignore => (),
+            Connectivity::Remove => cave.remove_pockets(),
+            Connectivity::Tunnel => cave.tunnel_pockets(),
+        }
+        cave
+    }
+}
+
+
+#[test]
+fn cave_connectivity() {
+    for seed in 0..10 {
+        let mut generator = CaveGenerator::new(40, 30, seed);
+        generator.set_connectivity(Connectivity::Ignore);
+        let cave = generator.generate::<(), ()>(());
+        let regions = cave.regions().len();
+        let floor = cave.regions().iter().map(|r| r.len()).sum::<usize>();
+
+        generator.set_connectivity(Connectivity::Remove);
+        let removed = generator.generate::<(), ()>(());
+        assert!(removed.regions().len() <= 1);
+        if regions > 1 {
+            assert!(removed.regions()[0].len() < floor);
+        }
+
+        generator.set_connectivity(Connectivity::Tunnel);
+        let tunneled = generator.generate::<(), ()>(());
+        assert!(tunneled.regions().len() <= 1);
+        assert!(tunneled.regions().iter().map(|r| r.len()).sum::<usize>() >= floor);
+    }
+}
+
+#[test]
+fn cave_walls() {
+    let cave: Cave = Cave::new(3, 1, vec![true, false, true], ());
+    assert!(cave.level().wall(0, 0, WallPosition::Right).is_some());
+    assert!(cave.level().wall(0, 0, WallPosition::Left).is_some());
+    assert!(cave.level().wall(1, 0, WallPosition::Top).is_none());
+    assert_eq!(cave.regions().len(), 2);
+}
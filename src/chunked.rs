@@ -0,0 +1,394 @@
+// (C) 2017, Élisabeth Henry
+//
+// Licensed under either of
+// 
+// Apache License, Version 2.0: http://www.apache.org/licenses/LICENSE-2.0
+// MIT license: http://opensource.org/licenses/MIT
+// at your option.
+//
+// Unless you explicitly state otherwise, any contribution intentionally submitted
+// for inclusion in the work by you, as defined in the Apache-2.0 license, shall be
+// dual licensed as above, without any additional terms or conditions.
+
+use level::Level;
+use wall::Wall;
+use wall::WallPosition;
+use visibility;
+
+use std::collections::HashMap;
+use std::default::Default;
+
+/// Hooks to stream the chunks of a `ChunkedLevel`, e.g. from and to the disk.
+pub trait ChunkLoader<FT, WT> {
+    /// Called when a chunk that isn't in memory is needed.
+    ///
+    /// Should return `None` if this chunk has never been saved, in which case
+    /// a new, flat one is created.
+    fn load(&mut self, chunk: (isize, isize)) -> Option<Level<FT, WT>>;
+
+    /// Called when a chunk is removed from memory
+    fn unload(&mut self, chunk: (isize, isize), level: Level<FT, WT>);
+}
+
+/// A level without bounds, split in square chunks that are created (or loaded)
+/// on demand.
+///
+/// Tile coordinates are signed. Each chunk is a `Level` of `chunk_size` ×
+/// `chunk_size` tiles; walls on the seam between two chunks are stored in both
+/// of them, and kept consistent by `set_wall`.
+///
+/// Queries never load anything: tiles of chunks that aren't in memory are flat
+/// (at the default height), without walls and with the default floor data.
+/// Setters load or create the chunk they modify.
+///
+/// # Example
+///
+/// ```
+/// use isometric::{ChunkedLevel, WallPosition};
+///
+/// let mut level: ChunkedLevel = ChunkedLevel::new(16, 0.0);
+/// level.set_z(-100, 2000, 3.0);
+/// assert_eq!(level.z(-100, 2000), 3.0);
+/// assert_eq!(level.z(-101, 2000), 0.0);
+///
+/// // Wall on the seam between two chunks
+/// level.set_wall(-1, 0, WallPosition::Right, Some(()));
+/// assert!(level.wall(0, 0, WallPosition::Left).is_some());
+/// assert!(!level.is_move_possible((0, 0), (-1, 0)));
+/// ```
+pub struct ChunkedLevel<FT=(), WT=()> {
+    chunk_size: usize,
+    default_z: f32,
+    chunks: HashMap<(isize, isize), Level<FT, WT>>,
+    loader: Option<Box<dyn ChunkLoader<FT, WT>>>,
+    no_wall: Option<WT>,
+    default_data: FT,
+}
+
+/// Splits a coordinate in the index of its chunk and the coordinate inside
+/// this chunk
+fn split(c: isize, size: usize) -> (isize, usize) {
+    let size = size as isize;
+    let chunk = if c >= 0 { c / size } else { (c + 1) / size - 1 };
+    (chunk, (c - chunk * size) as usize)
+}
+
+impl<FT: Default + Clone,
+     WT: Wall> ChunkedLevel<FT, WT> {
+    /// Creates a new, empty, chunked level.
+    ///
+    /// `default_z` is the height of the tiles of new chunks.
+    pub fn new(chunk_size: usize, default_z: f32) -> ChunkedLevel<FT, WT> {
+        debug_assert!(chunk_size > 0, "chunk size must not be zero");
+        ChunkedLevel {
+            chunk_size,
+            default_z,
+            chunks: HashMap::new(),
+            loader: None,
+            no_wall: None,
+            default_data: FT::default(),
+        }
+    }
+
+    /// Returns the width (and depth) of a chunk
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    /// Sets the loader called when chunks are loaded and unloaded
+    pub fn set_loader(&mut self, loader: Box<dyn ChunkLoader<FT, WT>>) -> &mut Self {
+        self.loader = Some(loader);
+        self
+    }
+
+    /// Returns the chunk containing tile (x, y), and the position of this
+    /// tile in it
+    pub fn chunk_of(&self, x: isize, y: isize) -> ((isize, isize), (usize, usize)) {
+        let (chunk_x, x) = split(x, self.chunk_size);
+        let (chunk_y, y) = split(y, self.chunk_size);
+        ((chunk_x, chunk_y), (x, y))
+    }
+
+    /// Returns true if the chunk is in memory
+    pub fn is_loaded(&self, chunk: (isize, isize)) -> bool {
+        self.chunks.contains_key(&chunk)
+    }
+
+    /// Returns the chunks that are in memory
+    pub fn loaded_chunks(&self) -> Vec<(isize, isize)> {
+        self.chunks.keys().cloned().collect()
+    }
+
+    /// Get access to a chunk, if it is in memory
+    pub fn chunk(&self, chunk: (isize, isize)) -> Option<&Level<FT, WT>> {
+        self.chunks.get(&chunk)
+    }
+
+    /// Get mutable access to a chunk, loading or creating it if needed
+    pub fn chunk_mut(&mut self, chunk: (isize, isize)) -> &mut Level<FT, WT> {
+        self.load_chunk(chunk);
+        self.chunks.get_mut(&chunk).unwrap()
+    }
+
+    /// Loads a chunk (see `ChunkLoader::load`), or creates it if the loader
+    /// doesn't know it. Does nothing if the chunk is already in memory.
+    pub fn load_chunk(&mut self, chunk: (isize, isize)) {
+        if self.chunks.contains_key(&chunk) {
+            return;
+        }
+        let loaded = match self.loader {
+            Some(ref mut loader) => loader.load(chunk),
+            None => None,
+        };
+        let level = match loaded {
+            Some(level) => {
+                debug_assert!(level.width() == self.chunk_size && level.depth() == self.chunk_size,
+                              "loaded chunk doesn't have the right size");
+                level
+            },
+            None => Level::new(self.chunk_size, self.chunk_size, self.default_z),
+        };
+        self.chunks.insert(chunk, level);
+    }
+
+    /// Removes a chunk from memory, passing it to the loader (see
+    /// `ChunkLoader::unload`). Does nothing if the chunk isn't in memory.
+    pub fn unload_chunk(&mut self, chunk: (isize, isize)) {
+        if let Some(level) = self.chunks.remove(&chunk) {
+            if let Some(ref mut loader) = self.loader {
+                loader.unload(chunk, level);
+            }
+        }
+    }
+
+    /// Makes sure that only the chunks within `radius` chunks of the chunk
+    /// containing tile `pos` are in memory, loading and unloading chunks as
+    /// needed.
+    pub fn stream_around(&mut self, pos: (isize, isize), radius: usize) {
+        let (center, _) = self.chunk_of(pos.0, pos.1);
+        let radius = radius as isize;
+        let is_near = |chunk: (isize, isize)| {
+            (chunk.0 - center.0).abs() <= radius && (chunk.1 - center.1).abs() <= radius
+        };
+        let far: Vec<_> = self.chunks.keys().cloned().filter(|&c| !is_near(c)).collect();
+        for chunk in far {
+            self.unload_chunk(chunk);
+        }
+        for x in (center.0 - radius)..(center.0 + radius + 1) {
+            for y in (center.1 - radius)..(center.1 + radius + 1) {
+                self.load_chunk((x, y));
+            }
+        }
+    }
+
+    /// Returns the level containing a tile and the position of the tile in it,
+    /// if it is in memory
+    fn tile(&self, x: isize, y: isize) -> Option<(&Level<FT, WT>, usize, usize)> {
+        let (chunk, (x, y)) = self.chunk_of(x, y);
+        self.chunks.get(&chunk).map(|level| (level, x, y))
+    }
+
+    /// Returns the level containing a tile and the position of the tile in it,
+    /// loading it if needed
+    fn tile_mut(&mut self, x: isize, y: isize) -> (&mut Level<FT, WT>, usize, usize) {
+        let (chunk, (x, y)) = self.chunk_of(x, y);
+        (self.chunk_mut(chunk), x, y)
+    }
+
+    /// Get the z value (height level in the world) of a tile
+    pub fn z(&self, x: isize, y: isize) -> f32 {
+        match self.tile(x, y) {
+            Some((level, x, y)) => level.z(x, y),
+            None => self.default_z,
+        }
+    }
+
+    /// Set the z value (height in the world) of a tile
+    pub fn set_z(&mut self, x: isize, y: isize, z: f32) -> &mut Self {
+        {
+            let (level, x, y) = self.tile_mut(x, y);
+            level.set_z(x, y, z);
+        }
+        self
+    }
+
+    /// Returns the wall's data (if any) at a tile's position or None if there isn't.
+    pub fn wall(&self, x: isize, y: isize, wall: WallPosition) -> &Option<WT> {
+        match self.tile(x, y) {
+            Some((level, x, y)) => level.wall(x, y, wall),
+            None => &self.no_wall,
+        }
+    }
+
+    /// Sets the wall at tile x, y. To remove the wall, set it to `None`.
+    ///
+    /// If the wall is on the seam between two chunks, it is set in both of them.
+    pub fn set_wall(&mut self, x: isize, y: isize, wall: WallPosition, data: Option<WT>) {
+        let (other, other_wall) = match wall {
+            WallPosition::Left => ((x - 1, y), WallPosition::Right),
+            WallPosition::Right => ((x + 1, y), WallPosition::Left),
+            WallPosition::Bottom => ((x, y - 1), WallPosition::Top),
+            WallPosition::Top => ((x, y + 1), WallPosition::Bottom),
+        };
+        if self.chunk_of(x, y).0 != self.chunk_of(other.0, other.1).0 {
+            let (level, other_x, other_y) = self.tile_mut(other.0, other.1);
+            level.set_wall(other_x, other_y, other_wall, data.clone());
+        }
+        let (level, x, y) = self.tile_mut(x, y);
+        level.set_wall(x, y, wall, data);
+    }
+
+    /// Gets a reference to custom floor data (e.g. the tile's representation)
+    pub fn floor_data(&self, x: isize, y: isize) -> &FT {
+        match self.tile(x, y) {
+            Some((level, x, y)) => level.floor_data(x, y),
+            None => &self.default_data,
+        }
+    }
+
+    /// Sets the value of custom floor data (e.g. the tile's representation)
+    pub fn set_floor_data(&mut self, x: isize, y: isize, data: FT) {
+        let (level, x, y) = self.tile_mut(x, y);
+        level.set_floor_data(x, y, data);
+    }
+
+    /// Returns true if character move is possible, false else
+    ///
+    /// Same rules as `Level::is_move_possible`, except there are no bounds:
+    /// tiles that are not in memory can't be entered nor left instead. A wall
+    /// on the seam between two chunks blocks the move if either of them has it.
+    pub fn is_move_possible(&self,
+                            start_pos: (isize, isize),
+                            end_pos: (isize, isize)) -> bool {
+        if start_pos == end_pos {
+            return true;
+        }
+        let dx = end_pos.0 - start_pos.0;
+        let dy = end_pos.1 - start_pos.1;
+        if dx.abs() > 1 || dy.abs() > 1 {
+            // Not adjacent
+            false
+        } else if dx.abs() + dy.abs() == 2 {
+            // Diagonal: possible if first dx then dy is possible OR first dy
+            // then dx is possible
+            let intermediate = (start_pos.0 + dx, start_pos.1);
+            if self.is_move_possible(start_pos, intermediate)
+                && self.is_move_possible(intermediate, end_pos) {
+                true
+            } else {
+                let intermediate = (start_pos.0, start_pos.1 + dy);
+                self.is_move_possible(start_pos, intermediate)
+                    && self.is_move_possible(intermediate, end_pos)
+            }
+        } else {
+            let (wall, other_wall) = match (dx, dy) {
+                (1, 0) => (WallPosition::Right, WallPosition::Left),
+                (-1, 0) => (WallPosition::Left, WallPosition::Right),
+                (0, 1) => (WallPosition::Top, WallPosition::Bottom),
+                (0, -1) => (WallPosition::Bottom, WallPosition::Top),
+                (_, _) => unreachable!(),
+            };
+            match (self.tile(start_pos.0, start_pos.1), self.tile(end_pos.0, end_pos.1)) {
+                (Some((start, start_x, start_y)), Some((end, end_x, end_y))) => {
+                    start.wall(start_x, start_y, wall).is_none()
+                        && end.wall(end_x, end_y, other_wall).is_none()
+                },
+                _ => false,
+            }
+        }
+    }
+
+    /// Returns a "visibility" matrix of bools centered on the pos view (see
+    /// `Level::visible_from`).
+    pub fn visible_from(&self, pos: (isize, isize), radius: usize) -> Vec<Vec<bool>> {
+        visibility::cast(pos, radius, &|start, end| self.is_move_possible(start, end))
+    }
+
+    /// Convenience method wrapping `visible_from`, returning a closure instead of
+    /// a vector.
+    pub fn visibility(&self, pos: (isize, isize), radius: usize) -> Box<dyn Fn(isize, isize) -> bool> {
+        let matrix = self.visible_from(pos, radius);
+        Box::new(move |x, y| {
+            let i = x - pos.0 + radius as isize;
+            let j = y - pos.1 + radius as isize;
+            if i < 0 || j < 0 || i > 2 * radius as isize || j > 2 * radius as isize {
+                false
+            } else {
+                matrix[i as usize][j as usize]
+            }
+        })
+    }
+}
+
+
+#[test]
+fn chunked_coordinates() {
+    let level: ChunkedLevel = ChunkedLevel::new(16, 0.0);
+    assert_eq!(level.chunk_of(0, 15), ((0, 0), (0, 15)));
+    assert_eq!(level.chunk_of(16, -1), ((1, -1), (0, 15)));
+    assert_eq!(level.chunk_of(-16, -17), ((-1, -2), (0, 15)));
+}
+
+#[test]
+fn chunked_walls() {
+    let mut level: ChunkedLevel = ChunkedLevel::new(4, 0.0);
+    for y in -10..10 {
+        level.set_wall(-1, y, WallPosition::Right, Some(()));
+    }
+    assert!(level.wall(0, 5, WallPosition::Left).is_some());
+    assert!(!level.is_move_possible((0, -3), (-1, -2)));
+    let f = level.visibility((2, 0), 4);
+    assert!(f(0, 0));
+    assert!(!f(-1, 0));
+}
+
+#[test]
+fn chunked_seams() {
+    let mut level: ChunkedLevel = ChunkedLevel::new(4, 0.0);
+    // Wall stored in a single chunk, the other one isn't in memory
+    level.chunk_mut((0, 0)).set_wall(3, 0, WallPosition::Right, Some(()));
+    assert!(!level.is_loaded((1, 0)));
+    assert!(!level.is_move_possible((3, 0), (4, 0)));
+    assert!(!level.is_move_possible((4, 0), (3, 0)));
+    assert!(!level.is_move_possible((3, 1), (4, 1)));
+    assert!(!level.visibility((0, 0), 8)(5, 0));
+
+    level.load_chunk((1, 0));
+    assert!(!level.is_move_possible((4, 0), (3, 0)));
+    assert!(level.is_move_possible((4, 1), (3, 1)));
+    assert!(level.is_move_possible((3, 1), (4, 1)));
+    // Visibility is symmetric across the seam
+    assert_eq!(level.visibility((3, 0), 2)(4, 0), level.visibility((4, 0), 2)(3, 0));
+}
+
+#[test]
+fn chunked_streaming() {
+    use std::rc::Rc;
+    use std::cell::RefCell;
+
+    struct Saver(Rc<RefCell<HashMap<(isize, isize), Level>>>);
+
+    impl ChunkLoader<(), ()> for Saver {
+        fn load(&mut self, chunk: (isize, isize)) -> Option<Level> {
+            self.0.borrow_mut().remove(&chunk)
+        }
+
+        fn unload(&mut self, chunk: (isize, isize), level: Level) {
+            self.0.borrow_mut().insert(chunk, level);
+        }
+    }
+
+    let disk = Rc::new(RefCell::new(HashMap::new()));
+    let mut level: ChunkedLevel = ChunkedLevel::new(8, 0.0);
+    level.set_loader(Box::new(Saver(disk.clone())));
+    level.set_z(3, 3, 5.0);
+    level.stream_around((100, 100), 1);
+    assert!(!level.is_loaded((0, 0)));
+    assert_eq!(level.loaded_chunks().len(), 9);
+    assert!(disk.borrow().contains_key(&(0, 0)));
+    // Not in memory: flat
+    assert_eq!(level.z(3, 3), 0.0);
+
+    level.stream_around((0, 0), 0);
+    assert_eq!(level.z(3, 3), 5.0);
+}
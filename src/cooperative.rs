@@ -0,0 +1,333 @@
+// (C) 2017, Élisabeth Henry
+//
+// Licensed under either of
+// 
+// Apache License, Version 2.0: http://www.apache.org/licenses/LICENSE-2.0
+// MIT license: http://opensource.org/licenses/MIT
+// at your option.
+//
+// Unless you explicitly state otherwise, any contribution intentionally submitted
+// for inclusion in the work by you, as defined in the Apache-2.0 license, shall be
+// dual licensed as above, without any additional terms or conditions.
+
+use level::Level;
+use wall::Wall;
+use astar;
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::default::Default;
+use std::u32;
+
+/// Move from a tile to another between a given time and the next
+type Move = ((usize, usize), (usize, usize), u32);
+
+/// Cooperative pathfinder for groups of units moving at the same time
+/// (cooperative A*).
+///
+/// Paths are planned one unit after the other, in order of priority. Each
+/// planned path is stored in a space-time reservation table, so the next
+/// units avoid being on the same tile at the same time, or swapping tiles,
+/// with the previous ones. Units can wait in place to let others pass, and
+/// once a unit reached its goal it stays there.
+///
+/// Time is counted in steps: a unit either moves to a neighbour tile (with
+/// the same rules as `Level::find_path`) or waits during a step, and the
+/// tile of a path at index `t` is where the unit stands at time `t`.
+///
+/// # Example
+///
+/// ```
+/// use isometric::{Level, CooperativePlanner};
+///
+/// let level: Level = Level::new(5, 1, 0.0);
+/// let mut planner = CooperativePlanner::new(&level);
+///
+/// // The second unit must wait for the first one to go away
+/// let paths = planner.plan(&level, &[((1, 0), (4, 0)), ((0, 0), (2, 0))]);
+/// assert_eq!(paths[0], Some(vec![(1, 0), (2, 0), (3, 0), (4, 0)]));
+/// assert_eq!(paths[1], Some(vec![(0, 0), (1, 0), (2, 0)]));
+///
+/// // Nobody can go through a unit standing on its goal
+/// assert_eq!(planner.find_path(&level, (0, 0), (4, 0)), None);
+/// ```
+#[derive(Debug, Clone)]
+pub struct CooperativePlanner {
+    // Tiles occupied at a given time
+    tiles: HashSet<((usize, usize), u32)>,
+    // Moves planned so far
+    moves: HashSet<Move>,
+    // Last time a tile is occupied while moving
+    last: HashMap<(usize, usize), u32>,
+    // Tiles occupied forever from a given time, by units that arrived
+    parked: HashMap<(usize, usize), u32>,
+    max_time: u32,
+}
+
+impl CooperativePlanner {
+    /// Creates a planner with no reservations, for a level of the same
+    /// dimensions as `level`
+    pub fn new<FT: Default + Clone, WT: Wall>(level: &Level<FT, WT>) -> CooperativePlanner {
+        CooperativePlanner {
+            tiles: HashSet::new(),
+            moves: HashSet::new(),
+            last: HashMap::new(),
+            parked: HashMap::new(),
+            max_time: 2 * (level.width() + level.depth()) as u32,
+        }
+    }
+
+    /// Returns the maximal duration of a path
+    pub fn max_time(&self) -> u32 {
+        self.max_time
+    }
+
+    /// Sets the maximal duration of a path (default: twice the sum of the
+    /// dimensions of the level). Searches giving up sooner are faster when
+    /// there is no path.
+    pub fn set_max_time(&mut self, max_time: u32) -> &mut Self {
+        self.max_time = max_time;
+        self
+    }
+
+    /// Removes all reservations
+    pub fn clear(&mut self) -> &mut Self {
+        self.tiles.clear();
+        self.moves.clear();
+        self.last.clear();
+        self.parked.clear();
+        self
+    }
+
+    /// Returns true if a unit is planned to stand on `pos` at time `time`
+    pub fn is_reserved(&self, pos: (usize, usize), time: u32) -> bool {
+        self.tiles.contains(&(pos, time)) || self.parked.get(&pos).map_or(false, |&t| time >= t)
+    }
+
+    /// Reserves the tiles of a path (e.g. a unit that doesn't go through the
+    /// planner), the unit staying on the last tile afterwards
+    pub fn reserve(&mut self, path: &[(usize, usize)]) -> &mut Self {
+        for (t, &pos) in path.iter().enumerate() {
+            let t = t as u32;
+            self.tiles.insert((pos, t));
+            let last = self.last.entry(pos).or_insert(t);
+            if *last < t {
+                *last = t;
+            }
+            if let Some(&next) = path.get(t as usize + 1) {
+                self.moves.insert((pos, next, t));
+            }
+        }
+        if let Some(&pos) = path.last() {
+            self.parked.insert(pos, path.len() as u32 - 1);
+        }
+        self
+    }
+
+    /// Returns true if a unit can move from `from` to `to` between `time`
+    /// and `time + 1` (waiting if they are the same)
+    fn is_free(&self, from: (usize, usize), to: (usize, usize), time: u32) -> bool {
+        !self.is_reserved(to, time + 1) && !self.moves.contains(&(to, from, time))
+    }
+
+    /// Finds a path from `start` to `goal` that avoids the units already
+    /// planned, and reserves it. Returns `None` if there is none within
+    /// `max_time` steps.
+    pub fn find_path<FT, WT>(&mut self, level: &Level<FT, WT>,
+                             start: (usize, usize),
+                             goal: (usize, usize)) -> Option<Vec<(usize, usize)>>
+        where FT: Default + Clone,
+              WT: Wall {
+        if start.0 >= level.width() || start.1 >= level.depth()
+            || goal.0 >= level.width() || goal.1 >= level.depth() {
+            return None;
+        }
+        // Number of steps to the goal, ignoring other units
+        let distances = level.dijkstra_map(&[goal], |_, _| 1.0, self.max_time as f32);
+        // No path at all, even ignoring other units
+        distances.value(start.0, start.1)?;
+        // The goal can only be kept once nobody else needs to go through it
+        let free_from = self.last.get(&goal).map_or(0, |&t| t + 1);
+
+        // Reaching the search's goal means reaching the goal for good
+        let end = (goal, u32::MAX);
+        let res = astar::search((start, 0), end,
+                                |(pos, t)| {
+                                    let mut res = vec![];
+                                    if pos == goal && t >= free_from && !self.parked.contains_key(&goal) {
+                                        res.push((end, 0.0));
+                                    }
+                                    if t >= self.max_time {
+                                        return res;
+                                    }
+                                    let mut nexts = level.neighbours(pos);
+                                    nexts.push(pos);
+                                    for next in nexts {
+                                        if distances.value(next.0, next.1).is_some()
+                                            && self.is_free(pos, next, t) {
+                                            res.push(((next, t + 1), 1.0));
+                                        }
+                                    }
+                                    res
+                                },
+                                |(pos, t)| if t == u32::MAX {
+                                    0.0
+                                } else {
+                                    distances.value(pos.0, pos.1).unwrap_or(0.0)
+                                });
+        res.map(|(nodes, _)| {
+            let path: Vec<_> = nodes.into_iter()
+                .filter(|&(_, t)| t != u32::MAX)
+                .map(|(pos, _)| pos)
+                .collect();
+            self.reserve(&path);
+            path
+        })
+    }
+
+    /// Plans the paths of several units, given their start and goal tiles.
+    /// Units are planned in order, so the first ones have priority.
+    ///
+    /// The start tiles of all units are reserved at time 0 first, since
+    /// they are occupied when the movement begins. Units for which there is
+    /// no path get `None` and are planned as staying on their start tile, so
+    /// the paths of the other ones don't go through them.
+    #[allow(clippy::type_complexity)]
+    pub fn plan<FT, WT>(&mut self, level: &Level<FT, WT>,
+                        agents: &[((usize, usize), (usize, usize))]) -> Vec<Option<Vec<(usize, usize)>>>
+        where FT: Default + Clone,
+              WT: Wall {
+        let initial = self.clone();
+        let mut failed = HashSet::new();
+        loop {
+            for (i, &(start, _)) in agents.iter().enumerate() {
+                if failed.contains(&i) {
+                    self.reserve(&[start]);
+                } else {
+                    self.tiles.insert((start, 0));
+                }
+            }
+            let paths: Vec<_> = agents.iter()
+                .enumerate()
+                .map(|(i, &(start, goal))| if failed.contains(&i) {
+                    None
+                } else {
+                    self.tiles.remove(&(start, 0));
+                    self.find_path(level, start, goal)
+                })
+                .collect();
+            // Units that failed change the plan of the others, which can in
+            // turn fail, so plan again until there are no new failures
+            let new_failures: Vec<_> = paths.iter()
+                .enumerate()
+                .filter(|&(i, path)| path.is_none() && !failed.contains(&i))
+                .map(|(i, _)| i)
+                .collect();
+            if new_failures.is_empty() {
+                return paths;
+            }
+            failed.extend(new_failures);
+            *self = initial.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+fn check_paths(level: &Level, paths: &[Vec<(usize, usize)>]) {
+    let duration = paths.iter().map(|path| path.len()).max().unwrap_or(0);
+    let at = |path: &Vec<(usize, usize)>, t: usize| path[if t < path.len() { t } else { path.len() - 1 }];
+    for t in 0..duration {
+        for (i, a) in paths.iter().enumerate() {
+            if t + 1 < a.len() && a[t] != a[t + 1] {
+                assert!(level.is_move_possible(a[t], a[t + 1]));
+            }
+            for b in &paths[i + 1..] {
+                assert!(at(a, t) != at(b, t), "collision at time {}", t);
+                assert!(!(at(a, t) == at(b, t + 1) && at(a, t + 1) == at(b, t)), "swap at time {}", t);
+            }
+        }
+    }
+}
+
+#[test]
+fn cooperative_corridor_swap() {
+    use wall::WallPosition;
+
+    // A corridor with a single alcove in the middle
+    let mut level: Level = Level::new(7, 2, 0.0);
+    for x in 0..7 {
+        if x != 3 {
+            level.set_wall(x, 0, WallPosition::Top, Some(()));
+        }
+    }
+    level.set_wall(3, 1, WallPosition::Left, Some(()));
+    level.set_wall(3, 1, WallPosition::Right, Some(()));
+
+    // Independent paths collide head-on
+    let a = level.find_path((0, 0), (6, 0)).unwrap();
+    let b = level.find_path((6, 0), (0, 0)).unwrap();
+    assert!(a.iter().zip(b.iter()).any(|(p, q)| p == q));
+
+    let mut planner = CooperativePlanner::new(&level);
+    let paths = planner.plan(&level, &[((0, 0), (6, 0)), ((6, 0), (0, 0))]);
+    let paths: Vec<_> = paths.into_iter().map(|path| path.unwrap()).collect();
+    assert_eq!(paths[0].last(), Some(&(6, 0)));
+    assert_eq!(paths[1].last(), Some(&(0, 0)));
+    // One of them steps aside
+    assert!(paths[1].contains(&(3, 1)));
+    check_paths(&level, &paths);
+
+    // Without the alcove, they can't swap
+    level.set_wall(3, 0, WallPosition::Top, Some(()));
+    planner.clear();
+    let agents = [((0, 0), (6, 0)), ((6, 0), (0, 0))];
+    let paths = planner.plan(&level, &agents);
+    // The second unit is stuck, so the first one can't reach its goal either
+    assert_eq!(paths, vec![None, None]);
+
+    planner.clear();
+    let agents = [((0, 0), (5, 0)), ((6, 0), (0, 0))];
+    let paths = planner.plan(&level, &agents);
+    assert_eq!(paths[0], Some(vec![(0, 0), (1, 0), (2, 0), (3, 0), (4, 0), (5, 0)]));
+    assert_eq!(paths[1], None);
+    let paths: Vec<_> = paths.into_iter()
+        .zip(agents.iter())
+        .map(|(path, &(start, _))| path.unwrap_or(vec![start]))
+        .collect();
+    check_paths(&level, &paths);
+}
+
+#[test]
+fn cooperative_group() {
+    use wall::WallPosition;
+
+    // Two rooms linked by a door, units going from one to the other
+    let mut level: Level = Level::new(9, 5, 0.0);
+    for y in 0..5 {
+        if y != 2 {
+            level.set_wall(4, y, WallPosition::Right, Some(()));
+        }
+    }
+    let agents: Vec<_> = (0..5).map(|y| ((0, y), (8, 4 - y)))
+        .chain((0..5).map(|y| ((7, y), (1, y))))
+        .collect();
+    let mut planner = CooperativePlanner::new(&level);
+    let paths: Vec<_> = planner.plan(&level, &agents)
+        .into_iter()
+        .map(|path| path.unwrap())
+        .collect();
+    for (path, &(start, goal)) in paths.iter().zip(agents.iter()) {
+        assert_eq!(path.first(), Some(&start));
+        assert_eq!(path.last(), Some(&goal));
+    }
+    check_paths(&level, &paths);
+
+    // Diagonal moves can't cut corners
+    let mut level: Level = Level::new(3, 3, 0.0);
+    level.set_wall(1, 1, WallPosition::Left, Some(()));
+    level.set_wall(1, 1, WallPosition::Bottom, Some(()));
+    let mut planner = CooperativePlanner::new(&level);
+    let path = planner.find_path(&level, (0, 0), (1, 1)).unwrap();
+    assert_eq!(path.len(), 4);
+    check_paths(&level, &[path]);
+}
@@ -0,0 +1,146 @@
+// (C) 2017, Élisabeth Henry
+//
+// Licensed under either of
+// 
+// Apache License, Version 2.0: http://www.apache.org/licenses/LICENSE-2.0
+// MIT license: http://opensource.org/licenses/MIT
+// at your option.
+//
+// Unless you explicitly state otherwise, any contribution intentionally submitted
+// for inclusion in the work by you, as defined in the Apache-2.0 license, shall be
+// dual licensed as above, without any additional terms or conditions.
+
+use wall::WallPosition;
+
+/// How walls that are cut away are drawn
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CutawayMode {
+    /// Walls are shortened to a stub of the given height
+    Stub(f32),
+    /// Walls are drawn with the given opacity (0.0 is invisible, 1.0 is opaque)
+    Transparent(f32),
+}
+
+/// Settings to cut away walls that hide a focus point (typically, the player's
+/// character).
+///
+/// Since the camera looks at the level from the bottom left, walls that are
+/// between the camera and the focus are walls at the bottom or at the left of a
+/// tile which is behind them, i.e. walls whose line is at a lower x (for `Left`
+/// and `Right` walls) or a lower y (for `Top` and `Bottom` walls) than the focus,
+/// and which are drawn in front of it (at a lower x + y). The radius is measured
+/// across the screen (along x - y), since walls above or below the focus on
+/// screen can still hide it.
+///
+/// # Example
+///
+/// ```
+/// use isometric::{Cutaway, WallPosition};
+///
+/// let cutaway = Cutaway::new(5.5, 5.5, 3.0);
+///
+/// // Wall between (5, 4) and (5, 5) hides the focus
+/// assert!(cutaway.is_cut(5, 5, WallPosition::Bottom));
+/// assert!(cutaway.is_cut(5, 4, WallPosition::Top));
+///
+/// // Wall between (5, 5) and (5, 6) is behind it
+/// assert!(!cutaway.is_cut(5, 5, WallPosition::Top));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Cutaway {
+    focus: (f32, f32),
+    radius: f32,
+    mode: CutawayMode,
+}
+
+impl Cutaway {
+    /// Creates new cutaway settings, with walls made transparent within
+    /// `radius` of the focus point (`x`, `y`).
+    pub fn new(x: f32, y: f32, radius: f32) -> Cutaway {
+        Cutaway {
+            focus: (x, y),
+            radius,
+            mode: CutawayMode::Transparent(0.3),
+        }
+    }
+
+    /// Returns the focus point
+    pub fn focus(&self) -> (f32, f32) {
+        self.focus
+    }
+
+    /// Sets the focus point (e.g. the position of the player's character)
+    pub fn set_focus(&mut self, x: f32, y: f32) -> &mut Self {
+        self.focus = (x, y);
+        self
+    }
+
+    /// Returns the radius around the focus point where walls are cut away
+    pub fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    /// Sets the radius around the focus point where walls are cut away
+    pub fn set_radius(&mut self, radius: f32) -> &mut Self {
+        self.radius = radius;
+        self
+    }
+
+    /// Returns how cut walls are drawn
+    pub fn mode(&self) -> CutawayMode {
+        self.mode
+    }
+
+    /// Sets how cut walls are drawn
+    pub fn set_mode(&mut self, mode: CutawayMode) -> &mut Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Returns true if the wall at this position of tile (x, y) must be cut away
+    pub fn is_cut(&self, x: usize, y: usize, position: WallPosition) -> bool {
+        let x = x as f32;
+        let y = y as f32;
+        // Middle of the wall, and whether it is in front of the focus
+        let (middle, in_front) = match position {
+            WallPosition::Left => ((x, y + 0.5), x <= self.focus.0),
+            WallPosition::Right => ((x + 1.0, y + 0.5), x + 1.0 <= self.focus.0),
+            WallPosition::Bottom => ((x + 0.5, y), y <= self.focus.1),
+            WallPosition::Top => ((x + 0.5, y + 1.0), y + 1.0 <= self.focus.1),
+        };
+        if !in_front || middle.0 + middle.1 > self.focus.0 + self.focus.1 {
+            return false;
+        }
+        let across = (middle.0 - middle.1) - (self.focus.0 - self.focus.1);
+        across.abs() <= self.radius
+    }
+}
+
+
+#[test]
+fn cutaway_near_walls() {
+    let cutaway = Cutaway::new(5.5, 5.5, 2.0);
+    assert!(cutaway.is_cut(5, 5, WallPosition::Left));
+    assert!(cutaway.is_cut(4, 5, WallPosition::Right));
+    assert!(!cutaway.is_cut(5, 5, WallPosition::Right));
+    assert!(!cutaway.is_cut(5, 5, WallPosition::Top));
+    assert!(cutaway.is_cut(4, 4, WallPosition::Bottom));
+}
+
+#[test]
+fn cutaway_radius() {
+    let mut cutaway = Cutaway::new(5.5, 5.5, 2.0);
+    assert!(!cutaway.is_cut(0, 5, WallPosition::Left));
+    cutaway.set_radius(6.0);
+    assert!(cutaway.is_cut(0, 5, WallPosition::Left));
+    cutaway.set_focus(0.5, 0.5);
+    assert!(!cutaway.is_cut(5, 5, WallPosition::Left));
+}
+
+#[test]
+fn cutaway_behind_focus() {
+    // These walls are at the left or below a tile, but drawn behind the focus
+    let cutaway = Cutaway::new(5.5, 5.5, 3.0);
+    assert!(!cutaway.is_cut(5, 7, WallPosition::Left));
+    assert!(!cutaway.is_cut(8, 5, WallPosition::Bottom));
+}
@@ -0,0 +1,299 @@
+// (C) 2017, Élisabeth Henry
+//
+// Licensed under either of
+// 
+// Apache License, Version 2.0: http://www.apache.org/licenses/LICENSE-2.0
+// MIT license: http://opensource.org/licenses/MIT
+// at your option.
+//
+// Unless you explicitly state otherwise, any contribution intentionally submitted
+// for inclusion in the work by you, as defined in the Apache-2.0 license, shall be
+// dual licensed as above, without any additional terms or conditions.
+
+use level::Level;
+use wall::Wall;
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::default::Default;
+use std::f32;
+
+/// Entry of the open list
+struct Open {
+    value: f32,
+    pos: (usize, usize),
+}
+
+impl PartialEq for Open {
+    fn eq(&self, other: &Open) -> bool {
+        self.value == other.value
+    }
+}
+
+impl Eq for Open {}
+
+impl PartialOrd for Open {
+    fn partial_cmp(&self, other: &Open) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Open {
+    fn cmp(&self, other: &Open) -> Ordering {
+        // BinaryHeap is a max-heap, and we want the lowest value first
+        other.value.partial_cmp(&self.value).unwrap_or(Ordering::Equal)
+    }
+}
+
+/// Lowers the values of `map` so that each tile's value is at most the value
+/// of a neighbour plus the cost of moving from there (tiles with a finite
+/// value act as sources). Values above `max_cost` are left infinite.
+pub fn scan<FT, WT, F>(level: &Level<FT, WT>, map: &mut DijkstraMap, cost: F, max_cost: f32)
+    where FT: Default + Clone,
+          WT: Wall,
+          F: Fn((usize, usize), (usize, usize)) -> f32 {
+    let mut sources = vec![];
+    for y in 0..map.depth {
+        for x in 0..map.width {
+            if map.values[y * map.width + x].is_finite() {
+                sources.push((x, y));
+            }
+        }
+    }
+    scan_from(level, map, &sources, cost, max_cost);
+}
+
+/// Same as `scan`, but only relaxes the values from `sources`: the other
+/// tiles are assumed to be up to date with their neighbours. Returns the
+/// tiles whose value was lowered (possibly several times).
+pub fn scan_from<FT, WT, F>(level: &Level<FT, WT>, map: &mut DijkstraMap, sources: &[(usize, usize)],
+                            cost: F, max_cost: f32) -> Vec<(usize, usize)>
+    where FT: Default + Clone,
+          WT: Wall,
+          F: Fn((usize, usize), (usize, usize)) -> f32 {
+    let mut open = BinaryHeap::new();
+    for &pos in sources {
+        let value = map.values[pos.1 * map.width + pos.0];
+        if value.is_finite() {
+            open.push(Open { value, pos });
+        }
+    }
+    let mut lowered = vec![];
+    while let Some(Open { value, pos }) = open.pop() {
+        if value > map.values[pos.1 * map.width + pos.0] {
+            // Already found a better way
+            continue;
+        }
+        for next in level.neighbours(pos) {
+            let step = cost(pos, next);
+            if step.is_nan() || step < 0.0 || step.is_infinite() {
+                continue;
+            }
+            let value = value + step;
+            let i = next.1 * map.width + next.0;
+            if value <= max_cost && value < map.values[i] {
+                map.values[i] = value;
+                lowered.push(next);
+                open.push(Open { value, pos: next });
+            }
+        }
+    }
+    lowered
+}
+
+/// A distance field over a level: the cost of the cheapest path from each
+/// tile to the closest source (see `Level::dijkstra_map`).
+///
+/// Walking down the field leads to a source, walking up leads away from
+/// them. Fields can be combined to mix several goals.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DijkstraMap {
+    width: usize,
+    depth: usize,
+    values: Vec<f32>,
+}
+
+impl DijkstraMap {
+    /// Creates a map where no tile has a value
+    pub fn new(width: usize, depth: usize) -> DijkstraMap {
+        DijkstraMap {
+            width,
+            depth,
+            values: vec![f32::INFINITY; width * depth],
+        }
+    }
+
+    /// Returns the width of the map
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Returns the depth of the map
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Returns the value of a tile, or `None` if it can't reach a source
+    pub fn value(&self, x: usize, y: usize) -> Option<f32> {
+        debug_assert!(x < self.width && y < self.depth, "x and y must be in map's bounds");
+        let value = self.values[y * self.width + x];
+        if value.is_finite() { Some(value) } else { None }
+    }
+
+    /// Sets the value of a tile (`None` to unset it)
+    pub fn set_value(&mut self, x: usize, y: usize, value: Option<f32>) -> &mut Self {
+        debug_assert!(x < self.width && y < self.depth, "x and y must be in map's bounds");
+        self.values[y * self.width + x] = value.unwrap_or(f32::INFINITY);
+        self
+    }
+
+    /// Returns the reachable neighbour with the lowest (`sign` = 1.0) or
+    /// highest (`sign` = -1.0) value, if it is better than `pos`'s
+    fn step<FT: Default + Clone, WT: Wall>(&self, level: &Level<FT, WT>, pos: (usize, usize),
+                                           sign: f32) -> Option<(usize, usize)> {
+        let mut best = pos;
+        let mut best_value = self.values[pos.1 * self.width + pos.0] * sign;
+        for next in level.neighbours(pos) {
+            let value = self.values[next.1 * self.width + next.0];
+            if value.is_finite() && value * sign < best_value {
+                best = next;
+                best_value = value * sign;
+            }
+        }
+        if best != pos { Some(best) } else { None }
+    }
+
+    /// Returns the neighbour of `pos` to move to in order to get closer to a
+    /// source, or `None` if there is none (e.g. `pos` is a source)
+    pub fn descend<FT: Default + Clone, WT: Wall>(&self, level: &Level<FT, WT>,
+                                                  pos: (usize, usize)) -> Option<(usize, usize)> {
+        self.step(level, pos, 1.0)
+    }
+
+    /// Returns the neighbour of `pos` to move to in order to get further from
+    /// the sources, or `None` if there is none
+    pub fn ascend<FT: Default + Clone, WT: Wall>(&self, level: &Level<FT, WT>,
+                                                 pos: (usize, usize)) -> Option<(usize, usize)> {
+        self.step(level, pos, -1.0)
+    }
+
+    /// Follows the map downhill from `pos` until reaching a tile with no
+    /// lower neighbour (usually a source). The path includes `pos`.
+    pub fn path_down<FT: Default + Clone, WT: Wall>(&self, level: &Level<FT, WT>,
+                                                    pos: (usize, usize)) -> Vec<(usize, usize)> {
+        let mut path = vec![pos];
+        let mut pos = pos;
+        while let Some(next) = self.descend(level, pos) {
+            path.push(next);
+            pos = next;
+        }
+        path
+    }
+
+    /// Returns the weighted sum of several maps, which must have the same
+    /// dimensions. A tile only has a value if it has one in every map.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use isometric::{Level, DijkstraMap};
+    ///
+    /// let level: Level = Level::new(10, 1, 0.0);
+    /// let cost = |_, _| 1.0;
+    /// let food = level.dijkstra_map(&[(0, 0)], &cost, 100.0);
+    /// let water = level.dijkstra_map(&[(9, 0)], &cost, 100.0);
+    ///
+    /// // Thirst is more pressing than hunger
+    /// let needs = DijkstraMap::combine(&[(&food, 1.0), (&water, 2.0)]);
+    /// assert_eq!(needs.value(3, 0), Some(3.0 + 2.0 * 6.0));
+    /// assert_eq!(needs.descend(&level, (3, 0)), Some((4, 0)));
+    /// ```
+    pub fn combine(maps: &[(&DijkstraMap, f32)]) -> DijkstraMap {
+        debug_assert!(!maps.is_empty(), "at least one map is needed");
+        let (width, depth) = (maps[0].0.width, maps[0].0.depth);
+        let mut res = DijkstraMap::new(width, depth);
+        for i in 0..width * depth {
+            let mut sum = 0.0;
+            for &(map, weight) in maps {
+                debug_assert!(map.width == width && map.depth == depth, "maps must have the same dimensions");
+                sum += map.values[i] * weight;
+            }
+            res.values[i] = if sum.is_nan() { f32::INFINITY } else { sum };
+        }
+        res
+    }
+
+    /// Returns a "flee map": walking down it leads away from the sources, but
+    /// towards safe places rather than into dead ends.
+    ///
+    /// Values are multiplied by `-coefficient` (e.g. 1.2; higher values
+    /// prefer running further over running smarter), then rescanned.
+    pub fn flee<FT, WT, F>(&self, level: &Level<FT, WT>, coefficient: f32, cost: F) -> DijkstraMap
+        where FT: Default + Clone,
+              WT: Wall,
+              F: Fn((usize, usize), (usize, usize)) -> f32 {
+        let mut res = self.clone();
+        for value in &mut res.values {
+            if value.is_finite() {
+                *value *= -coefficient;
+            }
+        }
+        scan(level, &mut res, cost, f32::INFINITY);
+        res
+    }
+}
+
+
+#[test]
+fn dijkstra_distances() {
+    use wall::WallPosition;
+
+    let mut level: Level = Level::new(6, 6, 0.0);
+    for y in 0..5 {
+        level.set_wall(2, y, WallPosition::Right, Some(()));
+    }
+    let cost = |_, _| 1.0;
+    let map = level.dijkstra_map(&[(0, 0), (5, 0)], cost, 100.0);
+    assert_eq!(map.value(0, 0), Some(0.0));
+    assert_eq!(map.value(2, 2), Some(2.0));
+    assert_eq!(map.value(3, 0), Some(2.0));
+    // (2, 5) is closer to (5, 0) through the opening
+    assert_eq!(map.value(2, 5), Some(5.0));
+
+    let path = map.path_down(&level, (3, 3));
+    assert_eq!(path.last(), Some(&(5, 0)));
+    assert_eq!(path.len(), 4);
+    assert_eq!(map.descend(&level, (0, 0)), None);
+
+    // Bounded map
+    let map = level.dijkstra_map(&[(0, 0)], cost, 3.0);
+    assert_eq!(map.value(2, 3), Some(3.0));
+    assert_eq!(map.value(2, 4), None);
+
+    // Impassable tiles
+    let map = level.dijkstra_map(&[(0, 0)], |_, to| if to == (1, 1) { f32::INFINITY } else { 1.0 }, 100.0);
+    assert_eq!(map.value(1, 1), None);
+    assert_eq!(map.value(2, 2), Some(3.0));
+}
+
+#[test]
+fn dijkstra_flee() {
+    use wall::WallPosition;
+
+    // Corridor with a room at its left end, and a dead end on the right
+    let mut level: Level = Level::new(12, 3, 0.0);
+    for x in 3..12 {
+        level.set_wall(x, 0, WallPosition::Top, Some(()));
+        level.set_wall(x, 1, WallPosition::Top, Some(()));
+    }
+    let cost = |from: (usize, usize), to: (usize, usize)| {
+        if from.0 != to.0 && from.1 != to.1 { 1.5 } else { 1.0 }
+    };
+    let threat = level.dijkstra_map(&[(6, 1)], cost, 100.0);
+    assert_eq!(threat.ascend(&level, (8, 1)), Some((9, 1)));
+
+    // Fleeing to the room on the left is better than being stuck at the end
+    let flee = threat.flee(&level, 1.2, cost);
+    assert_eq!(flee.descend(&level, (5, 1)), Some((4, 1)));
+    assert!(flee.value(0, 2).unwrap() < flee.value(11, 1).unwrap());
+}
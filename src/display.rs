@@ -11,9 +11,14 @@
 // dual licensed as above, without any additional terms or conditions.
 
 use level::Level;
+use layered::LayeredLevel;
 use camera::Camera;
 use wall::WallPosition;
 use camera::V3;
+use light::Lighting;
+use shadow::ShadowMap;
+use fog::{FogOfWar, TileVisibility};
+use cutaway::{Cutaway, CutawayMode};
 use wall::Wall;
 
 use glium;
@@ -32,16 +37,46 @@ struct Vertex {
     tex_coords: [f32; 2],
     normal: [f32; 3],
     lighted: f32,
+    shadow: f32,
+    occlusion: f32,
+    fog: f32,
+    alpha: f32,
     final_z: f32,
 }
 
-implement_vertex!(Vertex, position, tex_coords, normal, lighted, final_z);
+implement_vertex!(Vertex, position, tex_coords, normal, lighted, shadow, occlusion, fog, alpha, final_z);
+
+/// Offset of the depth (`final_z`) of each layer, so upper layers are drawn
+/// over the lower ones
+const LAYER_DEPTH: f32 = 0.15;
+
+/// Opacity of the water surface, so the bed can be seen through it
+const WATER_ALPHA: f32 = 0.6;
+
+/// Per-tile values that are passed to the vertices
+#[derive(Copy, Clone, Debug)]
+struct TileShading {
+    /// Light level, depending on the distance to the point of view
+    lighted: f32,
+    /// 1.0 if the tile is in the shadow, 0.0 else
+    shadow: f32,
+    /// 0.0 if the tile has never been seen, 0.5 if it has been explored,
+    /// 1.0 if it is visible
+    fog: f32,
+}
 
 /// Contains a level and add methods to render it
 pub struct Renderer<'a, FT=(), WT=()> {
-    level: Level<FT, WT>,
+    layers: LayeredLevel<FT, WT>,
+    current_layer: usize,
+    hide_upper_layers: bool,
     display: &'a Display,
     program: Program,
+    lighting: Lighting,
+    shadows: bool,
+    occlusion_strength: f32,
+    fog: Option<FogOfWar>,
+    cutaway: Option<Cutaway>,
 }
 
 impl<'a,
@@ -49,43 +84,197 @@ impl<'a,
      WT:Wall> Renderer<'a, FT, WT> {
     /// Creates a new renderer from an existing level and a glutin display
     pub fn new(level: Level<FT, WT>, display: &'a Display) -> Renderer<'a, FT, WT> {
+        Renderer::with_layers(LayeredLevel::new(level), display)
+    }
+
+    /// Creates a new renderer from an existing level with multiple layers and a
+    /// glutin display
+    pub fn with_layers(layers: LayeredLevel<FT, WT>, display: &'a Display) -> Renderer<'a, FT, WT> {
         Renderer {
-            level: level,
+            layers: layers,
+            current_layer: 0,
+            hide_upper_layers: true,
             display: display,
             program: program!(display,
                               140 => {
                                   vertex: include_str!("../shaders/renderer.glslv"),
                                   fragment: include_str!("../shaders/renderer.glslf"),
                      }).unwrap(),
+            lighting: Lighting::new(),
+            shadows: false,
+            occlusion_strength: 0.5,
+            fog: None,
+            cutaway: None,
         }
     }
 
-    /// Get access to the level
+    /// Get access to the level (the current layer if there are several)
     pub fn level(&self) -> &Level<FT, WT> {
-        &self.level
+        self.layers.layer(self.current_layer)
     }
 
-    /// Get mutable access to the level
+    /// Get mutable access to the level (the current layer if there are several)
     pub fn level_mut(&mut self) -> &mut Level<FT, WT> {
-        &mut self.level
+        self.layers.layer_mut(self.current_layer)
+    }
+
+    /// Get access to all the layers of the level
+    pub fn layers(&self) -> &LayeredLevel<FT, WT> {
+        &self.layers
+    }
+
+    /// Get mutable access to all the layers of the level
+    pub fn layers_mut(&mut self) -> &mut LayeredLevel<FT, WT> {
+        &mut self.layers
+    }
+
+    /// Returns the current layer (e.g. the one the player is on)
+    pub fn current_layer(&self) -> usize {
+        self.current_layer
+    }
+
+    /// Sets the current layer (e.g. the one the player is on)
+    pub fn set_current_layer(&mut self, layer: usize) -> &mut Self {
+        debug_assert!(layer < self.layers.layer_count(), "layer must exist");
+        self.current_layer = layer;
+        self
+    }
+
+    /// Returns true if layers above the current one are hidden
+    pub fn hide_upper_layers(&self) -> bool {
+        self.hide_upper_layers
+    }
+
+    /// Sets whether layers above the current one are hidden (default) or not
+    pub fn set_hide_upper_layers(&mut self, hide: bool) -> &mut Self {
+        self.hide_upper_layers = hide;
+        self
+    }
+
+    /// Get access to the lighting settings
+    pub fn lighting(&self) -> &Lighting {
+        &self.lighting
+    }
+
+    /// Get mutable access to the lighting settings
+    pub fn lighting_mut(&mut self) -> &mut Lighting {
+        &mut self.lighting
+    }
+
+    /// Sets the lighting settings (e.g. from `Lighting::from_time_of_day`)
+    pub fn set_lighting(&mut self, lighting: Lighting) -> &mut Self {
+        self.lighting = lighting;
+        self
+    }
+
+    /// Returns true if walls and terrain cast shadows
+    pub fn shadows(&self) -> bool {
+        self.shadows
+    }
+
+    /// Enables or disables shadows cast by walls and terrain (disabled by default).
+    ///
+    /// Shadows are computed on the CPU with a `ShadowMap` each time the level is rendered.
+    pub fn set_shadows(&mut self, shadows: bool) -> &mut Self {
+        self.shadows = shadows;
+        self
+    }
+
+    /// Returns the strength of ambient occlusion
+    pub fn occlusion_strength(&self) -> f32 {
+        self.occlusion_strength
+    }
+
+    /// Sets the strength of ambient occlusion, from 0.0 (disabled) to 1.0
+    /// (fully occluded corners are black). Default is 0.5.
+    pub fn set_occlusion_strength(&mut self, strength: f32) -> &mut Self {
+        self.occlusion_strength = strength;
+        self
+    }
+
+    /// Get access to the fog of war, if any
+    pub fn fog(&self) -> Option<&FogOfWar> {
+        self.fog.as_ref()
+    }
+
+    /// Get mutable access to the fog of war, if any (e.g. to update it)
+    pub fn fog_mut(&mut self) -> Option<&mut FogOfWar> {
+        self.fog.as_mut()
+    }
+
+    /// Sets the fog of war.
+    ///
+    /// If set, tiles that have been explored but aren't visible are drawn
+    /// desaturated, and tiles that have never been seen are drawn black.
+    /// Else, visibility is computed from the camera's position.
+    ///
+    /// # Panics
+    ///
+    /// If the fog of war doesn't have the same dimensions as the level.
+    pub fn set_fog(&mut self, fog: Option<FogOfWar>) -> &mut Self {
+        if let Some(ref fog) = fog {
+            assert!(fog.width() == self.level().width() && fog.depth() == self.level().depth(),
+                    "fog of war and level must have the same dimensions");
+        }
+        self.fog = fog;
+        self
+    }
+
+    /// Get access to the wall cutaway settings, if any
+    pub fn cutaway(&self) -> Option<&Cutaway> {
+        self.cutaway.as_ref()
+    }
+
+    /// Get mutable access to the wall cutaway settings, if any (e.g. to move
+    /// its focus along with the player)
+    pub fn cutaway_mut(&mut self) -> Option<&mut Cutaway> {
+        self.cutaway.as_mut()
+    }
+
+    /// Sets the wall cutaway settings.
+    ///
+    /// If set, normal walls that hide the focus point are shortened or drawn
+    /// transparent. Cliff and border walls are never cut away.
+    pub fn set_cutaway(&mut self, cutaway: Option<Cutaway>) -> &mut Self {
+        self.cutaway = cutaway;
+        self
+    }
+
+    /// Returns how the wall at this position must be drawn, if it must be cut away
+    fn cutaway_mode(&self, x: usize, y: usize, position: WallPosition) -> Option<CutawayMode> {
+        match self.cutaway {
+            Some(ref cutaway) if cutaway.is_cut(x, y, position) => Some(cutaway.mode()),
+            _ => None,
+        }
     }
 
     // Add vertical wall to the vertices
     fn add_horizontal_wall(&self, vertices: &mut Vec<Vertex>, data: &WT,
-                           f: &Fn(usize, usize) -> f32,
+                           f: &Fn(usize, usize) -> TileShading,
+                           cut: Option<CutawayMode>,
+                           level: &Level<FT, WT>, layer: usize,
                            x: usize, y: usize, z: f32, other_z: f32) {
         let other_z = if data.is_cliff() {
             other_z
         } else if data.is_border() {
             z - 3.0
+        } else if let Some(CutawayMode::Stub(height)) = cut {
+            z + height.min(data.height())
         } else {
-            z + 1.0
+            z + data.height()
+        };
+        let alpha = match cut {
+            Some(CutawayMode::Transparent(alpha)) if !data.is_cliff() && !data.is_border() => alpha,
+            _ => 1.0,
         };
-        let final_z = (x + y) as f32 / 2.0 - 0.1;
-        let lighted = {
+        let final_z = (x + y) as f32 / 2.0 - 0.1 - layer as f32 * LAYER_DEPTH;
+        let TileShading { lighted, shadow, fog } = {
             let y = if y > 0 { y - 1 } else { y };
             f(x, y)
         };
+        let occ_a = level.corner_occlusion(x, y, z);
+        let occ_b = level.corner_occlusion(x + 1, y, z);
+        let (occ_c, occ_d) = (0.0, 0.0);
         let x = x as f32;
         let y = y as f32;
         let a = [x, y, z];
@@ -98,6 +287,10 @@ impl<'a,
             tex_coords: [0.0, 1.0],
             normal: normal,
             lighted: lighted,
+            shadow: shadow,
+            occlusion: occ_a,
+            fog: fog,
+            alpha: alpha,
             final_z: final_z,
                 
         });
@@ -106,6 +299,10 @@ impl<'a,
             tex_coords: [1.0, 0.0],
             normal: normal,
             lighted: lighted,
+            shadow: shadow,
+            occlusion: occ_b,
+            fog: fog,
+            alpha: alpha,
             final_z: final_z,
         });
         vertices.push(Vertex {
@@ -113,6 +310,10 @@ impl<'a,
             tex_coords: [0.0, 1.0],
             normal: normal,
             lighted: lighted,
+            shadow: shadow,
+            occlusion: occ_c,
+            fog: fog,
+            alpha: alpha,
             final_z: final_z,
         });
         vertices.push(Vertex {
@@ -120,6 +321,10 @@ impl<'a,
             tex_coords: [1.0, 0.0],
             normal: normal,
             lighted: lighted,
+            shadow: shadow,
+            occlusion: occ_b,
+            fog: fog,
+            alpha: alpha,
             final_z: final_z,
         });
         vertices.push(Vertex {
@@ -127,6 +332,10 @@ impl<'a,
             tex_coords: [1.0, 1.0],
             normal: normal,
             lighted: lighted,
+            shadow: shadow,
+            occlusion: occ_d,
+            fog: fog,
+            alpha: alpha,
             final_z: final_z,
         });
         vertices.push(Vertex {
@@ -134,26 +343,41 @@ impl<'a,
             tex_coords: [0.0, 1.0],
             normal: normal,
             lighted: lighted,
+            shadow: shadow,
+            occlusion: occ_c,
+            fog: fog,
+            alpha: alpha,
             final_z: final_z,
         });
     }
 
     // Add horizontal wall to the vertices
     fn add_vertical_wall(&self, vertices: &mut Vec<Vertex>, data: &WT,
-                         f: &Fn(usize, usize) -> f32,
+                         f: &Fn(usize, usize) -> TileShading,
+                         cut: Option<CutawayMode>,
+                         level: &Level<FT, WT>, layer: usize,
                          x: usize, y: usize, z: f32, other_z: f32) {
         let other_z = if data.is_cliff() {
             other_z
         } else if data.is_border() {
             z - 3.0
+        } else if let Some(CutawayMode::Stub(height)) = cut {
+            z + height.min(data.height())
         } else {
-            z + 1.0
+            z + data.height()
         };
-        let final_z = (x + y) as f32 / 2.0 - 0.1;
-        let lighted = {
+        let alpha = match cut {
+            Some(CutawayMode::Transparent(alpha)) if !data.is_cliff() && !data.is_border() => alpha,
+            _ => 1.0,
+        };
+        let final_z = (x + y) as f32 / 2.0 - 0.1 - layer as f32 * LAYER_DEPTH;
+        let TileShading { lighted, shadow, fog } = {
             let x = if x > 0 { x - 1 } else { x };
             f(x, y)
         };
+        let occ_a = level.corner_occlusion(x, y, z);
+        let occ_b = level.corner_occlusion(x, y + 1, z);
+        let (occ_c, occ_d) = (0.0, 0.0);
         let x = x as f32;
         let y = y as f32;
         let a = [x, y, z];
@@ -166,6 +390,10 @@ impl<'a,
             tex_coords: [0.0, 0.0],
             normal: normal,
             lighted: lighted,
+            shadow: shadow,
+            occlusion: occ_a,
+            fog: fog,
+            alpha: alpha,
             final_z: final_z,
         });
         vertices.push(Vertex {
@@ -173,6 +401,10 @@ impl<'a,
             tex_coords: [1.0, 0.0],
             normal: normal,
             lighted: lighted,
+            shadow: shadow,
+            occlusion: occ_b,
+            fog: fog,
+            alpha: alpha,
             final_z: final_z,
         });
         vertices.push(Vertex {
@@ -180,6 +412,10 @@ impl<'a,
             tex_coords: [0.0, 1.0],
             normal: normal,
             lighted: lighted,
+            shadow: shadow,
+            occlusion: occ_c,
+            fog: fog,
+            alpha: alpha,
             final_z: final_z,
         });
         vertices.push(Vertex {
@@ -187,6 +423,10 @@ impl<'a,
             tex_coords: [1.0, 0.0],
             normal: normal,
             lighted: lighted,
+            shadow: shadow,
+            occlusion: occ_b,
+            fog: fog,
+            alpha: alpha,
             final_z: final_z,
         });
         vertices.push(Vertex {
@@ -194,6 +434,10 @@ impl<'a,
             tex_coords: [1.0, 1.0],
             normal: normal,
             lighted: lighted,
+            shadow: shadow,
+            occlusion: occ_d,
+            fog: fog,
+            alpha: alpha,
             final_z: final_z,
         });
         vertices.push(Vertex {
@@ -201,46 +445,57 @@ impl<'a,
             tex_coords: [0.0, 1.0],
             normal: normal,
             lighted: lighted,
+            shadow: shadow,
+            occlusion: occ_c,
+            fog: fog,
+            alpha: alpha,
             final_z: final_z,
         });
     }
     
-    /// Return the vertices corresponding to the walls' data
-    fn get_vertices_walls(&self, f: &Fn(usize, usize) -> f32) -> Vec<Vertex> {
+    /// Return the vertices corresponding to the walls' data of a layer
+    fn get_vertices_walls(&self, layer: usize, f: &Fn(usize, usize) -> TileShading) -> Vec<Vertex> {
         let mut vertices = vec!();
-        let level = self.level();
+        let level = self.layers.layer(layer);
         let width = level.width();
         let depth = level.depth();
 
         for x in 0..width {
             for y in 0..depth {
+                if !self.layers.has_floor(x, y, layer) {
+                    continue;
+                }
                 let z = level.z(x, y);
                 if let &Some(ref data) = level.wall(x, y, WallPosition::Bottom) {
+                    let cut = self.cutaway_mode(x, y, WallPosition::Bottom);
                     if y == 0 {
-                        self.add_horizontal_wall(&mut vertices, data, f, x, y, z, z + 1.0);
+                        self.add_horizontal_wall(&mut vertices, data, f, cut, level, layer, x, y, z, z + 1.0);
                     } else {
-                        self.add_horizontal_wall(&mut vertices, data, f, x, y, z, level.z(x, y - 1));
+                        self.add_horizontal_wall(&mut vertices, data, f, cut, level, layer, x, y, z, level.z(x, y - 1));
                     }
                 }
                 if let &Some(ref data) = level.wall(x, y, WallPosition::Left) {
+                    let cut = self.cutaway_mode(x, y, WallPosition::Left);
                     if x == 0 {
-                        self.add_vertical_wall(&mut vertices, data, f, x, y, z, z + 1.0);
+                        self.add_vertical_wall(&mut vertices, data, f, cut, level, layer, x, y, z, z + 1.0);
                     } else {
-                        self.add_vertical_wall(&mut vertices, data, f, x, y, z, level.z(x - 1, y));
+                        self.add_vertical_wall(&mut vertices, data, f, cut, level, layer, x, y, z, level.z(x - 1, y));
                     }
                 }
                 if let &Some(ref data) = level.wall(x, y, WallPosition::Top) {
+                    let cut = self.cutaway_mode(x, y, WallPosition::Top);
                     if y == depth - 1 {
-                        self.add_horizontal_wall(&mut vertices, data, f, x, y + 1, z, z + 1.0);
+                        self.add_horizontal_wall(&mut vertices, data, f, cut, level, layer, x, y + 1, z, z + 1.0);
                     } else {
-                        self.add_horizontal_wall(&mut vertices, data, f, x, y + 1, z, level.z(x, y + 1));
+                        self.add_horizontal_wall(&mut vertices, data, f, cut, level, layer, x, y + 1, z, level.z(x, y + 1));
                     }
                 }
                 if let &Some(ref data) = level.wall(x, y, WallPosition::Right)  {
+                    let cut = self.cutaway_mode(x, y, WallPosition::Right);
                     if x == width - 1 {
-                        self.add_vertical_wall(&mut vertices, data, f, x + 1, y, z, z + 1.0);
+                        self.add_vertical_wall(&mut vertices, data, f, cut, level, layer, x + 1, y, z, z + 1.0);
                     } else {
-                        self.add_vertical_wall(&mut vertices, data, f, x + 1, y, z, level.z(x + 1, y));
+                        self.add_vertical_wall(&mut vertices, data, f, cut, level, layer, x + 1, y, z, level.z(x + 1, y));
                     }
                 }
             }
@@ -248,86 +503,34 @@ impl<'a,
         vertices
     }
     
-    /// Returns the vertices corresponding to the level's data
-    fn get_vertices(&self, f: &Fn(usize, usize) -> f32) -> Vec<Vertex> {
+    /// Returns the vertices corresponding to the level's data of a layer
+    fn get_vertices(&self, layer: usize, f: &Fn(usize, usize) -> TileShading) -> Vec<Vertex> {
         let mut vertices = vec!();
-        let level = self.level();
+        let level = self.layers.layer(layer);
         let width = level.width();
         let depth = level.depth();
         for x in 0..width {
             for y in 0..depth {
-                let z = level.z(x, y);
-                let final_z = (x + y) as f32 / 2.0;
-                let mut sum_a = z;
-                let mut div_a = 1.0;
-                let mut sum_b = z;
-                let mut div_b = 1.0;
-                let mut sum_c = z;
-                let mut div_c = 1.0;
-                let mut sum_d = z;
-                let mut div_d = 1.0;
-                
-                // Each vertex's height is averaged to all adjacent tiles that
-                // a) exist b) have no wall between this tile and them
-                if x > 0 && level.is_move_possible((x, y), (x - 1, y)) {
-                    let z = level.z(x - 1, y);
-                    sum_a += z;
-                    div_a += 1.0;
-                    sum_c += z;
-                    div_c += 1.0;
-                    if y > 0 && level.is_move_possible((x, y), (x - 1, y - 1)) {
-                        let z = level.z(x -1, y - 1);
-                        sum_a += z;
-                        div_a += 1.0;
-                    }
-                }
-                if y > 0 && level.is_move_possible((x, y), (x, y - 1)) {
-                    let z = level.z(x, y - 1);
-                    sum_a += z;
-                    div_a += 1.0;
-                    sum_b += z;
-                    div_b += 1.0;
-                    if x < width - 1 && level.is_move_possible((x, y), (x + 1, y - 1)) {
-                        let z = level.z(x + 1, y - 1);
-                        sum_b += z;
-                        div_b += 1.0;
-                    }
-                }
-                if x < width - 1 && level.is_move_possible((x, y), (x + 1, y)) {
-                    let z = level.z(x + 1, y);
-                    sum_b += z;
-                    div_b += 1.0;
-                    sum_d += z;
-                    div_d += 1.0;
-                    if y < depth - 1 && level.is_move_possible((x, y), (x + 1, y + 1)) {
-                        let z = level.z(x + 1, y + 1);
-                        sum_d += z;
-                        div_d += 1.0;
-                    }
-                }
-                if y < depth - 1 && level.is_move_possible((x, y), (x, y + 1)) {
-                    let z = level.z(x, y + 1);
-                    sum_c += z;
-                    div_c += 1.0;
-                    sum_d += z;
-                    div_d += 1.0;
-                    if x > 0 && level.is_move_possible((x, y), (x - 1, y + 1)) {
-                        let z = level.z(x - 1, y + 1);
-                        sum_c += z;
-                        div_c += 1.0;
-                    }
+                if !self.layers.has_floor(x, y, layer) {
+                    continue;
                 }
+                let final_z = (x + y) as f32 / 2.0 - layer as f32 * LAYER_DEPTH;
+                let heights = level.corner_heights(x, y);
 
                 // Finally build the four vertices
-                let a = [x as f32, y as f32, sum_a / div_a];
+                let a = [x as f32, y as f32, heights[0]];
                 let ta = [0.0 + a[0] / (width as f32 + 1.0), 0.0 + a[1] / (width as f32 + 1.0)];
-                let b = [(x + 1) as f32, y as f32, sum_b / div_b];
+                let b = [(x + 1) as f32, y as f32, heights[1]];
                 let tb = [0.0 + b[0] / (width as f32 + 1.0), 0.0 + b[1] / (width as f32 + 1.0)];
-                let c = [x as f32, (y + 1) as f32, sum_c / div_c];
+                let c = [x as f32, (y + 1) as f32, heights[2]];
                 let tc = [0.0 + c[0] / (width as f32 + 1.0), 0.0 + c[1] / (width as f32 + 1.0)];
-                let d = [(x + 1) as f32, (y + 1) as f32, sum_d / div_d];
+                let d = [(x + 1) as f32, (y + 1) as f32, heights[3]];
                 let td = [0.0 + d[0] / (width as f32 + 1.0), 0.0 + d[1] / (width as f32 + 1.0)];
-                let lighted = f(x, y);
+                let TileShading { lighted, shadow, fog } = f(x, y);
+                let occ_a = level.corner_occlusion(x, y, a[2]);
+                let occ_b = level.corner_occlusion(x + 1, y, b[2]);
+                let occ_c = level.corner_occlusion(x, y + 1, c[2]);
+                let occ_d = level.corner_occlusion(x + 1, y + 1, d[2]);
                 // I probably should explain this computation as I won't remember it
                 //
                 // so the normal is (x, y z). We want z to be 1 (positive) so (x, y, 1).
@@ -353,6 +556,10 @@ impl<'a,
                     tex_coords: ta,
                     normal: normal_1,
                     lighted: lighted,
+                    shadow: shadow,
+                    occlusion: occ_a,
+                    fog: fog,
+                    alpha: 1.0,
                     final_z: final_z,
                 });
                 vertices.push(Vertex {
@@ -360,6 +567,10 @@ impl<'a,
                     tex_coords: tb,
                     normal: normal_1,
                     lighted: lighted,
+                    shadow: shadow,
+                    occlusion: occ_b,
+                    fog: fog,
+                    alpha: 1.0,
                     final_z: final_z,
                 });
                 vertices.push(Vertex {
@@ -367,6 +578,10 @@ impl<'a,
                     tex_coords: tc,
                     normal: normal_1,
                     lighted: lighted,
+                    shadow: shadow,
+                    occlusion: occ_c,
+                    fog: fog,
+                    alpha: 1.0,
                     final_z: final_z,
                 });
                 vertices.push(Vertex {
@@ -374,6 +589,10 @@ impl<'a,
                     tex_coords: tb,
                     normal: normal_2,
                     lighted: lighted,
+                    shadow: shadow,
+                    occlusion: occ_b,
+                    fog: fog,
+                    alpha: 1.0,
                     final_z: final_z,
                 });
                 vertices.push(Vertex {
@@ -381,6 +600,10 @@ impl<'a,
                     tex_coords: td,
                     normal: normal_2,
                     lighted: lighted,
+                    shadow: shadow,
+                    occlusion: occ_d,
+                    fog: fog,
+                    alpha: 1.0,
                     final_z: final_z,
                 });
                 vertices.push(Vertex {
@@ -388,21 +611,84 @@ impl<'a,
                     tex_coords: tc,
                     normal: normal_2,
                     lighted: lighted,
+                    shadow: shadow,
+                    occlusion: occ_c,
+                    fog: fog,
+                    alpha: 1.0,
                     final_z: final_z,
                 });
-                
+
+                // Water must be drawn over its bed, and decks over the walls of their tile
+                if let Some(z) = level.water_z(x, y) {
+                    self.add_flat(&mut vertices, f(x, y), x, y, z, width, final_z - 0.05, WATER_ALPHA);
+                }
+                if let Some(bridge) = level.bridge(x, y) {
+                    self.add_flat(&mut vertices, f(x, y), x, y, bridge.z, width, final_z - 0.15, 1.0);
+                }
             }
         }
         vertices
     }
 
+    /// Add the vertices of a flat surface above the floor of tile (x, y), such
+    /// as a bridge's deck or water
+    fn add_flat(&self, vertices: &mut Vec<Vertex>, shading: TileShading,
+                x: usize, y: usize, z: f32, width: usize, final_z: f32, alpha: f32) {
+        let TileShading { lighted, shadow, fog } = shading;
+        let normal = [0.0, 0.0, 1.0];
+        let x = x as f32;
+        let y = y as f32;
+        let corners = [[x, y, z], [x + 1.0, y, z], [x, y + 1.0, z],
+                       [x + 1.0, y, z], [x + 1.0, y + 1.0, z], [x, y + 1.0, z]];
+        for position in &corners {
+            vertices.push(Vertex {
+                position: *position,
+                tex_coords: [position[0] / (width as f32 + 1.0), position[1] / (width as f32 + 1.0)],
+                normal: normal,
+                lighted: lighted,
+                shadow: shadow,
+                occlusion: 0.0,
+                fog: fog,
+                alpha: alpha,
+                final_z: final_z,
+            });
+        }
+    }
+
     /// Render the level content to a Glium display
     pub fn render(&self, display: &Display, camera: &Camera) {
         let pos = camera.pos();
-        let visible = self.level.visibility((pos[0] as usize, pos[1] as usize), 5);
+        let visible = self.level().visibility((pos[0] as usize, pos[1] as usize), 5);
+        let shadow_map = if self.shadows {
+            Some(ShadowMap::new(self.level(), self.lighting.sun_direction()))
+        } else {
+            None
+        };
 //        let f = |x, y| 1.0;
         let f = |x, y| {
-            if visible(x, y) {
+            let shadow = match shadow_map {
+                Some(ref map) if map.is_shadowed(x, y) => 1.0,
+                _ => 0.0,
+            };
+            if let Some(ref fog) = self.fog {
+                // The level may have been resized since the fog was set
+                let visibility = if x < fog.width() && y < fog.depth() {
+                    fog.visibility(x, y)
+                } else {
+                    TileVisibility::Unseen
+                };
+                let (lighted, fog) = match visibility {
+                    TileVisibility::Visible => (1.0, 1.0),
+                    TileVisibility::Explored => (1.0, 0.5),
+                    TileVisibility::Unseen => (0.0, 0.0),
+                };
+                return TileShading {
+                    lighted: lighted,
+                    shadow: shadow,
+                    fog: fog,
+                };
+            }
+            let lighted = if visible(x, y) {
                 let x = x as f32;
                 let y = y as f32;
                 let mut dist:f32 = (((x-pos[0])*(x-pos[0]) + (y - pos[1]) * (y - pos[1]))).sqrt();
@@ -410,16 +696,30 @@ impl<'a,
                 1.0/(1.0 + dist) 
             } else {
                 0.0
+            };
+            TileShading {
+                lighted: lighted,
+                shadow: shadow,
+                fog: 1.0,
             }
         };
         
         let indices = glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList);
 
         
-        let vertices = self.get_vertices(&f);
+        let top_layer = if self.hide_upper_layers {
+            self.current_layer
+        } else {
+            self.layers.layer_count() - 1
+        };
+        let mut vertices = vec!();
+        let mut vertices_w = vec!();
+        for layer in 0..(top_layer + 1) {
+            vertices.extend(self.get_vertices(layer, &f));
+            vertices_w.extend(self.get_vertices_walls(layer, &f));
+        }
         let vertex_buffer = glium::VertexBuffer::new(display, &vertices).unwrap();
 
-        let vertices_w = self.get_vertices_walls(&f);
         let vertex_buffer_w =  glium::VertexBuffer::new(display, &vertices_w).unwrap();
 
 
@@ -466,21 +766,25 @@ impl<'a,
             .. Default::default()
         };
         let mut frame = display.draw();
-        let dark_color = [0.5, 0.5, 0.5f32];
-        frame.clear_color_and_depth((0.1, 0.0, 0.0, 1.0), 1.0);
+        let v_light = self.lighting.sun_direction();
+        let light_color = self.lighting.sun_color();
+        let dark_color = self.lighting.ambient_color();
+        let clear_color = self.lighting.clear_color();
+        frame.clear_color_and_depth((clear_color[0], clear_color[1], clear_color[2], clear_color[3]), 1.0);
         frame.draw(&vertex_buffer, &indices, &self.program,
                    &uniform! {
                        perspective: camera.perspective(),
                        view: camera.view(),
                        tex: &floor_texture,
-                       v_light: [1.0, 0.0, 0.0f32],
-                       light_color: [1.0, 1.0, 1.0f32],
+                       v_light: v_light,
+                       light_color: light_color,
                        dark_color: dark_color,
+                       occlusion_strength: self.occlusion_strength,
                    },
                    &params).unwrap();
 
         let pos = [pos[0] + 3.0, pos[1] + 1.0, pos[2]];
-        let z_bottom = self.level.z(pos[0] as usize, pos[1] as usize);
+        let z_bottom = self.level().z(pos[0] as usize, pos[1] as usize);
         let final_z = (pos[0] + pos[1]) / 2.0 - 0.05;
         println!("({}, {})", pos[0], pos[1]);
         let cam = camera.pos();
@@ -506,6 +810,10 @@ impl<'a,
                 normal: normal,
                 tex_coords: [0.0, 0.0],
                 lighted: 1.0,
+                shadow: 0.0,
+                occlusion: 0.0,
+                fog: 1.0,
+                alpha: 1.0,
                 final_z: final_z,
             },
             Vertex{ //b
@@ -513,6 +821,10 @@ impl<'a,
                 normal: normal,
                 tex_coords: [1.0, 0.0],
                 lighted: 1.0,
+                shadow: 0.0,
+                occlusion: 0.0,
+                fog: 1.0,
+                alpha: 1.0,
                 final_z: final_z,
             },
             Vertex{ //c
@@ -520,6 +832,10 @@ impl<'a,
                 normal: normal,
                 tex_coords: [0.0, 1.0],
                 lighted: 1.0,
+                shadow: 0.0,
+                occlusion: 0.0,
+                fog: 1.0,
+                alpha: 1.0,
                 final_z: final_z,
             },
             Vertex{ //b
@@ -527,6 +843,10 @@ impl<'a,
                 normal: normal,
                 tex_coords: [1.0, 0.0],
                 lighted: 1.0,
+                shadow: 0.0,
+                occlusion: 0.0,
+                fog: 1.0,
+                alpha: 1.0,
                 final_z: final_z,
             },
             Vertex{ //d
@@ -534,6 +854,10 @@ impl<'a,
                 normal: normal,
                 tex_coords: [1.0, 1.0],
                 lighted: 1.0,
+                shadow: 0.0,
+                occlusion: 0.0,
+                fog: 1.0,
+                alpha: 1.0,
                 final_z: final_z,
             },
             Vertex{ //c
@@ -541,6 +865,10 @@ impl<'a,
                 normal: normal,
                 tex_coords: [0.0, 1.0],
                 lighted: 1.0,
+                shadow: 0.0,
+                occlusion: 0.0,
+                fog: 1.0,
+                alpha: 1.0,
                 final_z: final_z,
             },
         ];
@@ -556,9 +884,10 @@ impl<'a,
             perspective: id,
             view: id,
             tex: &sprite_texture,
-            v_light: [1.0, 0.0, 0.0f32],
-            light_color: [1.0, 1.0, 1.0f32],
+            v_light: v_light,
+            light_color: light_color,
             dark_color: dark_color,
+            occlusion_strength: self.occlusion_strength,
         },&params).unwrap();
 
 //        frame.clear_depth(10000.0);
@@ -567,9 +896,10 @@ impl<'a,
                        perspective: camera.perspective(),
                        view: camera.view(),
                        tex: &wall_texture,
-                       v_light: [1.0, 0.0, 0.0f32],
-                       light_color: [1.0, 1.0, 1.0f32],
+                       v_light: v_light,
+                       light_color: light_color,
                        dark_color: dark_color,
+                       occlusion_strength: self.occlusion_strength,
                    },
                    &params).unwrap();
         frame.finish();
@@ -0,0 +1,468 @@
+// (C) 2017, Élisabeth Henry
+//
+// Licensed under either of
+// 
+// Apache License, Version 2.0: http://www.apache.org/licenses/LICENSE-2.0
+// MIT license: http://opensource.org/licenses/MIT
+// at your option.
+//
+// Unless you explicitly state otherwise, any contribution intentionally submitted
+// for inclusion in the work by you, as defined in the Apache-2.0 license, shall be
+// dual licensed as above, without any additional terms or conditions.
+
+use level::Level;
+use wall::Wall;
+use wall::WallPosition;
+use rect::Rect;
+use rng::Rng;
+
+use std::cmp;
+use std::default::Default;
+
+/// A room of a generated dungeon
+#[derive(Debug, PartialEq, Clone)]
+pub struct Room {
+    /// Tiles of the room
+    pub rect: Rect,
+    /// Center of the room
+    pub center: (usize, usize),
+    /// Indices of the rooms this one is connected to by a corridor
+    pub connections: Vec<usize>,
+}
+
+/// An opening between a room and a corridor.
+///
+/// Doorways are given as the wall position that is left open, always `Right`
+/// or `Top` of the tile (x, y).
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Doorway {
+    /// x coordinate of the tile
+    pub x: usize,
+    /// y coordinate of the tile
+    pub y: usize,
+    /// Side of the tile where the doorway is
+    pub position: WallPosition,
+}
+
+/// Kind of a tile of a dungeon while it is generated
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Cell {
+    Rock,
+    Room(usize),
+    Corridor,
+}
+
+/// A generated dungeon: the level and information about its rooms.
+#[derive(Debug)]
+pub struct Dungeon<FT=(), WT=()> {
+    level: Level<FT, WT>,
+    rooms: Vec<Room>,
+    doorways: Vec<Doorway>,
+    floor: Vec<bool>,
+}
+
+impl<FT: Default + Clone,
+     WT: Wall> Dungeon<FT, WT> {
+    /// Get access to the level
+    pub fn level(&self) -> &Level<FT, WT> {
+        &self.level
+    }
+
+    /// Returns the level, dropping information about the rooms
+    pub fn into_level(self) -> Level<FT, WT> {
+        self.level
+    }
+
+    /// Returns the rooms of the dungeon
+    pub fn rooms(&self) -> &[Room] {
+        &self.rooms
+    }
+
+    /// Returns the doorways between rooms and corridors
+    pub fn doorways(&self) -> &[Doorway] {
+        &self.doorways
+    }
+
+    /// Returns true if tile (x, y) is part of a room or of a corridor, false
+    /// if it is solid rock
+    pub fn is_floor(&self, x: usize, y: usize) -> bool {
+        debug_assert!(x < self.level.width() && y < self.level.depth(), "x and y must be in level's bounds");
+        self.floor[y * self.level.width() + x]
+    }
+}
+
+/// Generates dungeons made of rooms and corridors, using binary space
+/// partitioning.
+///
+/// The level is recursively split in two until parts are small enough, a room
+/// is placed in each part, and rooms of sibling parts are linked by corridors.
+/// The output only depends on the settings and on the seed.
+///
+/// Walls surround rooms and corridors, except at doorways; tiles that are
+/// neither are "rock", without walls between them.
+///
+/// # Example
+///
+/// ```
+/// use isometric::BspGenerator;
+///
+/// let mut generator = BspGenerator::new(40, 30, 42);
+/// generator.set_room_size(4, 8)
+///     .set_corridor_width(2);
+/// let dungeon = generator.generate::<(), ()>(());
+/// assert!(dungeon.rooms().len() > 1);
+///
+/// // Same seed, same dungeon
+/// let other = generator.generate::<(), ()>(());
+/// assert_eq!(dungeon.rooms(), other.rooms());
+///
+/// // Every room can be reached from the first one
+/// let start = dungeon.rooms()[0].center;
+/// for room in dungeon.rooms() {
+///     assert!(dungeon.level().find_path(start, room.center).is_some());
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct BspGenerator {
+    width: usize,
+    depth: usize,
+    seed: u64,
+    min_room_size: usize,
+    max_room_size: usize,
+    corridor_width: usize,
+}
+
+impl BspGenerator {
+    /// Creates a new generator for levels of the given dimensions.
+    ///
+    /// By default, rooms are between 4 and 10 tiles wide and corridors are
+    /// 1 tile wide.
+    pub fn new(width: usize, depth: usize, seed: u64) -> BspGenerator {
+        BspGenerator {
+            width,
+            depth,
+            seed,
+            min_room_size: 4,
+            max_room_size: 10,
+            corridor_width: 1,
+        }
+    }
+
+    /// Returns the seed
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Sets the seed
+    pub fn set_seed(&mut self, seed: u64) -> &mut Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Returns the minimum and maximum width (and depth) of rooms
+    pub fn room_size(&self) -> (usize, usize) {
+        (self.min_room_size, self.max_room_size)
+    }
+
+    /// Sets the minimum and maximum width (and depth) of rooms
+    pub fn set_room_size(&mut self, min: usize, max: usize) -> &mut Self {
+        debug_assert!(min > 0 && min <= max, "room sizes must be 0 < min <= max");
+        self.min_room_size = min;
+        self.max_room_size = max;
+        self
+    }
+
+    /// Returns the width of corridors
+    pub fn corridor_width(&self) -> usize {
+        self.corridor_width
+    }
+
+    /// Sets the width of corridors
+    pub fn set_corridor_width(&mut self, width: usize) -> &mut Self {
+        debug_assert!(width > 0, "corridors must be at least 1 tile wide");
+        self.corridor_width = width;
+        self
+    }
+
+    /// Generates a dungeon, using `wall` for all the walls
+    pub fn generate<FT: Default + Clone, WT: Wall>(&self, wall: WT) -> Dungeon<FT, WT> {
+        let mut rng = Rng::new(self.seed);
+        let mut cells = vec![Cell::Rock; self.width * self.depth];
+        let mut rooms = vec![];
+        let mut corridors = vec![];
+        self.split(Rect::new(0, 0, self.width, self.depth), &mut rng, &mut rooms, &mut corridors);
+
+        for (i, room) in rooms.iter().enumerate() {
+            for x in room.rect.x..(room.rect.x + room.rect.width) {
+                for y in room.rect.y..(room.rect.y + room.rect.depth) {
+                    cells[y * self.width + x] = Cell::Room(i);
+                }
+            }
+        }
+
+        // Carve corridors, then find where they go in and out of rooms
+        let mut paths = vec![];
+        for &(a, b) in &corridors {
+            let path = self.corridor_path(rooms[a].center, rooms[b].center, &mut rng);
+            for &(x, y) in &path {
+                for i in 0..self.corridor_width {
+                    for j in 0..self.corridor_width {
+                        if x + i < self.width && y + j < self.depth
+                            && cells[(y + j) * self.width + x + i] == Cell::Rock {
+                            cells[(y + j) * self.width + x + i] = Cell::Corridor;
+                        }
+                    }
+                }
+            }
+            paths.push(path);
+            rooms[a].connections.push(b);
+            rooms[b].connections.push(a);
+        }
+        let mut doors_right = vec![false; self.width * self.depth];
+        let mut doors_top = vec![false; self.width * self.depth];
+        for path in &paths {
+            for step in path.windows(2) {
+                let (from, to) = (step[0], step[1]);
+                for k in 0..self.corridor_width {
+                    let (a, b) = if from.1 == to.1 {
+                        ((from.0, from.1 + k), (to.0, to.1 + k))
+                    } else {
+                        ((from.0 + k, from.1), (to.0 + k, to.1))
+                    };
+                    if a.0 >= self.width || a.1 >= self.depth || b.0 >= self.width || b.1 >= self.depth {
+                        continue;
+                    }
+                    let is_door = match (cells[a.1 * self.width + a.0], cells[b.1 * self.width + b.0]) {
+                        (Cell::Room(_), Cell::Corridor) | (Cell::Corridor, Cell::Room(_)) => true,
+                        _ => false,
+                    };
+                    if is_door {
+                        // Edge is given by its lower tile
+                        let (low, high) = if a < b { (a, b) } else { (b, a) };
+                        if low.0 != high.0 {
+                            doors_right[low.1 * self.width + low.0] = true;
+                        } else {
+                            doors_top[low.1 * self.width + low.0] = true;
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut level = Level::new(self.width, self.depth, 0.0);
+        let mut doorways = vec![];
+        let needs_wall = |a: Cell, b: Cell, door: bool| {
+            match (a, b) {
+                (Cell::Rock, Cell::Rock) | (Cell::Corridor, Cell::Corridor) => false,
+                (Cell::Room(i), Cell::Room(j)) => i != j,
+                (Cell::Room(_), Cell::Corridor) | (Cell::Corridor, Cell::Room(_)) => !door,
+                _ => true,
+            }
+        };
+        for y in 0..self.depth {
+            for x in 0..self.width {
+                let i = y * self.width + x;
+                let cell = cells[i];
+                if x == 0 && cell != Cell::Rock {
+                    level.set_wall(x, y, WallPosition::Left, Some(wall.clone()));
+                }
+                if y == 0 && cell != Cell::Rock {
+                    level.set_wall(x, y, WallPosition::Bottom, Some(wall.clone()));
+                }
+                let right = if x + 1 < self.width {
+                    needs_wall(cell, cells[i + 1], doors_right[i])
+                } else {
+                    cell != Cell::Rock
+                };
+                if right {
+                    level.set_wall(x, y, WallPosition::Right, Some(wall.clone()));
+                }
+                let top = if y + 1 < self.depth {
+                    needs_wall(cell, cells[i + self.width], doors_top[i])
+                } else {
+                    cell != Cell::Rock
+                };
+                if top {
+                    level.set_wall(x, y, WallPosition::Top, Some(wall.clone()));
+                }
+                if doors_right[i] {
+                    doorways.push(Doorway { x, y, position: WallPosition::Right });
+                }
+                if doors_top[i] {
+                    doorways.push(Doorway { x, y, position: WallPosition::Top });
+                }
+            }
+        }
+
+        Dungeon {
+            level,
+            rooms,
+            doorways,
+            floor: cells.iter().map(|&cell| cell != Cell::Rock).collect(),
+        }
+    }
+
+    /// Recursively splits `rect`, adding the rooms of its leaves and the
+    /// corridors between them. Returns the indices of the rooms inside `rect`.
+    fn split(&self, rect: Rect, rng: &mut Rng,
+             rooms: &mut Vec<Room>, corridors: &mut Vec<(usize, usize)>) -> Vec<usize> {
+        // Rooms have a margin of one tile in their part
+        let min_part = self.min_room_size + 2;
+        let max_part = self.max_room_size + 2;
+        let can_split_x = rect.width >= 2 * min_part;
+        let can_split_y = rect.depth >= 2 * min_part;
+        let is_small = rect.width <= max_part && rect.depth <= max_part;
+
+        if is_small || !(can_split_x || can_split_y) {
+            if rect.width < min_part || rect.depth < min_part {
+                return vec![];
+            }
+            let width = rng.range(self.min_room_size, cmp::min(rect.width - 2, self.max_room_size) + 1);
+            let depth = rng.range(self.min_room_size, cmp::min(rect.depth - 2, self.max_room_size) + 1);
+            let x = rng.range(rect.x + 1, rect.x + rect.width - width);
+            let y = rng.range(rect.y + 1, rect.y + rect.depth - depth);
+            rooms.push(Room {
+                rect: Rect::new(x, y, width, depth),
+                center: (x + width / 2, y + depth / 2),
+                connections: vec![],
+            });
+            return vec![rooms.len() - 1];
+        }
+
+        let split_x = if can_split_x && can_split_y {
+            if rect.width != rect.depth {
+                rect.width > rect.depth
+            } else {
+                rng.chance(0.5)
+            }
+        } else {
+            can_split_x
+        };
+        let (a, b) = if split_x {
+            let cut = rng.range(min_part, rect.width - min_part + 1);
+            (Rect::new(rect.x, rect.y, cut, rect.depth),
+             Rect::new(rect.x + cut, rect.y, rect.width - cut, rect.depth))
+        } else {
+            let cut = rng.range(min_part, rect.depth - min_part + 1);
+            (Rect::new(rect.x, rect.y, rect.width, cut),
+             Rect::new(rect.x, rect.y + cut, rect.width, rect.depth - cut))
+        };
+        let mut left = self.split(a, rng, rooms, corridors);
+        let right = self.split(b, rng, rooms, corridors);
+
+        // Link the two closest rooms of both halves
+        let mut best = None;
+        for &i in &left {
+            for &j in &right {
+                let (ci, cj) = (rooms[i].center, rooms[j].center);
+                let dist = (ci.0 as isize - cj.0 as isize).abs() + (ci.1 as isize - cj.1 as isize).abs();
+                if best.map_or(true, |(_, _, d)| dist < d) {
+                    best = Some((i, j, dist));
+                }
+            }
+        }
+        if let Some((i, j, _)) = best {
+            corridors.push((i, j));
+        }
+        left.extend(right);
+        left
+    }
+
+    /// Returns the tiles of an L-shaped path between two points
+    fn corridor_path(&self, from: (usize, usize), to: (usize, usize), rng: &mut Rng) -> Vec<(usize, usize)> {
+        let mut path = vec![from];
+        let mut pos = from;
+        let horizontal_first = rng.chance(0.5);
+        for &horizontal in &[horizontal_first, !horizontal_first] {
+            if horizontal {
+                while pos.0 != to.0 {
+                    pos.0 = if pos.0 < to.0 { pos.0 + 1 } else { pos.0 - 1 };
+                    path.push(pos);
+                }
+            } else {
+                while pos.1 != to.1 {
+                    pos.1 = if pos.1 < to.1 { pos.1 + 1 } else { pos.1 - 1 };
+                    path.push(pos);
+                }
+            }
+        }
+        path
+    }
+}
+
+
+#[test]
+fn dungeon_connected() {
+    for seed in 0..20 {
+        let mut generator = BspGenerator::new(50, 40, seed);
+        generator.set_corridor_width(1 + seed as usize % 3);
+        let dungeon = generator.generate::<(), ()>(());
+        let start = dungeon.rooms()[0].center;
+        for room in dungeon.rooms() {
+            assert!(room.rect.width >= 4 && room.rect.width <= 10);
+            assert!(dungeon.level().find_path(start, room.center).is_some());
+        }
+        assert!(!dungeon.doorways().is_empty());
+    }
+}
+
+#[test]
+fn dungeon_snapshot() {
+    let mut generator = BspGenerator::new(20, 12, 7);
+    generator.set_room_size(3, 6);
+    let dungeon = generator.generate::<(), ()>(());
+    let mut floor = String::new();
+    for y in (0..12).rev() {
+        for x in 0..20 {
+            floor.push(if dungeon.is_floor(x, y) { '.' } else { '#' });
+        }
+        floor.push('\n');
+    }
+    // The whole layout, top row first
+    assert_eq!(floor, concat!("####################\n",
+                              "####################\n",
+                              "#...###.....########\n",
+                              "#...###.....####...#\n",
+                              "#...###............#\n",
+                              "###.#####.######...#\n",
+                              "###.#####.#######.##\n",
+                              "###.###...#######.##\n",
+                              "#....##...######...#\n",
+                              "#.........######...#\n",
+                              "#....###########...#\n",
+                              "####################\n"));
+    let rooms: Vec<_> = dungeon.rooms()
+        .iter()
+        .map(|room| (room.rect, room.center, room.connections.clone()))
+        .collect();
+    assert_eq!(rooms, vec![(Rect::new(1, 1, 4, 3), (3, 2), vec![1, 2]),
+                           (Rect::new(1, 7, 3, 3), (2, 8), vec![0]),
+                           (Rect::new(7, 2, 3, 3), (8, 3), vec![3, 0]),
+                           (Rect::new(7, 7, 5, 3), (9, 8), vec![2, 5]),
+                           (Rect::new(16, 1, 3, 3), (17, 2), vec![5]),
+                           (Rect::new(16, 6, 3, 3), (17, 7), vec![4, 3])]);
+    let doorways: Vec<_> = dungeon.doorways()
+        .iter()
+        .map(|d| (d.x, d.y, d.position))
+        .collect();
+    assert_eq!(doorways, vec![(4, 2, WallPosition::Right), (6, 2, WallPosition::Right),
+                              (3, 3, WallPosition::Top), (17, 3, WallPosition::Top),
+                              (9, 4, WallPosition::Top), (17, 5, WallPosition::Top),
+                              (3, 6, WallPosition::Top), (9, 6, WallPosition::Top),
+                              (11, 7, WallPosition::Right), (15, 7, WallPosition::Right)]);
+
+    // And what can be seen from the first room
+    let ascii = dungeon.level().to_ascii(dungeon.rooms()[0].center, 20);
+    assert_eq!(ascii, concat!("############################################################\n",
+                              "###|-  -  -  -|#############################################\n",
+                              "###|      @     =  =  -  -  -|##############################\n",
+                              "###|_  _     _|######|       |##############################\n",
+                              "#########| |################################################\n",
+                              "#########| |################################################\n",
+                              "#########| |################################################\n",
+                              "#########  |################################################\n",
+                              "######     |################################################\n",
+                              "###### _  _|################################################\n",
+                              "############################################################\n",
+                              "############################################################\n"));
+}
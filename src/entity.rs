@@ -0,0 +1,355 @@
+// (C) 2017, Élisabeth Henry
+//
+// Licensed under either of
+// 
+// Apache License, Version 2.0: http://www.apache.org/licenses/LICENSE-2.0
+// MIT license: http://opensource.org/licenses/MIT
+// at your option.
+//
+// Unless you explicitly state otherwise, any contribution intentionally submitted
+// for inclusion in the work by you, as defined in the Apache-2.0 license, shall be
+// dual licensed as above, without any additional terms or conditions.
+
+use level::Level;
+use wall::Wall;
+use rect::Rect;
+use astar;
+
+use std::cmp;
+use std::collections::HashMap;
+use std::default::Default;
+use std::error;
+use std::fmt;
+
+/// Identifies an entity. Ids are never reused, even after an entity is removed.
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Copy)]
+#[cfg_attr(feature = "serialization", derive(Serialize, Deserialize))]
+pub struct EntityId(usize);
+
+/// Error when placing or moving an entity
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum EntityError {
+    /// There is no entity with this id
+    NoSuchEntity,
+    /// The position is outside the level
+    OutOfBounds,
+    /// The tile is already occupied by this blocking entity
+    Occupied(EntityId),
+    /// The move isn't possible (see `Level::is_move_possible`)
+    Blocked,
+}
+
+impl fmt::Display for EntityError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EntityError::NoSuchEntity => write!(f, "no such entity"),
+            EntityError::OutOfBounds => write!(f, "position is outside the level"),
+            EntityError::Occupied(EntityId(id)) => write!(f, "tile is occupied by entity {}", id),
+            EntityError::Blocked => write!(f, "move is not possible"),
+        }
+    }
+}
+
+impl error::Error for EntityError {
+    fn description(&self) -> &str {
+        match *self {
+            EntityError::NoSuchEntity => "no such entity",
+            EntityError::OutOfBounds => "position out of bounds",
+            EntityError::Occupied(_) => "tile is occupied",
+            EntityError::Blocked => "move is not possible",
+        }
+    }
+}
+
+/// How pathfinding treats tiles occupied by blocking entities
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Occupancy {
+    /// Entities are ignored
+    Ignore,
+    /// Occupied tiles can't be crossed
+    Blocked,
+    /// Entering an occupied tile costs this much more than usual
+    Cost(f32),
+}
+
+#[derive(Debug, Clone)]
+struct Entity<T> {
+    pos: (usize, usize),
+    blocking: bool,
+    data: T,
+}
+
+/// Objects that stand on the tiles of a level, with a payload of type `T`.
+///
+/// Several entities can be on the same tile, but at most one of them can be
+/// blocking (e.g. characters are blocking, while items on the floor are not).
+///
+/// # Example
+///
+/// ```
+/// use isometric::{Level, Entities, EntityError, Occupancy};
+///
+/// let level: Level = Level::new(10, 10, 0.0);
+/// let mut entities = Entities::new(10, 10);
+/// let hero = entities.insert((1, 1), true, "hero").unwrap();
+/// let orc = entities.insert((3, 1), true, "orc").unwrap();
+/// entities.insert((2, 1), false, "sword").unwrap();
+///
+/// assert_eq!(entities.move_to(&level, hero, (2, 1)), Ok(()));
+/// assert_eq!(entities.move_to(&level, hero, (3, 1)), Err(EntityError::Occupied(orc)));
+/// assert_eq!(entities.at(2, 1).len(), 2);
+///
+/// // Walk around the orc
+/// let path = entities.find_path(&level, (2, 1), (4, 1), Occupancy::Blocked).unwrap();
+/// assert!(!path.contains(&(3, 1)));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Entities<T> {
+    width: usize,
+    depth: usize,
+    next_id: usize,
+    entities: HashMap<EntityId, Entity<T>>,
+    // Entities on each tile, in insertion order
+    tiles: Vec<Vec<EntityId>>,
+}
+
+impl<T> Entities<T> {
+    /// Creates an empty entity layer for a level of the given dimensions
+    pub fn new(width: usize, depth: usize) -> Entities<T> {
+        Entities {
+            width,
+            depth,
+            next_id: 0,
+            entities: HashMap::new(),
+            tiles: vec![vec![]; width * depth],
+        }
+    }
+
+    /// Returns the number of entities
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    /// Returns true if there are no entities
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    /// Returns the ids of all the entities, sorted
+    pub fn ids(&self) -> Vec<EntityId> {
+        let mut ids: Vec<_> = self.entities.keys().cloned().collect();
+        ids.sort();
+        ids
+    }
+
+    /// Checks that a tile is in bounds and that an entity can go there
+    fn check_tile(&self, pos: (usize, usize), blocking: bool, id: Option<EntityId>) -> Result<(), EntityError> {
+        if pos.0 >= self.width || pos.1 >= self.depth {
+            return Err(EntityError::OutOfBounds);
+        }
+        if blocking {
+            if let Some(other) = self.blocker(pos.0, pos.1) {
+                if Some(other) != id {
+                    return Err(EntityError::Occupied(other));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds an entity at a position, and returns its id.
+    ///
+    /// Fails if the position is out of bounds, or if the entity is blocking and
+    /// there is already a blocking entity there.
+    pub fn insert(&mut self, pos: (usize, usize), blocking: bool, data: T) -> Result<EntityId, EntityError> {
+        self.check_tile(pos, blocking, None)?;
+        let id = EntityId(self.next_id);
+        self.next_id += 1;
+        self.entities.insert(id, Entity {
+            pos,
+            blocking,
+            data,
+        });
+        self.tiles[pos.1 * self.width + pos.0].push(id);
+        Ok(id)
+    }
+
+    /// Removes an entity, returning its payload
+    pub fn remove(&mut self, id: EntityId) -> Option<T> {
+        self.entities.remove(&id).map(|entity| {
+            let (x, y) = entity.pos;
+            self.tiles[y * self.width + x].retain(|&other| other != id);
+            entity.data
+        })
+    }
+
+    /// Returns true if the entity exists
+    pub fn contains(&self, id: EntityId) -> bool {
+        self.entities.contains_key(&id)
+    }
+
+    /// Returns the payload of an entity
+    pub fn get(&self, id: EntityId) -> Option<&T> {
+        self.entities.get(&id).map(|entity| &entity.data)
+    }
+
+    /// Returns the payload of an entity
+    pub fn get_mut(&mut self, id: EntityId) -> Option<&mut T> {
+        self.entities.get_mut(&id).map(|entity| &mut entity.data)
+    }
+
+    /// Returns the position of an entity
+    pub fn position(&self, id: EntityId) -> Option<(usize, usize)> {
+        self.entities.get(&id).map(|entity| entity.pos)
+    }
+
+    /// Returns true if the entity exists and is blocking
+    pub fn is_blocking(&self, id: EntityId) -> bool {
+        self.entities.get(&id).map_or(false, |entity| entity.blocking)
+    }
+
+    /// Returns the entities on a tile, in the order they arrived there
+    pub fn at(&self, x: usize, y: usize) -> &[EntityId] {
+        debug_assert!(x < self.width && y < self.depth, "x and y must be in level's bounds");
+        &self.tiles[y * self.width + x]
+    }
+
+    /// Returns the blocking entity on a tile, if any
+    pub fn blocker(&self, x: usize, y: usize) -> Option<EntityId> {
+        self.at(x, y).iter().cloned().find(|&id| self.is_blocking(id))
+    }
+
+    /// Returns true if there is a blocking entity on a tile
+    pub fn is_occupied(&self, x: usize, y: usize) -> bool {
+        self.blocker(x, y).is_some()
+    }
+
+    /// Returns the entities in a rectangle (clipped to the level's bounds),
+    /// sorted by position (y, then x)
+    pub fn in_rect(&self, rect: Rect) -> Vec<EntityId> {
+        let mut res = vec![];
+        for y in rect.y..cmp::min(rect.y + rect.depth, self.depth) {
+            for x in rect.x..cmp::min(rect.x + rect.width, self.width) {
+                res.extend_from_slice(self.at(x, y));
+            }
+        }
+        res
+    }
+
+    /// Puts an entity at a new position, without checking if it can move
+    /// there (only bounds and occupancy are checked).
+    pub fn set_position(&mut self, id: EntityId, pos: (usize, usize)) -> Result<(), EntityError> {
+        let (old, blocking) = match self.entities.get(&id) {
+            Some(entity) => (entity.pos, entity.blocking),
+            None => return Err(EntityError::NoSuchEntity),
+        };
+        self.check_tile(pos, blocking, Some(id))?;
+        self.tiles[old.1 * self.width + old.0].retain(|&other| other != id);
+        self.tiles[pos.1 * self.width + pos.0].push(id);
+        self.entities.get_mut(&id).unwrap().pos = pos;
+        Ok(())
+    }
+
+    /// Moves an entity to an adjacent tile (see `Level::is_move_possible`)
+    pub fn move_to<FT: Default + Clone, WT: Wall>(&mut self, level: &Level<FT, WT>,
+                                                 id: EntityId, pos: (usize, usize)) -> Result<(), EntityError> {
+        let start = match self.position(id) {
+            Some(start) => start,
+            None => return Err(EntityError::NoSuchEntity),
+        };
+        if pos.0 >= self.width || pos.1 >= self.depth {
+            return Err(EntityError::OutOfBounds);
+        }
+        if !level.is_move_possible(start, pos) {
+            return Err(EntityError::Blocked);
+        }
+        self.set_position(id, pos)
+    }
+
+    /// Finds the shortest path between two tiles (see `Level::find_path`),
+    /// treating tiles occupied by blocking entities according to `occupancy`.
+    ///
+    /// The start and goal tiles are never considered occupied, so the path
+    /// can start from a character and lead to another one.
+    pub fn find_path<FT: Default + Clone, WT: Wall>(&self, level: &Level<FT, WT>,
+                                                   start: (usize, usize), goal: (usize, usize),
+                                                   occupancy: Occupancy) -> Option<Vec<(usize, usize)>> {
+        if start.0 >= level.width() || start.1 >= level.depth()
+            || goal.0 >= level.width() || goal.1 >= level.depth() {
+            return None;
+        }
+        let occupied = |pos: (usize, usize)| pos != start && pos != goal && self.is_occupied(pos.0, pos.1);
+        astar::search(start, goal,
+                      |pos| {
+                          level.neighbours(pos)
+                              .into_iter()
+                              .filter_map(|next| {
+                                  let cost = astar::octile(pos, next);
+                                  match occupancy {
+                                      Occupancy::Ignore => Some((next, cost)),
+                                      Occupancy::Blocked if occupied(next) => None,
+                                      Occupancy::Cost(extra) if occupied(next) => Some((next, cost + extra)),
+                                      _ => Some((next, cost)),
+                                  }
+                              })
+                              .collect()
+                      },
+                      |pos| astar::octile(pos, goal))
+            .map(|(path, _)| path)
+    }
+}
+
+
+#[test]
+fn entities_occupancy() {
+    let level: Level = Level::new(5, 5, 0.0);
+    let mut entities = Entities::new(5, 5);
+    let a = entities.insert((0, 0), true, 'a').unwrap();
+    let b = entities.insert((1, 0), true, 'b').unwrap();
+    let item = entities.insert((1, 0), false, 'i').unwrap();
+
+    assert_eq!(entities.insert((1, 0), true, 'c'), Err(EntityError::Occupied(b)));
+    assert_eq!(entities.insert((5, 0), false, 'c'), Err(EntityError::OutOfBounds));
+    assert_eq!(entities.move_to(&level, a, (1, 0)), Err(EntityError::Occupied(b)));
+    assert_eq!(entities.move_to(&level, a, (2, 0)), Err(EntityError::Blocked));
+    assert_eq!(entities.move_to(&level, item, (0, 0)), Ok(()));
+    assert_eq!(entities.at(0, 0), &[a, item]);
+
+    assert_eq!(entities.remove(b), Some('b'));
+    assert_eq!(entities.remove(b), None);
+    assert_eq!(entities.move_to(&level, a, (1, 1)), Ok(()));
+    assert_eq!(entities.position(a), Some((1, 1)));
+    assert!(entities.at(1, 0).is_empty());
+
+    *entities.get_mut(item).unwrap() = 'j';
+    assert_eq!(entities.get(item), Some(&'j'));
+    assert_eq!(entities.in_rect(Rect::new(0, 0, 2, 2)), vec![item, a]);
+    assert_eq!(entities.in_rect(Rect::new(1, 1, 10, 10)), vec![a]);
+    assert_eq!(entities.ids(), vec![a, item]);
+}
+
+#[test]
+fn entities_paths() {
+    use wall::WallPosition;
+
+    // Corridor along y = 1, with a character in the middle
+    let mut level: Level = Level::new(7, 3, 0.0);
+    for x in 0..7 {
+        level.set_wall(x, 1, WallPosition::Bottom, Some(()));
+        if x != 3 {
+            level.set_wall(x, 1, WallPosition::Top, Some(()));
+        }
+    }
+    let mut entities = Entities::new(7, 3);
+    entities.insert((3, 1), true, ()).unwrap();
+
+    let direct = entities.find_path(&level, (0, 1), (6, 1), Occupancy::Ignore).unwrap();
+    assert_eq!(direct.len(), 7);
+    // Go around the character, through the opening at (3, 2)
+    let around = entities.find_path(&level, (0, 1), (6, 1), Occupancy::Blocked).unwrap();
+    assert!(around.contains(&(3, 2)) && !around.contains(&(3, 1)));
+    let costly = entities.find_path(&level, (0, 1), (6, 1), Occupancy::Cost(0.1)).unwrap();
+    assert_eq!(costly, direct);
+    // Goal can be an occupied tile
+    assert!(entities.find_path(&level, (0, 1), (3, 1), Occupancy::Blocked).is_some());
+}
@@ -0,0 +1,494 @@
+// (C) 2017, Élisabeth Henry
+//
+// Licensed under either of
+// 
+// Apache License, Version 2.0: http://www.apache.org/licenses/LICENSE-2.0
+// MIT license: http://opensource.org/licenses/MIT
+// at your option.
+//
+// Unless you explicitly state otherwise, any contribution intentionally submitted
+// for inclusion in the work by you, as defined in the Apache-2.0 license, shall be
+// dual licensed as above, without any additional terms or conditions.
+
+use level::Level;
+use wall::Wall;
+use rng::Rng;
+
+use std::default::Default;
+
+/// Statistics about the heights of a level
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct HeightStats {
+    /// Lowest height
+    pub min_z: f32,
+    /// Highest height
+    pub max_z: f32,
+    /// Average height
+    pub mean_z: f32,
+    /// Largest height difference between two adjacent tiles
+    pub max_slope: f32,
+    /// Average height difference between two adjacent tiles
+    pub roughness: f32,
+}
+
+impl HeightStats {
+    /// Computes the statistics of a level's heights
+    pub fn of<FT: Default + Clone, WT: Wall>(level: &Level<FT, WT>) -> HeightStats {
+        let mut min_z = level.z(0, 0);
+        let mut max_z = min_z;
+        let mut sum = 0.0;
+        let mut max_slope: f32 = 0.0;
+        let mut slopes = 0.0;
+        let mut pairs = 0;
+        for x in 0..level.width() {
+            for y in 0..level.depth() {
+                let z = level.z(x, y);
+                min_z = min_z.min(z);
+                max_z = max_z.max(z);
+                sum += z;
+                if x + 1 < level.width() {
+                    let d = (z - level.z(x + 1, y)).abs();
+                    max_slope = max_slope.max(d);
+                    slopes += d;
+                    pairs += 1;
+                }
+                if y + 1 < level.depth() {
+                    let d = (z - level.z(x, y + 1)).abs();
+                    max_slope = max_slope.max(d);
+                    slopes += d;
+                    pairs += 1;
+                }
+            }
+        }
+        HeightStats {
+            min_z,
+            max_z,
+            mean_z: sum / (level.width() * level.depth()) as f32,
+            max_slope,
+            roughness: if pairs > 0 { slopes / pairs as f32 } else { 0.0 },
+        }
+    }
+}
+
+/// What an erosion pass did to a level
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ErosionStats {
+    /// Statistics before the pass
+    pub before: HeightStats,
+    /// Statistics after the pass
+    pub after: HeightStats,
+    /// Total height of material that was moved
+    pub moved: f32,
+}
+
+/// Thermal erosion: material falls from a tile to its lowest neighbour
+/// whenever the slope between them is steeper than the talus angle, which
+/// smoothes cliffs and spikes. The total amount of material is preserved.
+///
+/// # Example
+///
+/// ```
+/// use isometric::{Level, ThermalErosion};
+///
+/// let mut level: Level = Level::new(10, 10, 0.0);
+/// level.set_z(5, 5, 8.0);
+///
+/// let stats = ThermalErosion::new(1).apply(&mut level);
+/// assert!(stats.after.max_slope < stats.before.max_slope);
+/// ```
+#[derive(Debug, Clone)]
+pub struct ThermalErosion {
+    seed: u64,
+    iterations: usize,
+    talus: f32,
+    rate: f32,
+}
+
+impl ThermalErosion {
+    /// Creates a new thermal erosion pass.
+    ///
+    /// By default, it runs 50 iterations, the talus is 1.0 and half of the
+    /// excess material is moved at each step.
+    pub fn new(seed: u64) -> ThermalErosion {
+        ThermalErosion {
+            seed,
+            iterations: 50,
+            talus: 1.0,
+            rate: 0.5,
+        }
+    }
+
+    /// Returns the seed
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Sets the seed, which decides the order in which tiles are visited
+    pub fn set_seed(&mut self, seed: u64) -> &mut Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Returns the number of iterations
+    pub fn iterations(&self) -> usize {
+        self.iterations
+    }
+
+    /// Sets the number of iterations
+    pub fn set_iterations(&mut self, iterations: usize) -> &mut Self {
+        self.iterations = iterations;
+        self
+    }
+
+    /// Returns the height difference above which material falls
+    pub fn talus(&self) -> f32 {
+        self.talus
+    }
+
+    /// Sets the height difference above which material falls
+    pub fn set_talus(&mut self, talus: f32) -> &mut Self {
+        self.talus = talus;
+        self
+    }
+
+    /// Returns the part of the excess material moved at each step
+    pub fn rate(&self) -> f32 {
+        self.rate
+    }
+
+    /// Sets the part of the excess material moved at each step, between 0.0
+    /// and 1.0 (higher is faster but less smooth)
+    pub fn set_rate(&mut self, rate: f32) -> &mut Self {
+        debug_assert!(rate >= 0.0 && rate <= 1.0, "rate must be between 0.0 and 1.0");
+        self.rate = rate;
+        self
+    }
+
+    /// Erodes the level in place. Stops early if nothing moves.
+    pub fn apply<FT: Default + Clone, WT: Wall>(&self, level: &mut Level<FT, WT>) -> ErosionStats {
+        let before = HeightStats::of(level);
+        let (width, depth) = (level.width(), level.depth());
+        let mut rng = Rng::new(self.seed);
+        let mut order: Vec<(usize, usize)> = (0..depth)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .collect();
+        let mut moved = 0.0;
+
+        for _ in 0..self.iterations {
+            // Shuffle so there is no bias towards a direction
+            for i in (1..order.len()).rev() {
+                let j = rng.range(0, i + 1);
+                order.swap(i, j);
+            }
+            let mut moved_now = 0.0;
+            for &(x, y) in &order {
+                let z = level.z(x, y);
+                let mut lowest = None;
+                for &(dx, dy) in &[(-1, 0), (1, 0), (0, -1), (0, 1)] {
+                    let nx = x as isize + dx;
+                    let ny = y as isize + dy;
+                    if nx < 0 || ny < 0 || nx >= width as isize || ny >= depth as isize {
+                        continue;
+                    }
+                    let (nx, ny) = (nx as usize, ny as usize);
+                    let nz = level.z(nx, ny);
+                    if lowest.map_or(true, |(_, _, lz)| nz < lz) {
+                        lowest = Some((nx, ny, nz));
+                    }
+                }
+                if let Some((nx, ny, nz)) = lowest {
+                    let diff = z - nz;
+                    if diff > self.talus {
+                        let amount = self.rate * (diff - self.talus) / 2.0;
+                        level.set_z(x, y, z - amount);
+                        level.set_z(nx, ny, nz + amount);
+                        moved_now += amount;
+                    }
+                }
+            }
+            moved += moved_now;
+            if moved_now == 0.0 {
+                break;
+            }
+        }
+
+        ErosionStats {
+            before,
+            after: HeightStats::of(level),
+            moved,
+        }
+    }
+}
+
+/// Hydraulic erosion: rain droplets roll down the terrain, picking up material
+/// on steep slopes and dropping it where they slow down, which carves valleys
+/// and fills basins.
+///
+/// Heights are interpolated between the centres of tiles. Droplets that leave
+/// the level take their sediment with them.
+///
+/// # Example
+///
+/// ```
+/// use isometric::{HydraulicErosion, TerrainGenerator};
+///
+/// let mut level = TerrainGenerator::new(32, 32, 5).generate::<(), ()>(());
+/// let stats = HydraulicErosion::new(5).apply(&mut level);
+/// assert!(stats.moved > 0.0);
+/// ```
+#[derive(Debug, Clone)]
+pub struct HydraulicErosion {
+    seed: u64,
+    droplets: usize,
+    lifetime: usize,
+    inertia: f32,
+    capacity: f32,
+    erosion: f32,
+    deposition: f32,
+    evaporation: f32,
+    min_slope: f32,
+}
+
+impl HydraulicErosion {
+    /// Creates a new hydraulic erosion pass.
+    ///
+    /// By default, 5000 droplets are dropped, each one living at most 30
+    /// steps.
+    pub fn new(seed: u64) -> HydraulicErosion {
+        HydraulicErosion {
+            seed,
+            droplets: 5000,
+            lifetime: 30,
+            inertia: 0.05,
+            capacity: 4.0,
+            erosion: 0.3,
+            deposition: 0.3,
+            evaporation: 0.01,
+            min_slope: 0.01,
+        }
+    }
+
+    /// Returns the seed
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Sets the seed, which decides where droplets fall
+    pub fn set_seed(&mut self, seed: u64) -> &mut Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Returns the number of droplets and the maximum number of steps of each one
+    pub fn droplets(&self) -> (usize, usize) {
+        (self.droplets, self.lifetime)
+    }
+
+    /// Sets the number of droplets and the maximum number of steps of each one
+    pub fn set_droplets(&mut self, droplets: usize, lifetime: usize) -> &mut Self {
+        self.droplets = droplets;
+        self.lifetime = lifetime;
+        self
+    }
+
+    /// Returns how much droplets keep their direction instead of following
+    /// the slope
+    pub fn inertia(&self) -> f32 {
+        self.inertia
+    }
+
+    /// Sets how much droplets keep their direction instead of following the
+    /// slope, between 0.0 and 1.0
+    pub fn set_inertia(&mut self, inertia: f32) -> &mut Self {
+        debug_assert!(inertia >= 0.0 && inertia <= 1.0, "inertia must be between 0.0 and 1.0");
+        self.inertia = inertia;
+        self
+    }
+
+    /// Returns how much sediment a droplet can carry, relative to its speed,
+    /// water and slope
+    pub fn capacity(&self) -> f32 {
+        self.capacity
+    }
+
+    /// Sets how much sediment a droplet can carry, relative to its speed,
+    /// water and slope
+    pub fn set_capacity(&mut self, capacity: f32) -> &mut Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Returns the erosion and deposition rates
+    pub fn rates(&self) -> (f32, f32) {
+        (self.erosion, self.deposition)
+    }
+
+    /// Sets the part of the missing (resp. excess) sediment a droplet picks
+    /// up (resp. drops) at each step, between 0.0 and 1.0
+    pub fn set_rates(&mut self, erosion: f32, deposition: f32) -> &mut Self {
+        self.erosion = erosion;
+        self.deposition = deposition;
+        self
+    }
+
+    /// Returns the part of its water a droplet loses at each step
+    pub fn evaporation(&self) -> f32 {
+        self.evaporation
+    }
+
+    /// Sets the part of its water a droplet loses at each step
+    pub fn set_evaporation(&mut self, evaporation: f32) -> &mut Self {
+        self.evaporation = evaporation;
+        self
+    }
+
+    /// Returns the height and gradient at (x, y), interpolated between the
+    /// centres of the four surrounding tiles
+    fn sample<FT: Default + Clone, WT: Wall>(level: &Level<FT, WT>, x: f32, y: f32) -> (f32, f32, f32) {
+        let (ix, iy) = (x as usize, y as usize);
+        let (fx, fy) = (x - ix as f32, y - iy as f32);
+        let z00 = level.z(ix, iy);
+        let z10 = level.z(ix + 1, iy);
+        let z01 = level.z(ix, iy + 1);
+        let z11 = level.z(ix + 1, iy + 1);
+        let gx = (z10 - z00) * (1.0 - fy) + (z11 - z01) * fy;
+        let gy = (z01 - z00) * (1.0 - fx) + (z11 - z10) * fx;
+        let z = z00 * (1.0 - fx) * (1.0 - fy) + z10 * fx * (1.0 - fy)
+            + z01 * (1.0 - fx) * fy + z11 * fx * fy;
+        (z, gx, gy)
+    }
+
+    /// Adds `amount` (possibly negative) at (x, y), spread between the four
+    /// surrounding tiles
+    fn spread<FT: Default + Clone, WT: Wall>(level: &mut Level<FT, WT>, x: f32, y: f32, amount: f32) {
+        let (ix, iy) = (x as usize, y as usize);
+        let (fx, fy) = (x - ix as f32, y - iy as f32);
+        for &(dx, dy, w) in &[(0, 0, (1.0 - fx) * (1.0 - fy)),
+                              (1, 0, fx * (1.0 - fy)),
+                              (0, 1, (1.0 - fx) * fy),
+                              (1, 1, fx * fy)] {
+            let z = level.z(ix + dx, iy + dy);
+            level.set_z(ix + dx, iy + dy, z + amount * w);
+        }
+    }
+
+    /// Erodes the level in place
+    pub fn apply<FT: Default + Clone, WT: Wall>(&self, level: &mut Level<FT, WT>) -> ErosionStats {
+        let before = HeightStats::of(level);
+        let (width, depth) = (level.width(), level.depth());
+        let mut moved = 0.0;
+        if width < 2 || depth < 2 {
+            return ErosionStats { before, after: before, moved: 0.0 };
+        }
+        // Droplets stay where four tiles can be sampled
+        let (max_x, max_y) = ((width - 1) as f32, (depth - 1) as f32);
+        let mut rng = Rng::new(self.seed);
+
+        for _ in 0..self.droplets {
+            let mut x = rng.float() * max_x;
+            let mut y = rng.float() * max_y;
+            let (mut dir_x, mut dir_y) = (0.0, 0.0);
+            let mut speed = 1.0;
+            let mut water = 1.0;
+            let mut sediment = 0.0;
+
+            for _ in 0..self.lifetime {
+                let (z, gx, gy) = Self::sample(level, x, y);
+                dir_x = dir_x * self.inertia - gx * (1.0 - self.inertia);
+                dir_y = dir_y * self.inertia - gy * (1.0 - self.inertia);
+                let len = (dir_x * dir_x + dir_y * dir_y).sqrt();
+                if len == 0.0 {
+                    // Flat ground: drop everything
+                    Self::spread(level, x, y, sediment);
+                    moved += sediment;
+                    break;
+                }
+                dir_x /= len;
+                dir_y /= len;
+                let (nx, ny) = (x + dir_x, y + dir_y);
+                if nx < 0.0 || ny < 0.0 || nx >= max_x || ny >= max_y {
+                    break;
+                }
+                let (new_z, _, _) = Self::sample(level, nx, ny);
+                let dz = new_z - z;
+                let capacity = (-dz).max(self.min_slope) * speed * water * self.capacity;
+
+                if dz > 0.0 || sediment > capacity {
+                    // Going uphill fills the hole behind, otherwise drop the excess
+                    let amount = if dz > 0.0 {
+                        dz.min(sediment)
+                    } else {
+                        (sediment - capacity) * self.deposition
+                    };
+                    Self::spread(level, x, y, amount);
+                    sediment -= amount;
+                    moved += amount;
+                } else {
+                    // Never dig deeper than the next position
+                    let amount = ((capacity - sediment) * self.erosion).min(-dz);
+                    Self::spread(level, x, y, -amount);
+                    sediment += amount;
+                    moved += amount;
+                }
+
+                speed = (speed * speed - dz).max(0.0).sqrt();
+                water *= 1.0 - self.evaporation;
+                x = nx;
+                y = ny;
+            }
+        }
+
+        ErosionStats {
+            before,
+            after: HeightStats::of(level),
+            moved,
+        }
+    }
+}
+
+
+#[test]
+fn thermal_erosion() {
+    let mut level: Level = Level::new(12, 12, 0.0);
+    level.set_z(6, 6, 10.0);
+    level.set_z(2, 3, 4.0);
+    let mut erosion = ThermalErosion::new(3);
+    erosion.set_iterations(500);
+    let stats = erosion.apply(&mut level);
+
+    // Material is preserved, and slopes end up close to the talus
+    assert!((stats.before.mean_z - stats.after.mean_z).abs() < 1e-4);
+    assert!(stats.after.max_slope <= 1.0 + 1e-2);
+    assert!(stats.after.max_z < stats.before.max_z);
+    assert!(stats.moved > 0.0);
+
+    // Nothing to do on smooth terrain
+    let stats = erosion.apply(&mut level);
+    assert!(stats.moved < 0.1);
+}
+
+#[test]
+fn hydraulic_erosion() {
+    use terrain::TerrainGenerator;
+
+    let mut generator = TerrainGenerator::new(40, 40, 2);
+    generator.set_heights(0.0, 10.0);
+    let original: Level = generator.generate(());
+
+    let mut a: Level = generator.generate(());
+    let mut b: Level = generator.generate(());
+    let erosion = HydraulicErosion::new(11);
+    let stats = erosion.apply(&mut a);
+    erosion.apply(&mut b);
+
+    // Same seed, same result
+    for x in 0..40 {
+        for y in 0..40 {
+            assert_eq!(a.z(x, y), b.z(x, y));
+        }
+    }
+    assert!(stats.moved > 0.0);
+    assert_eq!(stats.before, HeightStats::of(&original));
+    assert!(stats.after.max_z <= stats.before.max_z);
+    assert!(stats.after.min_z >= stats.before.min_z - 0.5);
+}
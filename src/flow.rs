@@ -0,0 +1,303 @@
+// (C) 2017, Élisabeth Henry
+//
+// Licensed under either of
+// 
+// Apache License, Version 2.0: http://www.apache.org/licenses/LICENSE-2.0
+// MIT license: http://opensource.org/licenses/MIT
+// at your option.
+//
+// Unless you explicitly state otherwise, any contribution intentionally submitted
+// for inclusion in the work by you, as defined in the Apache-2.0 license, shall be
+// dual licensed as above, without any additional terms or conditions.
+
+use level::Level;
+use wall::Wall;
+use rect::Rect;
+use dijkstra;
+use dijkstra::DijkstraMap;
+
+use std::cmp;
+use std::collections::HashSet;
+use std::default::Default;
+use std::f32;
+
+/// A flow field, guiding any number of units towards the same goals
+/// (see `Level::flow_field`).
+///
+/// It is made of an integration field (the cost of the cheapest path from
+/// each tile to the closest goal) and, for each tile, the neighbour to move
+/// to next. Units just have to follow the directions, so no path has to be
+/// computed for each of them.
+///
+/// # Example
+///
+/// ```
+/// use isometric::{Level, WallPosition, Rect};
+///
+/// let mut level: Level = Level::new(10, 10, 0.0);
+/// let cost = |_, _| 1.0;
+/// let mut field = level.flow_field(&[(9, 0)], &cost);
+/// assert_eq!(field.next_tile(5, 0), Some((6, 0)));
+///
+/// // Block the way, then only update the part of the field that changed
+/// level.set_wall(5, 0, WallPosition::Right, Some(()));
+/// field.update(&level, Rect::new(5, 0, 1, 1), &cost);
+/// assert_eq!(field.next_tile(5, 0), Some((6, 1)));
+///
+/// // Units in the middle of a tile head to the center of the next one
+/// assert_eq!(field.direction_at(5.5, 0.5), Some([0.70710677, 0.70710677]));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlowField {
+    goals: Vec<(usize, usize)>,
+    integration: DijkstraMap,
+    next: Vec<Option<(usize, usize)>>,
+}
+
+impl FlowField {
+    /// Creates the flow field, `cost` returning the cost of moving between
+    /// two adjacent tiles (see `Level::dijkstra_map`)
+    pub fn new<FT, WT, F>(level: &Level<FT, WT>, goals: &[(usize, usize)], cost: F) -> FlowField
+        where FT: Default + Clone,
+              WT: Wall,
+              F: Fn((usize, usize), (usize, usize)) -> f32 {
+        let mut field = FlowField {
+            goals: goals.to_vec(),
+            integration: level.dijkstra_map(goals, cost, f32::INFINITY),
+            next: vec![None; level.width() * level.depth()],
+        };
+        for y in 0..level.depth() {
+            for x in 0..level.width() {
+                field.compute_next(level, (x, y));
+            }
+        }
+        field
+    }
+
+    /// Returns the width of the field
+    pub fn width(&self) -> usize {
+        self.integration.width()
+    }
+
+    /// Returns the depth of the field
+    pub fn depth(&self) -> usize {
+        self.integration.depth()
+    }
+
+    /// Returns the goals of the field
+    pub fn goals(&self) -> &[(usize, usize)] {
+        &self.goals
+    }
+
+    /// Returns the integration field, i.e. the cost to get from each tile to
+    /// the closest goal
+    pub fn integration(&self) -> &DijkstraMap {
+        &self.integration
+    }
+
+    /// Returns the tile to move to from (x, y), or `None` if (x, y) is a goal
+    /// or can't reach one
+    pub fn next_tile(&self, x: usize, y: usize) -> Option<(usize, usize)> {
+        debug_assert!(x < self.width() && y < self.depth(), "x and y must be in field's bounds");
+        self.next[y * self.width() + x]
+    }
+
+    /// Returns the (normalized) direction to follow for a unit at the world
+    /// position (x, y), tile (x, y) spanning from (x, y) to (x + 1, y + 1).
+    ///
+    /// The unit is steered towards the center of the next tile, so it gets
+    /// back on track if it drifted away from its own tile's center. Returns
+    /// `None` outside of the field, on a goal or if no goal can be reached.
+    pub fn direction_at(&self, x: f32, y: f32) -> Option<[f32; 2]> {
+        if !(x >= 0.0 && y >= 0.0) {
+            return None;
+        }
+        let (tile_x, tile_y) = (x as usize, y as usize);
+        if tile_x >= self.width() || tile_y >= self.depth() {
+            return None;
+        }
+        self.next_tile(tile_x, tile_y).map(|(next_x, next_y)| {
+            let dx = next_x as f32 + 0.5 - x;
+            let dy = next_y as f32 + 0.5 - y;
+            let norm = (dx * dx + dy * dy).sqrt();
+            [dx / norm, dy / norm]
+        })
+    }
+
+    /// Updates the field after the level changed inside `rect` (e.g. with
+    /// `set_wall` or `set_z`), or after `cost` changed for moves from or to
+    /// tiles of `rect`.
+    ///
+    /// Only the tiles whose way to the goal went through `rect` (or right
+    /// next to it), and the ones that get a shorter way through it, are
+    /// visited, so small changes are much cheaper than creating a new field.
+    /// The level must keep the same dimensions.
+    ///
+    /// Returns the number of tiles whose value was recomputed.
+    pub fn update<FT, WT, F>(&mut self, level: &Level<FT, WT>, rect: Rect, cost: F) -> usize
+        where FT: Default + Clone,
+              WT: Wall,
+              F: Fn((usize, usize), (usize, usize)) -> f32 {
+        debug_assert!(level.width() == self.width() && level.depth() == self.depth(),
+                      "level and field must have the same dimensions");
+        let (width, depth) = (self.width(), self.depth());
+        if rect.width == 0 || rect.depth == 0 || rect.x >= width || rect.y >= depth {
+            return 0;
+        }
+        // Moves between the tiles right next to rect can also change, e.g.
+        // diagonal moves along a new wall
+        let x0 = rect.x.saturating_sub(1);
+        let y0 = rect.y.saturating_sub(1);
+        let x1 = cmp::min(rect.x + rect.width + 1, width);
+        let y1 = cmp::min(rect.y + rect.depth + 1, depth);
+        let changed = Rect::new(x0, y0, x1 - x0, y1 - y0);
+
+        let invalid = self.invalid_tiles(changed);
+        for &(x, y) in &invalid {
+            let value = if self.goals.contains(&(x, y)) { Some(0.0) } else { None };
+            self.integration.set_value(x, y, value);
+        }
+        // Start from the goals that were reset and from the tiles around the
+        // invalid ones, which are still up to date
+        let mut sources = vec![];
+        for &pos in &invalid {
+            if self.goals.contains(&pos) {
+                sources.push(pos);
+            }
+            for next in self.adjacent(pos) {
+                if !invalid.contains(&next) {
+                    sources.push(next);
+                }
+            }
+        }
+        let lowered = dijkstra::scan_from(level, &mut self.integration, &sources, cost, f32::INFINITY);
+
+        // Directions only change around tiles whose value changed
+        let mut recomputed = invalid;
+        recomputed.extend(lowered);
+        let mut dirty = HashSet::new();
+        for &pos in &recomputed {
+            dirty.insert(pos);
+            dirty.extend(self.adjacent(pos));
+        }
+        for pos in dirty {
+            self.compute_next(level, pos);
+        }
+        recomputed.len()
+    }
+
+    /// Returns the tiles around pos, diagonals included
+    fn adjacent(&self, pos: (usize, usize)) -> Vec<(usize, usize)> {
+        let mut res = vec![];
+        for dx in 0..3 {
+            for dy in 0..3 {
+                let (x, y) = ((pos.0 + dx).wrapping_sub(1), (pos.1 + dy).wrapping_sub(1));
+                if (x, y) != pos && x < self.width() && y < self.depth() {
+                    res.push((x, y));
+                }
+            }
+        }
+        res
+    }
+
+    /// Returns the tiles of `rect`, and the tiles whose directions lead
+    /// through it
+    fn invalid_tiles(&self, rect: Rect) -> HashSet<(usize, usize)> {
+        let mut invalid = HashSet::new();
+        let mut todo = vec![];
+        for y in rect.y..rect.y + rect.depth {
+            for x in rect.x..rect.x + rect.width {
+                invalid.insert((x, y));
+                todo.push((x, y));
+            }
+        }
+        // Follow the directions backwards
+        while let Some(pos) = todo.pop() {
+            for prev in self.adjacent(pos) {
+                if self.next_tile(prev.0, prev.1) == Some(pos) && invalid.insert(prev) {
+                    todo.push(prev);
+                }
+            }
+        }
+        invalid
+    }
+
+    /// Sets the next tile of pos from the integration field
+    fn compute_next<FT: Default + Clone, WT: Wall>(&mut self, level: &Level<FT, WT>,
+                                                   pos: (usize, usize)) {
+        let next = if self.integration.value(pos.0, pos.1).is_some() {
+            self.integration.descend(level, pos)
+        } else {
+            None
+        };
+        let width = self.width();
+        self.next[pos.1 * width + pos.0] = next;
+    }
+}
+
+
+#[test]
+fn flow_field_update() {
+    use wall::WallPosition;
+
+    let cost = |from: (usize, usize), to: (usize, usize)| {
+        if from.0 != to.0 && from.1 != to.1 { 1.5 } else { 1.0 }
+    };
+    let mut level: Level = Level::new(12, 8, 0.0);
+    let mut field = level.flow_field(&[(10, 4), (11, 7)], cost);
+    assert_eq!(field.next_tile(10, 4), None);
+    assert_eq!(field.next_tile(9, 4), Some((10, 4)));
+
+    // Build a wall with a door, then remove part of it: the updated field
+    // must be the same as a new one
+    for y in 0..8 {
+        level.set_wall(5, y, WallPosition::Right, Some(()));
+        field.update(&level, Rect::new(5, y, 1, 1), cost);
+        assert_eq!(field, level.flow_field(&[(10, 4), (11, 7)], cost));
+    }
+    assert_eq!(field.integration().value(0, 0), None);
+    assert_eq!(field.next_tile(0, 0), None);
+
+    level.set_wall(5, 1, WallPosition::Right, None);
+    level.set_z(3, 3, 2.0);
+    field.update(&level, Rect::new(3, 1, 3, 3), cost);
+    assert_eq!(field, level.flow_field(&[(10, 4), (11, 7)], cost));
+    assert_eq!(field.next_tile(5, 1).map(|(x, _)| x), Some(6));
+
+    // Sampling
+    assert_eq!(field.direction_at(9.5, 4.5), Some([1.0, 0.0]));
+    assert_eq!(field.direction_at(10.2, 4.7), None);
+    assert_eq!(field.direction_at(-1.0, 0.0), None);
+    assert_eq!(field.direction_at(12.0, 0.0), None);
+    let dir = field.direction_at(9.5, 4.1).unwrap();
+    assert!(dir[0] > 0.0 && dir[1] > 0.0);
+}
+
+#[test]
+fn flow_field_work() {
+    use wall::WallPosition;
+
+    let cost = |from: (usize, usize), to: (usize, usize)| {
+        if from.0 != to.0 && from.1 != to.1 { 1.5 } else { 1.0 }
+    };
+    // Only the tiles behind a change are visited
+    let mut level: Level = Level::new(100, 100, 0.0);
+    let mut field = level.flow_field(&[(0, 0)], cost);
+    level.set_wall(95, 95, WallPosition::Right, Some(()));
+    let work = field.update(&level, Rect::new(95, 95, 1, 1), cost);
+    assert_eq!(field, level.flow_field(&[(0, 0)], cost));
+    assert!(work > 0 && work < 100, "{} tiles recomputed", work);
+
+    // Removing it again lowers the values behind it
+    level.set_wall(95, 95, WallPosition::Right, None);
+    let work = field.update(&level, Rect::new(95, 95, 1, 1), cost);
+    assert_eq!(field, level.flow_field(&[(0, 0)], cost));
+    assert!(work < 100, "{} tiles recomputed", work);
+    assert_eq!(field.update(&level, Rect::new(0, 0, 0, 0), cost), 0);
+
+    // A change near the goal affects most of the level
+    level.set_wall(0, 0, WallPosition::Right, Some(()));
+    let work = field.update(&level, Rect::new(0, 0, 1, 1), cost);
+    assert_eq!(field, level.flow_field(&[(0, 0)], cost));
+    assert!(work > 1000);
+}
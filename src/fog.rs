@@ -0,0 +1,176 @@
+// (C) 2017, Élisabeth Henry
+//
+// Licensed under either of
+// 
+// Apache License, Version 2.0: http://www.apache.org/licenses/LICENSE-2.0
+// MIT license: http://opensource.org/licenses/MIT
+// at your option.
+//
+// Unless you explicitly state otherwise, any contribution intentionally submitted
+// for inclusion in the work by you, as defined in the Apache-2.0 license, shall be
+// dual licensed as above, without any additional terms or conditions.
+
+use level::Level;
+use wall::Wall;
+
+use std::default::Default;
+
+/// Represents what is known about a tile
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TileVisibility {
+    /// Tile has never been seen
+    Unseen,
+    /// Tile has already been seen, but isn't currently visible
+    Explored,
+    /// Tile is currently visible by at least one viewer
+    Visible,
+}
+
+/// Fog of war: keeps track of the tiles of a level that have been seen.
+///
+/// # Example
+///
+/// ```
+/// use isometric::{Level, FogOfWar, TileVisibility};
+///
+/// let level: Level = Level::new(20, 20, 0.0);
+/// let mut fog = FogOfWar::new(&level);
+/// assert_eq!(fog.visibility(5, 5), TileVisibility::Unseen);
+///
+/// // A viewer at (5, 5), seeing up to 3 tiles away
+/// fog.update(&level, &[((5, 5), 3)]);
+/// assert_eq!(fog.visibility(5, 6), TileVisibility::Visible);
+///
+/// // Viewer went away
+/// fog.update(&level, &[((15, 15), 3)]);
+/// assert_eq!(fog.visibility(5, 6), TileVisibility::Explored);
+/// assert_eq!(fog.visibility(0, 19), TileVisibility::Unseen);
+/// ```
+#[derive(Debug, Clone)]
+pub struct FogOfWar {
+    width: usize,
+    depth: usize,
+    tiles: Vec<TileVisibility>,
+}
+
+impl FogOfWar {
+    /// Creates a new fog of war, with the same dimensions as the level, where
+    /// no tile has been seen yet
+    pub fn new<FT: Default + Clone, WT: Wall>(level: &Level<FT, WT>) -> FogOfWar {
+        FogOfWar {
+            width: level.width(),
+            depth: level.depth(),
+            tiles: vec![TileVisibility::Unseen; level.width() * level.depth()],
+        }
+    }
+
+    /// Returns the width of the fog of war
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Returns the depth of the fog of war
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Returns the visibility of a tile
+    ///
+    /// x must be strictly less than level's width and
+    /// y must be strictly less than level's height
+    pub fn visibility(&self, x: usize, y: usize) -> TileVisibility {
+        debug_assert!(x < self.width && y < self.depth, "x and y must be in level's bounds");
+        self.tiles[y * self.width + x]
+    }
+
+    /// Updates the fog of war from the field of view of some viewers.
+    ///
+    /// Each viewer is given by its position and its visibility radius (see
+    /// `Level::visible_from`). Tiles that were visible and are not anymore
+    /// become explored.
+    pub fn update<FT: Default + Clone, WT: Wall>(&mut self,
+                                                 level: &Level<FT, WT>,
+                                                 viewers: &[((usize, usize), usize)]) {
+        debug_assert!(level.width() == self.width && level.depth() == self.depth,
+                      "fog of war and level must have the same dimensions");
+        for tile in &mut self.tiles {
+            if *tile == TileVisibility::Visible {
+                *tile = TileVisibility::Explored;
+            }
+        }
+
+        for &(pos, radius) in viewers {
+            let matrix = level.visible_from(pos, radius);
+            for (i, column) in matrix.iter().enumerate() {
+                for (j, &visible) in column.iter().enumerate() {
+                    if !visible {
+                        continue;
+                    }
+                    let x = pos.0 as isize + i as isize - radius as isize;
+                    let y = pos.1 as isize + j as isize - radius as isize;
+                    if x < 0 || y < 0 || x >= self.width as isize || y >= self.depth as isize {
+                        continue;
+                    }
+                    self.tiles[y as usize * self.width + x as usize] = TileVisibility::Visible;
+                }
+            }
+        }
+    }
+
+    /// Marks every tile as explored (e.g. when reading a map of the level)
+    pub fn explore_all(&mut self) {
+        for tile in &mut self.tiles {
+            if *tile == TileVisibility::Unseen {
+                *tile = TileVisibility::Explored;
+            }
+        }
+    }
+
+    /// Forgets everything that was seen
+    pub fn reset(&mut self) {
+        for tile in &mut self.tiles {
+            *tile = TileVisibility::Unseen;
+        }
+    }
+}
+
+
+#[test]
+fn fog_memory() {
+    let level: Level = Level::new(20, 20, 0.0);
+    let mut fog = FogOfWar::new(&level);
+    fog.update(&level, &[((2, 2), 2)]);
+    assert_eq!(fog.visibility(2, 2), TileVisibility::Visible);
+    assert_eq!(fog.visibility(10, 10), TileVisibility::Unseen);
+
+    fog.update(&level, &[((10, 10), 2)]);
+    assert_eq!(fog.visibility(2, 2), TileVisibility::Explored);
+    assert_eq!(fog.visibility(10, 10), TileVisibility::Visible);
+
+    fog.reset();
+    assert_eq!(fog.visibility(2, 2), TileVisibility::Unseen);
+}
+
+#[test]
+fn fog_several_viewers() {
+    let level: Level = Level::new(20, 20, 0.0);
+    let mut fog = FogOfWar::new(&level);
+    fog.update(&level, &[((0, 0), 2), ((19, 19), 2)]);
+    assert_eq!(fog.visibility(0, 0), TileVisibility::Visible);
+    assert_eq!(fog.visibility(19, 19), TileVisibility::Visible);
+    assert_eq!(fog.visibility(10, 10), TileVisibility::Unseen);
+}
+
+#[test]
+fn fog_walls() {
+    use wall::WallPosition;
+
+    let mut level: Level = Level::new(20, 20, 0.0);
+    for y in 0..20 {
+        level.set_wall(5, y, WallPosition::Right, Some(()));
+    }
+    let mut fog = FogOfWar::new(&level);
+    fog.update(&level, &[((4, 10), 4)]);
+    assert_eq!(fog.visibility(5, 10), TileVisibility::Visible);
+    assert_eq!(fog.visibility(6, 10), TileVisibility::Unseen);
+}
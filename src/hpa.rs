@@ -0,0 +1,447 @@
+// (C) 2017, Élisabeth Henry
+//
+// Licensed under either of
+// 
+// Apache License, Version 2.0: http://www.apache.org/licenses/LICENSE-2.0
+// MIT license: http://opensource.org/licenses/MIT
+// at your option.
+//
+// Unless you explicitly state otherwise, any contribution intentionally submitted
+// for inclusion in the work by you, as defined in the Apache-2.0 license, shall be
+// dual licensed as above, without any additional terms or conditions.
+
+use level::Level;
+use wall::Wall;
+use rect::Rect;
+use astar;
+
+use std::cmp;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::default::Default;
+
+/// Openings along a border at least this wide get an entrance at each end
+/// instead of a single one in the middle
+const WIDE_ENTRANCE: usize = 6;
+
+/// Pair of adjacent tiles on each side of a border between two clusters
+type Entrance = ((usize, usize), (usize, usize));
+
+/// Paths between the entrance tiles of a cluster: the tiles reachable from
+/// each one, with their cost
+type ClusterEdges = HashMap<(usize, usize), Vec<((usize, usize), f32)>>;
+
+/// Abstraction layer for hierarchical pathfinding (HPA*) on large levels.
+///
+/// The level is partitioned into square clusters. Entrances are placed along
+/// the borders between clusters, and the cost of the paths between the
+/// entrances of a cluster is precomputed. Finding a path then only requires
+/// searching this small graph and refining the result inside a few clusters,
+/// which is much faster than `Level::find_path` on large levels, though paths
+/// can be slightly longer than the shortest ones.
+///
+/// When the level changes, `update` only recomputes the affected clusters.
+///
+/// # Example
+///
+/// ```
+/// use isometric::{Level, HpaGraph, WallPosition, Rect};
+///
+/// let mut level: Level = Level::new(100, 100, 0.0);
+/// let mut graph = HpaGraph::new(&level, 10);
+/// let path = graph.find_path(&level, (5, 5), (95, 90)).unwrap();
+/// assert_eq!(path.first(), Some(&(5, 5)));
+/// assert_eq!(path.last(), Some(&(95, 90)));
+///
+/// // Close a room around the goal
+/// for i in 90..100 {
+///     level.set_wall(i, 89, WallPosition::Top, Some(()));
+///     level.set_wall(89, i, WallPosition::Right, Some(()));
+/// }
+/// graph.update(&level, Rect::new(89, 89, 11, 11));
+/// assert_eq!(graph.find_path(&level, (5, 5), (95, 90)), None);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct HpaGraph {
+    width: usize,
+    depth: usize,
+    cluster_size: usize,
+    clusters_x: usize,
+    clusters_y: usize,
+    // Entrances between each cluster and the one at its right; the first
+    // tile of an entrance is in the left cluster
+    right: Vec<Vec<Entrance>>,
+    // Entrances between each cluster and the one above it
+    top: Vec<Vec<Entrance>>,
+    // Costs of the paths between the entrance tiles of each cluster
+    intra: Vec<ClusterEdges>,
+}
+
+impl HpaGraph {
+    /// Builds the abstract graph of a level, with clusters of
+    /// `cluster_size` x `cluster_size` tiles (e.g. 10 or 16)
+    pub fn new<FT: Default + Clone, WT: Wall>(level: &Level<FT, WT>, cluster_size: usize) -> HpaGraph {
+        debug_assert!(cluster_size > 0, "cluster_size must be positive");
+        let clusters_x = (level.width() + cluster_size - 1) / cluster_size;
+        let clusters_y = (level.depth() + cluster_size - 1) / cluster_size;
+        let n = clusters_x * clusters_y;
+        let mut graph = HpaGraph {
+            width: level.width(),
+            depth: level.depth(),
+            cluster_size,
+            clusters_x,
+            clusters_y,
+            right: vec![vec![]; n],
+            top: vec![vec![]; n],
+            intra: vec![HashMap::new(); n],
+        };
+        for c in 0..n {
+            graph.build_borders(level, c);
+        }
+        for c in 0..n {
+            graph.build_intra(level, c);
+        }
+        graph
+    }
+
+    /// Returns the size of the clusters
+    pub fn cluster_size(&self) -> usize {
+        self.cluster_size
+    }
+
+    /// Returns all the entrances, as pairs of adjacent tiles in two
+    /// different clusters
+    pub fn entrances(&self) -> Vec<((usize, usize), (usize, usize))> {
+        self.right.iter().chain(self.top.iter()).flat_map(|v| v.iter().cloned()).collect()
+    }
+
+    /// Returns the cluster containing a tile
+    fn cluster_of(&self, pos: (usize, usize)) -> usize {
+        (pos.1 / self.cluster_size) * self.clusters_x + pos.0 / self.cluster_size
+    }
+
+    /// Returns the tiles of a cluster
+    fn rect(&self, c: usize) -> Rect {
+        let x = (c % self.clusters_x) * self.cluster_size;
+        let y = (c / self.clusters_x) * self.cluster_size;
+        Rect::new(x, y,
+                  cmp::min(self.cluster_size, self.width - x),
+                  cmp::min(self.cluster_size, self.depth - y))
+    }
+
+    /// Computes the entrances on the right and top borders of a cluster
+    fn build_borders<FT: Default + Clone, WT: Wall>(&mut self, level: &Level<FT, WT>, c: usize) {
+        let rect = self.rect(c);
+        let (cx, cy) = (c % self.clusters_x, c / self.clusters_x);
+        self.right[c] = if cx + 1 < self.clusters_x {
+            let x = rect.x + rect.width - 1;
+            entrances((rect.y..rect.y + rect.depth).map(|y| ((x, y), (x + 1, y))), level)
+        } else {
+            vec![]
+        };
+        self.top[c] = if cy + 1 < self.clusters_y {
+            let y = rect.y + rect.depth - 1;
+            entrances((rect.x..rect.x + rect.width).map(|x| ((x, y), (x, y + 1))), level)
+        } else {
+            vec![]
+        };
+    }
+
+    /// Returns the entrance tiles in a cluster
+    fn nodes(&self, c: usize) -> Vec<(usize, usize)> {
+        let mut nodes: Vec<(usize, usize)> = vec![];
+        {
+            let mut add = |pos| if !nodes.contains(&pos) { nodes.push(pos); };
+            for &(a, _) in self.right[c].iter().chain(self.top[c].iter()) {
+                add(a);
+            }
+            if c % self.clusters_x != 0 {
+                for &(_, b) in &self.right[c - 1] {
+                    add(b);
+                }
+            }
+            if c >= self.clusters_x {
+                for &(_, b) in &self.top[c - self.clusters_x] {
+                    add(b);
+                }
+            }
+        }
+        nodes
+    }
+
+    /// Computes the paths between the entrances of a cluster
+    fn build_intra<FT: Default + Clone, WT: Wall>(&mut self, level: &Level<FT, WT>, c: usize) {
+        let rect = self.rect(c);
+        let nodes = self.nodes(c);
+        let mut edges: ClusterEdges = HashMap::new();
+        for (i, &a) in nodes.iter().enumerate() {
+            edges.entry(a).or_insert(vec![]);
+            for &b in &nodes[i + 1..] {
+                // Entrance tiles can be entered, so paths between them can
+                // be walked both ways
+                if let Some((_, cost)) = local_path(level, rect, a, b) {
+                    edges.entry(a).or_insert(vec![]).push((b, cost));
+                    edges.entry(b).or_insert(vec![]).push((a, cost));
+                }
+            }
+        }
+        self.intra[c] = edges;
+    }
+
+    /// Returns the tiles in other clusters reachable from an entrance tile
+    fn inter(&self, pos: (usize, usize)) -> Vec<(usize, usize)> {
+        let c = self.cluster_of(pos);
+        let mut res = vec![];
+        for &(a, b) in self.right[c].iter().chain(self.top[c].iter()) {
+            if a == pos {
+                res.push(b);
+            }
+        }
+        if c % self.clusters_x != 0 {
+            res.extend(self.right[c - 1].iter().filter(|e| e.1 == pos).map(|e| e.0));
+        }
+        if c >= self.clusters_x {
+            res.extend(self.top[c - self.clusters_x].iter().filter(|e| e.1 == pos).map(|e| e.0));
+        }
+        res
+    }
+
+    /// Updates the graph after the level changed inside `rect` (e.g. with
+    /// `set_wall`). Only the clusters around `rect` are recomputed. The level
+    /// must keep the same dimensions.
+    pub fn update<FT: Default + Clone, WT: Wall>(&mut self, level: &Level<FT, WT>, rect: Rect) {
+        debug_assert!(level.width() == self.width && level.depth() == self.depth,
+                      "level and graph must have the same dimensions");
+        if rect.width == 0 || rect.depth == 0 || rect.x >= self.width || rect.y >= self.depth {
+            return;
+        }
+        // Walls are shared with the adjacent tiles, which can be in another
+        // cluster
+        let x0 = rect.x.saturating_sub(1) / self.cluster_size;
+        let y0 = rect.y.saturating_sub(1) / self.cluster_size;
+        let x1 = cmp::min(rect.x + rect.width, self.width - 1) / self.cluster_size;
+        let y1 = cmp::min(rect.y + rect.depth, self.depth - 1) / self.cluster_size;
+        for cy in y0..y1 + 1 {
+            for cx in x0..x1 + 1 {
+                self.build_borders(level, cy * self.clusters_x + cx);
+            }
+        }
+        // Neighbouring clusters get new entrances too
+        let x0 = x0.saturating_sub(1);
+        let y0 = y0.saturating_sub(1);
+        let x1 = cmp::min(x1 + 1, self.clusters_x - 1);
+        let y1 = cmp::min(y1 + 1, self.clusters_y - 1);
+        for cy in y0..y1 + 1 {
+            for cx in x0..x1 + 1 {
+                self.build_intra(level, cy * self.clusters_x + cx);
+            }
+        }
+    }
+
+    /// Finds a path between `start` and `goal` (both included), or `None`
+    /// if there is none.
+    ///
+    /// Moves follow the same rules as `Level::find_path`. If the level
+    /// changed since the last `update`, the path is still valid, but can be
+    /// longer (or missing) because entrances are out of date.
+    pub fn find_path<FT, WT>(&self, level: &Level<FT, WT>,
+                             start: (usize, usize),
+                             goal: (usize, usize)) -> Option<Vec<(usize, usize)>>
+        where FT: Default + Clone,
+              WT: Wall {
+        if start.0 >= self.width || start.1 >= self.depth || goal.0 >= self.width || goal.1 >= self.depth {
+            return None;
+        }
+        let start_cluster = self.cluster_of(start);
+        let goal_cluster = self.cluster_of(goal);
+        if start_cluster == goal_cluster {
+            if let Some((path, _)) = local_path(level, self.rect(start_cluster), start, goal) {
+                return Some(path);
+            }
+        }
+
+        // Connect start and goal to the entrances of their clusters
+        let mut start_edges = vec![];
+        for node in self.nodes(start_cluster) {
+            if node != start {
+                if let Some((_, cost)) = local_path(level, self.rect(start_cluster), start, node) {
+                    start_edges.push((node, cost));
+                }
+            }
+        }
+        let mut goal_edges = HashMap::new();
+        for node in self.nodes(goal_cluster) {
+            if node != goal {
+                if let Some((_, cost)) = local_path(level, self.rect(goal_cluster), node, goal) {
+                    goal_edges.insert(node, cost);
+                }
+            }
+        }
+
+        // Edges of the graph that turned out to be wrong, if the level
+        // changed since the last update
+        let mut broken = HashSet::new();
+        'search: loop {
+            let abstract_path = astar::search(start, goal,
+                                              |pos| {
+                                                  let mut res = if pos == start {
+                                                      start_edges.clone()
+                                                  } else {
+                                                      self.intra[self.cluster_of(pos)]
+                                                          .get(&pos)
+                                                          .cloned()
+                                                          .unwrap_or_else(Vec::new)
+                                                  };
+                                                  res.extend(self.inter(pos).into_iter().map(|next| (next, 1.0)));
+                                                  if let Some(&cost) = goal_edges.get(&pos) {
+                                                      res.push((goal, cost));
+                                                  }
+                                                  res.retain(|&(next, _)| !broken.contains(&(pos, next)));
+                                                  res
+                                              },
+                                              |pos| astar::octile(pos, goal));
+            let (abstract_path, _) = abstract_path?;
+
+            // Refine the path inside each cluster
+            let mut path = vec![start];
+            for pair in abstract_path.windows(2) {
+                let (a, b) = (pair[0], pair[1]);
+                let c = self.cluster_of(a);
+                if c == self.cluster_of(b) {
+                    match local_path(level, self.rect(c), a, b) {
+                        Some((local, _)) => path.extend(local.into_iter().skip(1)),
+                        None => {
+                            broken.insert((a, b));
+                            continue 'search;
+                        },
+                    }
+                } else if level.is_move_possible(a, b) {
+                    path.push(b);
+                } else {
+                    broken.insert((a, b));
+                    continue 'search;
+                }
+            }
+            return Some(path);
+        }
+    }
+}
+
+/// Returns the entrances along a border, given the pairs of adjacent tiles
+/// on each side of it
+fn entrances<FT, WT, I>(pairs: I, level: &Level<FT, WT>) -> Vec<Entrance>
+    where FT: Default + Clone,
+          WT: Wall,
+          I: Iterator<Item = Entrance> {
+    let mut res = vec![];
+    let mut opening: Vec<Entrance> = vec![];
+    for (a, b) in pairs {
+        if level.is_move_possible(a, b) && level.is_move_possible(b, a) {
+            opening.push((a, b));
+        } else {
+            add_entrances(&mut res, &opening);
+            opening.clear();
+        }
+    }
+    add_entrances(&mut res, &opening);
+    res
+}
+
+/// Adds the entrances of an opening along a border
+fn add_entrances(res: &mut Vec<Entrance>, opening: &[Entrance]) {
+    if opening.len() >= WIDE_ENTRANCE {
+        res.push(opening[0]);
+        res.push(opening[opening.len() - 1]);
+    } else if !opening.is_empty() {
+        res.push(opening[opening.len() / 2]);
+    }
+}
+
+/// A* restricted to the tiles of a cluster
+fn local_path<FT, WT>(level: &Level<FT, WT>,
+                      rect: Rect,
+                      start: (usize, usize),
+                      goal: (usize, usize)) -> Option<(Vec<(usize, usize)>, f32)>
+    where FT: Default + Clone,
+          WT: Wall {
+    astar::search(start, goal,
+                  |pos| {
+                      level.neighbours(pos)
+                          .into_iter()
+                          .filter(|&(x, y)| rect.contains(x, y))
+                          .map(|next| (next, astar::octile(pos, next)))
+                          .collect()
+                  },
+                  |pos| astar::octile(pos, goal))
+}
+
+
+#[test]
+fn hpa_paths() {
+    use wall::WallPosition;
+
+    let mut level: Level = Level::new(40, 30, 0.0);
+    // A long wall with a door
+    for y in 0..30 {
+        if y != 25 {
+            level.set_wall(17, y, WallPosition::Right, Some(()));
+        }
+    }
+    let mut graph = HpaGraph::new(&level, 8);
+    let path = graph.find_path(&level, (2, 2), (37, 3)).unwrap();
+    assert_eq!(path.first(), Some(&(2, 2)));
+    assert_eq!(path.last(), Some(&(37, 3)));
+    // Through the door
+    assert!(path.iter().any(|&(_, y)| y >= 24));
+    for pair in path.windows(2) {
+        assert!(level.is_move_possible(pair[0], pair[1]));
+    }
+    // Not much longer than the shortest path
+    let best = level.find_path((2, 2), (37, 3)).unwrap();
+    assert!(path.len() <= best.len() * 3 / 2);
+
+    // Paths inside a cluster, and from an entrance
+    assert_eq!(graph.find_path(&level, (1, 1), (3, 1)), Some(vec![(1, 1), (2, 1), (3, 1)]));
+    let entrance = graph.entrances()[0].0;
+    assert!(graph.find_path(&level, entrance, (39, 29)).is_some());
+    assert_eq!(graph.find_path(&level, (0, 0), (40, 0)), None);
+
+    // Close the door, then open another one
+    level.set_wall(17, 25, WallPosition::Right, Some(()));
+    graph.update(&level, Rect::new(17, 25, 1, 1));
+    assert_eq!(graph, HpaGraph::new(&level, 8));
+    assert_eq!(graph.find_path(&level, (2, 2), (37, 3)), None);
+
+    level.set_wall(17, 3, WallPosition::Right, None);
+    graph.update(&level, Rect::new(17, 3, 1, 1));
+    assert_eq!(graph, HpaGraph::new(&level, 8));
+    // Through the new door
+    assert!(graph.find_path(&level, (2, 2), (37, 3)).unwrap().iter().all(|&(_, y)| y < 10));
+}
+
+#[test]
+fn hpa_outdated() {
+    use wall::WallPosition;
+
+    let mut level: Level = Level::new(20, 10, 0.0);
+    let graph = HpaGraph::new(&level, 5);
+    // Walls across the level, one inside clusters and one on a border (where
+    // there was an entrance), with a single door each, and no update
+    for y in 0..10 {
+        if y != 8 {
+            level.set_wall(7, y, WallPosition::Right, Some(()));
+        }
+        if y != 2 {
+            level.set_wall(14, y, WallPosition::Right, Some(()));
+        }
+    }
+    let path = graph.find_path(&level, (0, 0), (19, 0)).unwrap();
+    assert_eq!(path.last(), Some(&(19, 0)));
+    for pair in path.windows(2) {
+        assert!(level.is_move_possible(pair[0], pair[1]));
+    }
+
+    // No way at all
+    level.set_wall(7, 8, WallPosition::Right, Some(()));
+    assert_eq!(graph.find_path(&level, (0, 0), (19, 0)), None);
+}
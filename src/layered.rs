@@ -0,0 +1,313 @@
+// (C) 2017, Élisabeth Henry
+//
+// Licensed under either of
+// 
+// Apache License, Version 2.0: http://www.apache.org/licenses/LICENSE-2.0
+// MIT license: http://opensource.org/licenses/MIT
+// at your option.
+//
+// Unless you explicitly state otherwise, any contribution intentionally submitted
+// for inclusion in the work by you, as defined in the Apache-2.0 license, shall be
+// dual licensed as above, without any additional terms or conditions.
+
+use level::Level;
+use wall::Wall;
+use astar;
+
+use std::default::Default;
+
+/// Kind of connector between two layers
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ConnectorKind {
+    /// Stairs
+    Stairs,
+    /// Ladder
+    Ladder,
+    /// Ramp
+    Ramp,
+}
+
+/// Connects a tile of a layer to a tile of another layer.
+///
+/// Positions are given as (x, y, layer). Connectors can be used both ways.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Connector {
+    /// Kind of the connector
+    pub kind: ConnectorKind,
+    /// One end of the connector
+    pub from: (usize, usize, usize),
+    /// The other end of the connector
+    pub to: (usize, usize, usize),
+}
+
+impl Connector {
+    /// Returns the other end of the connector if `pos` is one of its ends
+    fn other_end(&self, pos: (usize, usize, usize)) -> Option<(usize, usize, usize)> {
+        if self.from == pos {
+            Some(self.to)
+        } else if self.to == pos {
+            Some(self.from)
+        } else {
+            None
+        }
+    }
+}
+
+/// A level with multiple storeys.
+///
+/// Each layer is a `Level` with the same dimensions, stacked on top of each
+/// other (layer 0 is the ground). Heights of each layer are still absolute, so
+/// an upper floor must have a higher z than the layer below. Tiles of a layer
+/// can have no floor at all (e.g. outside of a building on the first floor),
+/// and layers are linked together by connectors (stairs, ladders, ramps).
+///
+/// Positions are given as (x, y, layer).
+///
+/// # Example
+///
+/// ```
+/// use isometric::{Level, LayeredLevel, ConnectorKind};
+///
+/// let ground: Level = Level::new(10, 10, 0.0);
+/// let mut level = LayeredLevel::new(ground);
+/// let first = level.add_layer(Level::new(10, 10, 2.0));
+///
+/// // Not possible to go upstairs yet
+/// assert!(level.find_path((0, 0, 0), (5, 5, first)).is_none());
+///
+/// level.add_connector(ConnectorKind::Stairs, (2, 2, 0), (2, 3, first));
+/// assert!(level.is_move_possible((2, 2, 0), (2, 3, first)));
+/// assert!(level.find_path((0, 0, 0), (5, 5, first)).is_some());
+/// ```
+#[derive(Debug)]
+pub struct LayeredLevel<FT=(), WT=()> {
+    layers: Vec<Level<FT, WT>>,
+    floors: Vec<Vec<bool>>,
+    connectors: Vec<Connector>,
+}
+
+impl<FT: Default + Clone,
+     WT: Wall> LayeredLevel<FT, WT> {
+    /// Creates a new layered level, with only one layer (the ground)
+    pub fn new(ground: Level<FT, WT>) -> LayeredLevel<FT, WT> {
+        let floors = vec![vec![true; ground.width() * ground.depth()]];
+        LayeredLevel {
+            layers: vec![ground],
+            floors,
+            connectors: vec![],
+        }
+    }
+
+    /// Returns the width of the layers
+    pub fn width(&self) -> usize {
+        self.layers[0].width()
+    }
+
+    /// Returns the depth of the layers
+    pub fn depth(&self) -> usize {
+        self.layers[0].depth()
+    }
+
+    /// Adds a layer above the existing ones and returns its index.
+    ///
+    /// The new layer must have the same dimensions as the ground. All its tiles
+    /// have a floor; use `set_has_floor` to remove them.
+    pub fn add_layer(&mut self, level: Level<FT, WT>) -> usize {
+        debug_assert!(level.width() == self.width() && level.depth() == self.depth(),
+                      "all layers must have the same dimensions");
+        self.floors.push(vec![true; level.width() * level.depth()]);
+        self.layers.push(level);
+        self.layers.len() - 1
+    }
+
+    /// Returns the number of layers
+    pub fn layer_count(&self) -> usize {
+        self.layers.len()
+    }
+
+    /// Get access to a layer
+    pub fn layer(&self, layer: usize) -> &Level<FT, WT> {
+        &self.layers[layer]
+    }
+
+    /// Get mutable access to a layer
+    pub fn layer_mut(&mut self, layer: usize) -> &mut Level<FT, WT> {
+        &mut self.layers[layer]
+    }
+
+    /// Returns true if there is a floor at this position
+    pub fn has_floor(&self, x: usize, y: usize, layer: usize) -> bool {
+        debug_assert!(x < self.width() && y < self.depth(), "x and y must be in level's bounds");
+        self.floors[layer][y * self.width() + x]
+    }
+
+    /// Sets whether there is a floor at this position.
+    ///
+    /// Tiles without a floor can't be walked upon and aren't rendered.
+    pub fn set_has_floor(&mut self, x: usize, y: usize, layer: usize, floor: bool) -> &mut Self {
+        debug_assert!(x < self.width() && y < self.depth(), "x and y must be in level's bounds");
+        let width = self.width();
+        self.floors[layer][y * width + x] = floor;
+        self
+    }
+
+    /// Adds a connector between two positions
+    pub fn add_connector(&mut self, kind: ConnectorKind,
+                         from: (usize, usize, usize),
+                         to: (usize, usize, usize)) -> &mut Self {
+        debug_assert!(from.2 < self.layers.len() && to.2 < self.layers.len(), "layers must exist");
+        self.connectors.push(Connector {
+            kind,
+            from,
+            to,
+        });
+        self
+    }
+
+    /// Returns the connectors between layers
+    pub fn connectors(&self) -> &[Connector] {
+        &self.connectors
+    }
+
+    /// Returns true if character move is possible, false else
+    ///
+    /// A move is possible if:
+    /// * both positions are on the same layer, there is a floor at end position
+    ///   and the move is possible on this layer (see `Level::is_move_possible`);
+    ///   diagonal moves also need a floor on one of the two tiles beside them
+    /// * or both positions are linked by a connector
+    pub fn is_move_possible(&self,
+                            start_pos: (usize, usize, usize),
+                            end_pos: (usize, usize, usize)) -> bool {
+        if start_pos.2 == end_pos.2 {
+            end_pos.2 < self.layers.len()
+                && self.can_walk(end_pos.2, (start_pos.0, start_pos.1), (end_pos.0, end_pos.1))
+        } else {
+            self.connectors.iter().any(|c| c.other_end(start_pos) == Some(end_pos))
+        }
+    }
+
+    /// Returns true if a unit can walk between two tiles of a layer, without
+    /// crossing the void
+    fn can_walk(&self, layer: usize, start: (usize, usize), end: (usize, usize)) -> bool {
+        let level = &self.layers[layer];
+        if end.0 >= self.width() || end.1 >= self.depth()
+            || !self.has_floor(end.0, end.1, layer)
+            || !level.is_move_possible(start, end) {
+            return false;
+        }
+        if start.0 == end.0 || start.1 == end.1 {
+            return true;
+        }
+        // Diagonal: one of the tiles beside it must be walkable too
+        [(end.0, start.1), (start.0, end.1)].iter().any(|&side| {
+            self.has_floor(side.0, side.1, layer)
+                && level.is_move_possible(start, side)
+                && level.is_move_possible(side, end)
+        })
+    }
+
+    /// Returns the positions that can be reached from `pos` in one move,
+    /// including through connectors
+    pub fn neighbours(&self, pos: (usize, usize, usize)) -> Vec<(usize, usize, usize)> {
+        let mut res: Vec<_> = self.layers[pos.2]
+            .neighbours((pos.0, pos.1))
+            .into_iter()
+            .filter(|&next| self.can_walk(pos.2, (pos.0, pos.1), next))
+            .map(|(x, y)| (x, y, pos.2))
+            .collect();
+        for connector in &self.connectors {
+            if let Some(other) = connector.other_end(pos) {
+                res.push(other);
+            }
+        }
+        res
+    }
+
+    /// Returns the shortest path between two positions (including both of
+    /// them), or `None` if there isn't any.
+    ///
+    /// Taking a connector costs the horizontal distance between its two ends,
+    /// or 1 if they are above each other.
+    pub fn find_path(&self,
+                     start: (usize, usize, usize),
+                     goal: (usize, usize, usize)) -> Option<Vec<(usize, usize, usize)>> {
+        for &(x, y, layer) in &[start, goal] {
+            if layer >= self.layers.len() || x >= self.width() || y >= self.depth()
+                || !self.has_floor(x, y, layer) {
+                return None;
+            }
+        }
+        astar::search(start, goal,
+                      |pos| {
+                          self.neighbours(pos)
+                              .into_iter()
+                              .map(|next| {
+                                  let cost = astar::octile((pos.0, pos.1), (next.0, next.1));
+                                  (next, if cost < 1.0 { 1.0 } else { cost })
+                              })
+                              .collect()
+                      },
+                      |pos| astar::octile((pos.0, pos.1), (goal.0, goal.1)))
+            .map(|(path, _)| path)
+    }
+}
+
+
+#[test]
+fn layered_floors() {
+    let mut level: LayeredLevel = LayeredLevel::new(Level::new(10, 10, 0.0));
+    let first = level.add_layer(Level::new(10, 10, 2.0));
+    for x in 0..10 {
+        for y in 5..10 {
+            level.set_has_floor(x, y, first, false);
+        }
+    }
+    assert!(level.is_move_possible((0, 3, first), (0, 4, first)));
+    assert!(!level.is_move_possible((0, 4, first), (0, 5, first)));
+    assert!(level.is_move_possible((0, 4, 0), (0, 5, 0)));
+    assert!(!level.is_move_possible((0, 4, 0), (0, 4, first)));
+}
+
+#[test]
+fn layered_paths() {
+    let mut level: LayeredLevel = LayeredLevel::new(Level::new(10, 10, 0.0));
+    let first = level.add_layer(Level::new(10, 10, 2.0));
+    let second = level.add_layer(Level::new(10, 10, 4.0));
+    level.add_connector(ConnectorKind::Stairs, (0, 0, 0), (0, 2, first));
+    level.add_connector(ConnectorKind::Ladder, (9, 9, first), (9, 9, second));
+
+    let path = level.find_path((5, 5, 0), (0, 0, second)).unwrap();
+    assert_eq!(path[0], (5, 5, 0));
+    assert_eq!(path[path.len() - 1], (0, 0, second));
+    assert!(path.contains(&(0, 2, first)));
+    assert!(path.contains(&(9, 9, second)));
+
+    // Connectors work both ways
+    let path = level.find_path((0, 0, second), (5, 5, 0)).unwrap();
+    assert!(path.contains(&(0, 0, 0)));
+}
+
+#[test]
+fn layered_void() {
+    let mut level: LayeredLevel = LayeredLevel::new(Level::new(3, 3, 0.0));
+    let first = level.add_layer(Level::new(3, 3, 2.0));
+    for x in 0..3 {
+        for y in 0..3 {
+            level.set_has_floor(x, y, first, false);
+        }
+    }
+    level.set_has_floor(0, 0, first, true);
+    level.set_has_floor(1, 1, first, true);
+
+    // Diagonal moves can't cross the void
+    assert!(!level.is_move_possible((0, 0, first), (1, 1, first)));
+    assert_eq!(level.find_path((0, 0, first), (1, 1, first)), None);
+    level.set_has_floor(1, 0, first, true);
+    assert!(level.is_move_possible((0, 0, first), (1, 1, first)));
+    assert_eq!(level.find_path((0, 0, first), (1, 1, first)), Some(vec![(0, 0, first), (1, 1, first)]));
+
+    // Nor start from it
+    assert_eq!(level.find_path((2, 2, first), (1, 1, first)), None);
+    assert!(level.find_path((2, 2, 0), (0, 0, 0)).is_some());
+}
@@ -12,13 +12,29 @@
 
 use wall::Wall;
 use wall::WallPosition;
+use bridge::Bridge;
+use bridge::Surface;
+use bridge::BridgeAxis;
+use shape::TileShape;
+use rect::Rect;
+use rect::Anchor;
+use astar;
+use dijkstra;
+use dijkstra::DijkstraMap;
+use flow::FlowField;
+use analysis;
+use analysis::Regions;
+use analysis::Chokepoints;
+use analysis::DetectedRoom;
+use visibility;
 
+use std::cmp;
 use std::default::Default;
-use std::f32;
 
 /// Represents a level.
 ///
-/// Contains the floor, walls, and objects.
+/// Contains the floor and walls. Objects standing on the level are stored
+/// separately, see `Entities`.
 #[derive(Debug)]
 pub struct Level<FT=(),
                  WT=()> {
@@ -28,6 +44,10 @@ pub struct Level<FT=(),
     floor_data: Vec<FT>,
     walls_h: Vec<Option<WT>>,
     walls_v: Vec<Option<WT>>,
+    bridges: Vec<Option<Bridge>>,
+    shapes: Vec<TileShape>,
+    water: Vec<f32>,
+    deep_water: Option<f32>,
 }
 
 impl<FT:Default+Clone,
@@ -69,6 +89,10 @@ impl<FT:Default+Clone,
             walls_h: vec![None; (depth + 1) * width],
             walls_v: vec![None; (width  + 1) * depth],
             floor_data: vec![FT::default() ; width * depth],
+            bridges: vec![None; width * depth],
+            shapes: vec![TileShape::Smooth; width * depth],
+            water: vec![0.0; width * depth],
+            deep_water: None,
         }
     }
 
@@ -103,23 +127,267 @@ impl<FT:Default+Clone,
         self
     }
 
-    /// Returns the height of a corner.
+    /// Returns the depth of water on a tile (0.0 if it is dry)
     ///
-    /// A tile has four corners (yeah): (x, y) to (x+1, y +1). Therefore,
-    /// the indice of corner can go one step further than the tile's index
-    /// (max is width and depth instead of width - 1 and depth - 1).
+    /// x must be strictly less than level's width and
+    /// y must be strictly less than level's height
+    pub fn water(&self, x: usize, y: usize) -> f32 {
+        debug_assert!(x < self.width && y < self.depth, "x and y must be in level's bounds");
+        let i = self.get_index(x, y);
+        self.water[i]
+    }
+
+    /// Sets the depth of water on a tile. The surface of the water is at
+    /// `z + depth`.
     ///
-    /// A corner's height is averaged from the neighboring tiles (if any),
-    /// except if said tiles have a wall between them.
-    // pub fn z_corner(&mut self, x: usize, y: usize) -> f32 {
-    //         debug_assert!(x <= self.width && y <= self.depth, "x and y must be in level's bounds");
-    //     let mut z = 0.0;
-    //     let mut div_z = 0.0;
-        
-        
-    //     return z / div_z;
-    // }
+    /// # Example
+    ///
+    /// ```
+    /// use isometric::Level;
+    ///
+    /// let mut level: Level = Level::new(10, 10, 1.0);
+    /// level.set_water(3, 3, 0.5);
+    /// assert_eq!(level.water_z(3, 3), Some(1.5));
+    /// assert_eq!(level.water_z(4, 3), None);
+    /// ```
+    pub fn set_water(&mut self, x: usize, y: usize, depth: f32) -> &mut Self {
+        debug_assert!(x < self.width && y < self.depth, "x and y must be in level's bounds");
+        debug_assert!(depth >= 0.0, "water depth can't be negative");
+        let i = self.get_index(x, y);
+        self.water[i] = depth;
+        self
+    }
+
+    /// Returns the height of the water surface on a tile, if there is water
+    pub fn water_z(&self, x: usize, y: usize) -> Option<f32> {
+        let depth = self.water(x, y);
+        if depth > 0.0 {
+            Some(self.z(x, y) + depth)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the depth from which water can't be walked through, if any
+    pub fn deep_water(&self) -> Option<f32> {
+        self.deep_water
+    }
+
+    /// Sets the depth from which water can't be walked through (see
+    /// `is_move_possible`). If `None` (the default), water never blocks moves.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use isometric::Level;
+    ///
+    /// let mut level: Level = Level::new(10, 10, 0.0);
+    /// level.set_water(3, 3, 2.0);
+    /// assert_eq!(level.is_move_possible((2, 3), (3, 3)), true);
+    ///
+    /// level.set_deep_water(Some(1.0));
+    /// assert_eq!(level.is_move_possible((2, 3), (3, 3)), false);
+    /// // Getting out is always possible
+    /// assert_eq!(level.is_move_possible((3, 3), (2, 3)), true);
+    /// ```
+    pub fn set_deep_water(&mut self, depth: Option<f32>) -> &mut Self {
+        self.deep_water = depth;
+        self
+    }
+
+    /// Returns true if water on a tile is too deep to walk through
+    pub fn is_deep_water(&self, x: usize, y: usize) -> bool {
+        self.deep_water.map_or(false, |depth| self.water(x, y) >= depth)
+    }
+
+    /// Returns the bridge (if any) above a tile
+    ///
+    /// x must be strictly less than level's width and
+    /// y must be strictly less than level's height
+    pub fn bridge(&self, x: usize, y: usize) -> Option<Bridge> {
+        debug_assert!(x < self.width && y < self.depth, "x and y must be in level's bounds");
+        let i = self.get_index(x, y);
+        self.bridges[i]
+    }
+
+    /// Sets the bridge above a tile. To remove it, set it to `None`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use isometric::{Level, Bridge, BridgeAxis, Surface};
+    ///
+    /// let mut level: Level = Level::new(10, 10, 2.0);
+    /// // A chasm from x = 3 to x = 5, crossed by a bridge
+    /// for x in 3..6 {
+    ///     level.set_z(x, 5, 0.0);
+    ///     level.set_bridge(x, 5, Some(Bridge::new(2.0, BridgeAxis::X)));
+    /// }
+    ///
+    /// // Bridge can be entered at its ends...
+    /// assert!(level.is_surface_move_possible((2, 5, Surface::Ground), (3, 5, Surface::Bridge)));
+    /// // ...but not from its sides
+    /// assert!(!level.is_surface_move_possible((4, 4, Surface::Ground), (4, 5, Surface::Bridge)));
+    /// // and units can still walk underneath
+    /// assert!(level.is_surface_move_possible((4, 4, Surface::Ground), (4, 5, Surface::Ground)));
+    /// ```
+    pub fn set_bridge(&mut self, x: usize, y: usize, bridge: Option<Bridge>) -> &mut Self {
+        debug_assert!(x < self.width && y < self.depth, "x and y must be in level's bounds");
+        let i = self.get_index(x, y);
+        self.bridges[i] = bridge;
+        self
+    }
+
+    /// Returns the height of a surface of a tile.
+    ///
+    /// If there is no bridge above this tile, returns the floor's height.
+    pub fn surface_z(&self, x: usize, y: usize, surface: Surface) -> f32 {
+        match (surface, self.bridge(x, y)) {
+            (Surface::Bridge, Some(bridge)) => bridge.z,
+            _ => self.z(x, y),
+        }
+    }
+
+    /// Returns the shape of a tile's floor
+    ///
+    /// x must be strictly less than level's width and
+    /// y must be strictly less than level's height
+    pub fn shape(&self, x: usize, y: usize) -> TileShape {
+        debug_assert!(x < self.width && y < self.depth, "x and y must be in level's bounds");
+        let i = self.get_index(x, y);
+        self.shapes[i]
+    }
+
+    /// Sets the shape of a tile's floor
+    ///
+    /// x must be strictly less than level's width and
+    /// y must be strictly less than level's height
+    pub fn set_shape(&mut self, x: usize, y: usize, shape: TileShape) -> &mut Self {
+        debug_assert!(x < self.width && y < self.depth, "x and y must be in level's bounds");
+        let i = self.get_index(x, y);
+        self.shapes[i] = shape;
+        self
+    }
+
+    /// Returns the height of the corners of a tile that doesn't have a
+    /// `Smooth` shape
+    fn explicit_corner_heights(&self, x: usize, y: usize) -> Option<[f32; 4]> {
+        self.shape(x, y).corner_offsets().map(|offsets| {
+            let z = self.z(x, y);
+            [z + offsets[0], z + offsets[1], z + offsets[2], z + offsets[3]]
+        })
+    }
+
+    /// Returns the height of the four corners of a tile, in this order:
+    /// (x, y), (x + 1, y), (x, y + 1) and (x + 1, y + 1) (see `Corner`).
+    ///
+    /// If the tile has an explicit shape, corners' heights are entirely
+    /// determined by it. Else, a corner's height is averaged from the
+    /// neighbouring tiles sharing this corner, except if said tiles have a wall
+    /// between them; if one of these tiles has an explicit shape, its own
+    /// height for this corner is used instead, so there is no gap between them.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use isometric::{Level, TileShape, WallPosition};
+    ///
+    /// let mut level: Level = Level::new(10, 10, 0.0);
+    /// level.set_z(5, 5, 1.0);
+    /// assert_eq!(level.corner_heights(4, 5), [0.0, 0.25, 0.0, 0.25]);
+    ///
+    /// level.set_shape(4, 5, TileShape::Ramp(WallPosition::Right, 1.0));
+    /// assert_eq!(level.corner_heights(4, 5), [0.0, 1.0, 0.0, 1.0]);
+    /// ```
+    pub fn corner_heights(&self, x: usize, y: usize) -> [f32; 4] {
+        debug_assert!(x < self.width && y < self.depth, "x and y must be in level's bounds");
+        if let Some(heights) = self.explicit_corner_heights(x, y) {
+            return heights;
+        }
+        let z = self.z(x, y);
+        let mut res = [z; 4];
+        // Direction of each corner from the center of the tile
+        let directions: [(isize, isize); 4] = [(-1, -1), (1, -1), (-1, 1), (1, 1)];
+        for (corner, &(dx, dy)) in directions.iter().enumerate() {
+            let mut sum = z;
+            let mut div = 1.0;
+            let mut snapped = None;
+            for &(dx, dy) in &[(dx, 0), (0, dy), (dx, dy)] {
+                let other_x = x as isize + dx;
+                let other_y = y as isize + dy;
+                if other_x < 0 || other_y < 0 {
+                    continue;
+                }
+                let other = (other_x as usize, other_y as usize);
+                if !self.is_open((x, y), other) {
+                    continue;
+                }
+                match self.explicit_corner_heights(other.0, other.1) {
+                    Some(heights) => {
+                        // Same corner, seen from the other tile
+                        let i = (corner as isize - dx - 2 * dy) as usize;
+                        snapped = Some(heights[i]);
+                    },
+                    None => {
+                        sum += self.z(other.0, other.1);
+                        div += 1.0;
+                    }
+                }
+            }
+            res[corner] = snapped.unwrap_or(sum / div);
+        }
+        res
+    }
 
+    /// Returns the height of the floor at any point of the level, following
+    /// the two triangles each tile is drawn with (see `corner_heights`), so
+    /// that objects placed there stand on the floor.
+    ///
+    /// Tile (x, y) covers the points from (x, y) to (x + 1, y + 1); points
+    /// outside the level are clamped to its bounds.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use isometric::{Level, TileShape, WallPosition};
+    ///
+    /// let mut level: Level = Level::new(10, 10, 0.0);
+    /// level.set_shape(4, 5, TileShape::Ramp(WallPosition::Right, 1.0));
+    /// assert_eq!(level.z_at(4.5, 5.5), 0.5);
+    /// assert_eq!(level.z_at(4.75, 5.2), 0.75);
+    /// ```
+    pub fn z_at(&self, x: f32, y: f32) -> f32 {
+        let x = x.max(0.0).min(self.width as f32);
+        let y = y.max(0.0).min(self.depth as f32);
+        let tile_x = cmp::min(x as usize, self.width - 1);
+        let tile_y = cmp::min(y as usize, self.depth - 1);
+        let (u, v) = (x - tile_x as f32, y - tile_y as f32);
+        let h = self.corner_heights(tile_x, tile_y);
+        if u + v <= 1.0 {
+            h[0] + u * (h[1] - h[0]) + v * (h[2] - h[0])
+        } else {
+            h[3] + (1.0 - u) * (h[2] - h[3]) + (1.0 - v) * (h[1] - h[3])
+        }
+    }
+
+    /// Returns the height of the two ends of the edge of a tile at the given
+    /// side, used to check if the edge is a cliff.
+    ///
+    /// For `Smooth` tiles, this is simply the tile's height.
+    fn edge_heights(&self, x: usize, y: usize, side: WallPosition) -> [f32; 2] {
+        match self.explicit_corner_heights(x, y) {
+            Some(h) => match side {
+                WallPosition::Bottom => [h[0], h[1]],
+                WallPosition::Top => [h[2], h[3]],
+                WallPosition::Left => [h[0], h[2]],
+                WallPosition::Right => [h[1], h[3]],
+            },
+            None => {
+                let z = self.z(x, y);
+                [z, z]
+            }
+        }
+    }
 
     /// Returns the wall's data (if any) at a tile's position or None if there isn't.
     ///
@@ -169,32 +437,134 @@ impl<FT:Default+Clone,
 
     /// Add walls between two tiles if the height (z) difference between
     /// the two is superior or equal to the given threshold.
+    ///
+    /// For tiles with an explicit shape, the heights of their common edge are
+    /// compared instead, so a ramp leading to a higher tile doesn't get a wall.
     pub fn add_cliff_walls(&mut self, threshold: f32, data: WT) {
         for x in 0..(self.width - 1) {
             for y in 0..(self.depth - 1) {
-                let z = self.z(x, y);
-                let z_right = self.z(x + 1, y);
-                let z_top = self.z(x, y + 1);
+                let is_cliff = |a: [f32; 2], b: [f32; 2]| {
+                    (a[0] - b[0]).abs() >= threshold || (a[1] - b[1]).abs() >= threshold
+                };
+                let top = is_cliff(self.edge_heights(x, y, WallPosition::Top),
+                                   self.edge_heights(x, y + 1, WallPosition::Bottom));
+                let right = is_cliff(self.edge_heights(x, y, WallPosition::Right),
+                                     self.edge_heights(x + 1, y, WallPosition::Left));
 
-                if (z - z_top).abs() >= threshold {
+                if top {
                     self.set_wall(x, y, WallPosition::Top, Some(data.clone()));
                 }
-                if (z - z_right).abs() >= threshold {
+                if right {
                     self.set_wall(x, y, WallPosition::Right, Some(data.clone()));
                 }
             }
         }
     }
 
+    /// Returns the ambient occlusion of a corner, between 0.0 (none) and 1.0.
+    ///
+    /// A tile has four corners, from (x, y) to (x + 1, y + 1), so corner indices
+    /// go up to `width` and `depth`.
+    /// `z` is the height of the vertex at this corner: neighbouring tiles higher
+    /// than it occlude it, and so do walls meeting at this corner (more so if
+    /// it is the corner of a room). Cliff and border walls are ignored since
+    /// height differences already account for them.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use isometric::{Level, WallPosition};
+    ///
+    /// let mut level: Level = Level::new(10, 10, 0.0);
+    /// level.set_wall(2, 2, WallPosition::Left, Some(()));
+    /// level.set_wall(2, 2, WallPosition::Bottom, Some(()));
+    ///
+    /// // Corner of the "room" is darker than the middle of its wall
+    /// assert!(level.corner_occlusion(2, 2, 0.0) > level.corner_occlusion(2, 3, 0.0));
+    /// assert_eq!(level.corner_occlusion(6, 6, 0.0), 0.0);
+    /// ```
+    pub fn corner_occlusion(&self, x: usize, y: usize, z: f32) -> f32 {
+        debug_assert!(x <= self.width && y <= self.depth, "x and y must be in level's bounds");
+        let is_occluding = |data: &Option<WT>| {
+            match *data {
+                Some(ref data) => !data.is_cliff() && !data.is_border(),
+                None => false,
+            }
+        };
+
+        // Horizontal walls at line y, on both sides of the corner
+        let mut horizontal = false;
+        for &tile_x in &[x.wrapping_sub(1), x] {
+            if tile_x >= self.width {
+                continue;
+            }
+            let wall = if y < self.depth {
+                self.wall(tile_x, y, WallPosition::Bottom)
+            } else {
+                self.wall(tile_x, y - 1, WallPosition::Top)
+            };
+            horizontal = horizontal || is_occluding(wall);
+        }
+        // Vertical walls at column x
+        let mut vertical = false;
+        for &tile_y in &[y.wrapping_sub(1), y] {
+            if tile_y >= self.depth {
+                continue;
+            }
+            let wall = if x < self.width {
+                self.wall(x, tile_y, WallPosition::Left)
+            } else {
+                self.wall(x - 1, tile_y, WallPosition::Right)
+            };
+            vertical = vertical || is_occluding(wall);
+        }
+
+        let mut occlusion = 0.0;
+        if horizontal {
+            occlusion += 0.4;
+        }
+        if vertical {
+            occlusion += 0.4;
+        }
+
+        // Tiles sharing this corner
+        for &tile_x in &[x.wrapping_sub(1), x] {
+            for &tile_y in &[y.wrapping_sub(1), y] {
+                if tile_x < self.width && tile_y < self.depth {
+                    let dz = self.z(tile_x, tile_y) - z;
+                    if dz > 0.0 {
+                        occlusion += 0.25 * dz.min(1.0);
+                    }
+                }
+            }
+        }
+
+        occlusion.min(1.0)
+    }
+
     /// Returns true if character move is possible, false else
     ///
     /// A move is possible if:
     /// * start position and end position are adjacent
     /// * there is no wall between them
     /// * end position is not outside the level
+    /// * end position is not deep water (see `set_deep_water`)
     pub fn is_move_possible(&self,
                             start_pos: (usize, usize),
                             end_pos: (usize, usize)) -> bool {
+        self.can_cross(start_pos, end_pos, true)
+    }
+
+    /// Same as `is_move_possible`, but only walls block: used for lines of
+    /// sight and for smoothing heights.
+    fn is_open(&self, start_pos: (usize, usize), end_pos: (usize, usize)) -> bool {
+        self.can_cross(start_pos, end_pos, false)
+    }
+
+    fn can_cross(&self,
+                 start_pos: (usize, usize),
+                 end_pos: (usize, usize),
+                 check_water: bool) -> bool {
         if start_pos == end_pos {
             // Trivially true, though useless
             return true;
@@ -215,15 +585,17 @@ impl<FT:Default+Clone,
 
             let intermediate_x = (start_pos.0 as isize + dx) as usize;
             let intermediate = (intermediate_x, start_pos.1);
-            if self.is_move_possible(start_pos, intermediate)
-                && self.is_move_possible(intermediate, end_pos) {
+            if self.can_cross(start_pos, intermediate, check_water)
+                && self.can_cross(intermediate, end_pos, check_water) {
                     true
                 } else {
                     let intermediate_y = (start_pos.1 as isize + dy) as usize;
                     let intermediate = (start_pos.0, intermediate_y);
-                    self.is_move_possible(start_pos, intermediate) && 
-                        self.is_move_possible(intermediate, end_pos)
+                    self.can_cross(start_pos, intermediate, check_water) && 
+                        self.can_cross(intermediate, end_pos, check_water)
                 }
+        } else if check_water && self.is_deep_water(end_pos.0, end_pos.1) {
+            false
         } else {
             match (dx, dy) {
                 (1, 0) => self.wall(start_pos.0, start_pos.1, WallPosition::Right).is_none(),
@@ -235,6 +607,261 @@ impl<FT:Default+Clone,
         }
     }
 
+    /// Returns true if character move between two surfaces is possible, false else
+    ///
+    /// Moves on the ground follow the rules of `is_move_possible`: bridges
+    /// don't prevent units from walking underneath. A bridge can only be
+    /// entered or left at its ends, i.e. from an adjacent tile along its axis
+    /// that doesn't have a bridge itself, and diagonal moves from or to a
+    /// bridge are not possible. Walls are ignored for these moves, since they
+    /// stand below the deck.
+    pub fn is_surface_move_possible(&self,
+                                    start_pos: (usize, usize, Surface),
+                                    end_pos: (usize, usize, Surface)) -> bool {
+        let start = (start_pos.0, start_pos.1);
+        let end = (end_pos.0, end_pos.1);
+        if start_pos.2 == Surface::Ground && end_pos.2 == Surface::Ground {
+            return self.is_move_possible(start, end);
+        }
+        if start == end {
+            return start_pos.2 == end_pos.2;
+        }
+        if end.0 >= self.width || end.1 >= self.depth {
+            return false;
+        }
+
+        let dx: isize = end.0 as isize - start.0 as isize;
+        let dy: isize = end.1 as isize - start.1 as isize;
+        let axis = match (dx, dy) {
+            (1, 0) | (-1, 0) => BridgeAxis::X,
+            (0, 1) | (0, -1) => BridgeAxis::Y,
+            (_, _) => return false,
+        };
+        for &(x, y, surface) in &[start_pos, end_pos] {
+            match (surface, self.bridge(x, y)) {
+                (Surface::Bridge, Some(bridge)) => if bridge.axis != axis {
+                    return false;
+                },
+                (Surface::Bridge, None) => return false,
+                // Can't climb on a bridge from underneath it
+                (Surface::Ground, Some(_)) => return false,
+                (Surface::Ground, None) => (),
+            }
+        }
+        end_pos.2 == Surface::Bridge || !self.is_deep_water(end.0, end.1)
+    }
+
+    /// Returns the tiles that can be reached from `pos` in one move (including
+    /// diagonal moves)
+    pub fn neighbours(&self, pos: (usize, usize)) -> Vec<(usize, usize)> {
+        let mut res = vec![];
+        for dx in -1..2 {
+            for dy in -1..2 {
+                if (dx, dy) == (0, 0) {
+                    continue;
+                }
+                let x = pos.0 as isize + dx;
+                let y = pos.1 as isize + dy;
+                if x < 0 || y < 0 {
+                    continue;
+                }
+                let next = (x as usize, y as usize);
+                if self.is_move_possible(pos, next) {
+                    res.push(next);
+                }
+            }
+        }
+        res
+    }
+
+    /// Returns all the tiles that can be reached from `start` (including it),
+    /// moving from tile to adjacent tile (see `is_move_possible`).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use isometric::{Level, WallPosition};
+    ///
+    /// let mut level: Level = Level::new(10, 10, 0.0);
+    /// for y in 0..10 {
+    ///     level.set_wall(1, y, WallPosition::Right, Some(()));
+    /// }
+    /// assert_eq!(level.flood_fill((0, 0)).len(), 20);
+    /// ```
+    pub fn flood_fill(&self, start: (usize, usize)) -> Vec<(usize, usize)> {
+        debug_assert!(start.0 < self.width && start.1 < self.depth, "start must be in level's bounds");
+        let mut seen = vec![false; self.width * self.depth];
+        let mut res = vec![start];
+        seen[self.get_index(start.0, start.1)] = true;
+        let mut i = 0;
+        while i < res.len() {
+            let (x, y) = res[i];
+            i += 1;
+            let mut next = vec![(x + 1, y), (x, y + 1)];
+            if x > 0 {
+                next.push((x - 1, y));
+            }
+            if y > 0 {
+                next.push((x, y - 1));
+            }
+            for pos in next {
+                if self.is_move_possible((x, y), pos) && !seen[self.get_index(pos.0, pos.1)] {
+                    seen[self.get_index(pos.0, pos.1)] = true;
+                    res.push(pos);
+                }
+            }
+        }
+        res
+    }
+
+    /// Returns the shortest path between two tiles (including both of them), or
+    /// `None` if there isn't any.
+    ///
+    /// Moves follow the same rules as `is_move_possible`; diagonal moves cost
+    /// sqrt(2) while other moves cost 1.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use isometric::{Level, WallPosition};
+    ///
+    /// let mut level: Level = Level::new(10, 10, 0.0);
+    /// for y in 0..9 {
+    ///     level.set_wall(4, y, WallPosition::Right, Some(()));
+    /// }
+    ///
+    /// let path = level.find_path((0, 0), (9, 0)).unwrap();
+    /// assert_eq!(path[0], (0, 0));
+    /// assert_eq!(path[path.len() - 1], (9, 0));
+    ///
+    /// // Path goes through the opening at y = 9
+    /// assert!(path.iter().any(|&(_, y)| y == 9));
+    /// ```
+    pub fn find_path(&self, start: (usize, usize), goal: (usize, usize)) -> Option<Vec<(usize, usize)>> {
+        if start.0 >= self.width || start.1 >= self.depth || goal.0 >= self.width || goal.1 >= self.depth {
+            return None;
+        }
+        astar::search(start, goal,
+                      |pos| {
+                          self.neighbours(pos)
+                              .into_iter()
+                              .map(|next| (next, astar::octile(pos, next)))
+                              .collect()
+                      },
+                      |pos| astar::octile(pos, goal))
+            .map(|(path, _)| path)
+    }
+
+    /// Returns a distance field: for each tile, the cost of the cheapest path
+    /// to the closest of the `sources`, moving as `neighbours` allows.
+    ///
+    /// `cost(from, to)` is the cost of moving between two adjacent tiles; it
+    /// can be infinite to forbid the move. Tiles that can't be reached for at
+    /// most `max_cost` have no value.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use isometric::Level;
+    ///
+    /// let level: Level = Level::new(10, 10, 0.0);
+    /// let cost = |from: (usize, usize), to: (usize, usize)| {
+    ///     if from.0 != to.0 && from.1 != to.1 { 1.5 } else { 1.0 }
+    /// };
+    /// let map = level.dijkstra_map(&[(0, 0), (9, 9)], cost, 5.0);
+    ///
+    /// assert_eq!(map.value(2, 1), Some(2.5));
+    /// assert_eq!(map.value(9, 6), Some(3.0));
+    /// assert_eq!(map.value(5, 5), None);
+    /// assert_eq!(map.descend(&level, (2, 1)), Some((1, 0)));
+    /// ```
+    pub fn dijkstra_map<F>(&self, sources: &[(usize, usize)], cost: F, max_cost: f32) -> DijkstraMap
+        where F: Fn((usize, usize), (usize, usize)) -> f32 {
+        let mut map = DijkstraMap::new(self.width, self.depth);
+        for &(x, y) in sources {
+            map.set_value(x, y, Some(0.0));
+        }
+        dijkstra::scan(self, &mut map, cost, max_cost);
+        map
+    }
+
+    /// Returns a flow field leading to the closest of `goals`, `cost`
+    /// returning the cost of moving between two adjacent tiles.
+    ///
+    /// This is more efficient than `find_path` when many units head to the
+    /// same place. See `FlowField` for an example.
+    pub fn flow_field<F>(&self, goals: &[(usize, usize)], cost: F) -> FlowField
+        where F: Fn((usize, usize), (usize, usize)) -> f32 {
+        FlowField::new(self, goals, cost)
+    }
+
+    /// Labels the connected regions of the level: two tiles are in the same
+    /// region if a unit can walk from one to the other and back.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use isometric::{Level, WallPosition};
+    ///
+    /// let mut level: Level = Level::new(10, 10, 0.0);
+    /// for y in 0..10 {
+    ///     level.set_wall(4, y, WallPosition::Right, Some(()));
+    /// }
+    /// let regions = level.regions();
+    /// assert_eq!(regions.len(), 2);
+    /// assert!(!regions.are_connected((0, 0), (9, 9)));
+    /// assert_eq!(regions.size(regions.label(0, 0)), 50);
+    /// ```
+    pub fn regions(&self) -> Regions {
+        analysis::regions(self)
+    }
+
+    /// Returns the tiles that can't be left nor entered, e.g. tiles surrounded
+    /// by walls or deep water, which usually show a mistake in a level
+    pub fn isolated_tiles(&self) -> Vec<(usize, usize)> {
+        let regions = self.regions();
+        let mut res = vec![];
+        for y in 0..self.depth {
+            for x in 0..self.width {
+                if regions.size(regions.label(x, y)) == 1 {
+                    res.push((x, y));
+                }
+            }
+        }
+        res
+    }
+
+    /// Returns the chokepoints of the level: tiles and sides of tiles that
+    /// would split their region in two if they were blocked.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use isometric::{Level, WallPosition};
+    ///
+    /// // A corridor
+    /// let level: Level = Level::new(5, 1, 0.0);
+    /// let chokepoints = level.chokepoints();
+    /// assert_eq!(chokepoints.tiles, vec![(1, 0), (2, 0), (3, 0)]);
+    /// assert_eq!(chokepoints.edges.len(), 4);
+    /// assert_eq!(chokepoints.edges[0], (0, 0, WallPosition::Right));
+    /// ```
+    pub fn chokepoints(&self) -> Chokepoints {
+        analysis::chokepoints(self)
+    }
+
+    /// Finds the rooms of the level, i.e. areas enclosed by walls, and the
+    /// doorways between them.
+    ///
+    /// A doorway is a one tile wide opening in a line of walls (or between a
+    /// wall and the level's border). Rooms are the areas between doorways,
+    /// so corridors are rooms too, and a room with a wider opening is merged
+    /// with the area on the other side. Rooms are ordered by their bottom
+    /// left tile.
+    pub fn detect_rooms(&self) -> Vec<DetectedRoom> {
+        analysis::detect_rooms(self)
+    }
+
     /// Convenience method wrapping `visible_from`, returning a closure instead of
     /// a vector.
     ///
@@ -276,63 +903,95 @@ impl<FT:Default+Clone,
     ///
     /// Center ((0,0) in relative position compared to pos) is at index `radius`
     pub fn visible_from(&self, pos: (usize, usize), radius: usize) -> Vec<Vec<bool>> {
-        let mut res = vec![];
-        for _ in 0..(2*radius + 1) {
-            res.push(vec![false; 2 * radius + 1]);
-        }
-        // Special case of tile of the POV
-        res[radius][radius] = true;
-
-        let steps = 10 * radius;
-        let dtheta: f32 = 2.0 * f32::consts::PI / (steps as f32);
-        let two_pies = 2.0 * f32::consts::PI;
-
-        let mut theta: f32 = 0.0;
-
-        while theta <= two_pies {
-            let dx = theta.cos();
-            let dy = theta.sin();
-
-            let max_dist = (radius * radius) as f32;
-            let mut x = 0.0;
-            let mut y = 0.0;
-            let mut prev_x = pos.0;
-            let mut prev_y = pos.1;
-
-            while x * x + y * y  < max_dist {
-                x += dx;
-                y += dy;
-                let abs_x = (x + pos.0 as f32).round();
-                let abs_y = (y + pos.1 as f32).round();
-                if abs_x < 0.0 || abs_y < 0.0 {
-                    continue;
-                }
-                let abs_x = abs_x as usize;
-                let abs_y = abs_y as usize;
-                    
-                if abs_x == prev_x && abs_y == prev_y {
-                    // nothing to do, we already computed this tile
+        self.visible_from_with(pos, radius, &|start, end| self.is_open(start, end))
+    }
+
+    /// Convenience method similar to `visibility`, taking bridges into account.
+    ///
+    /// `pos` is the position of the viewer and the surface it stands on, and
+    /// the returned closure tells whether a surface of a tile is visible. A
+    /// bridge's deck is an occluder: viewers that are at least as high as the
+    /// deck see it but not the floor underneath, while viewers below it only
+    /// see the floor. Lines of sight that cross a bridge that isn't above the
+    /// viewer ignore the walls below it (e.g. the cliff walls of a chasm).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use isometric::{Level, Bridge, BridgeAxis, Surface};
+    ///
+    /// let mut level: Level = Level::new(20, 20, 0.0);
+    /// level.set_bridge(5, 6, Some(Bridge::new(2.0, BridgeAxis::X)));
+    ///
+    /// let f = level.surface_visibility((5, 5, Surface::Ground), 3);
+    /// assert_eq!(f(5, 6, Surface::Ground), true);
+    /// assert_eq!(f(5, 6, Surface::Bridge), false);
+    ///
+    /// let f = level.surface_visibility((5, 6, Surface::Bridge), 3);
+    /// assert_eq!(f(5, 6, Surface::Ground), false);
+    /// assert_eq!(f(5, 6, Surface::Bridge), true);
+    /// ```
+    pub fn surface_visibility(&self,
+                              pos: (usize, usize, Surface),
+                              radius: usize) -> Box<dyn Fn(usize, usize, Surface) -> bool> {
+        let z = self.surface_z(pos.0, pos.1, pos.2);
+        let is_below = |(x, y): (usize, usize)| {
+            x < self.width && y < self.depth
+                && self.bridge(x, y).map_or(false, |bridge| bridge.z <= z)
+        };
+        let matrix = self.visible_from_with((pos.0, pos.1), radius, &|start, end| {
+            self.is_open(start, end) || is_below(start) || is_below(end)
+        });
+
+        let mut ground = vec![vec![false; 2 * radius + 1]; 2 * radius + 1];
+        let mut deck = ground.clone();
+        for (i, column) in matrix.iter().enumerate() {
+            for (j, &visible) in column.iter().enumerate() {
+                let x = pos.0 as isize + i as isize - radius as isize;
+                let y = pos.1 as isize + j as isize - radius as isize;
+                if !visible || x < 0 || y < 0 || x >= self.width as isize || y >= self.depth as isize {
                     continue;
                 }
-                if self.is_move_possible((prev_x, prev_y), (abs_x, abs_y)) {
-                    let i = (x as f32).round() as isize + radius as isize;
-                    let j = (y as f32).round() as isize + radius as isize;
-                    if i < 0 || i >= 2 * (radius as isize) + 1 {
-                        continue;
-                    }
-                    if j < 0 || j >= 2 * (radius as isize) + 1 {
-                        continue;
-                    }
-                    res[i as usize][j as usize] = true;
-                    prev_x = abs_x;
-                    prev_y = abs_y;
-                } else {
-                    break;
+                match self.bridge(x as usize, y as usize) {
+                    Some(ref bridge) if bridge.z <= z => deck[i][j] = true,
+                    _ => ground[i][j] = true,
                 }
             }
-            theta += dtheta;
         }
-        res
+
+        Box::new(move |x, y, surface| {
+            let i = x as isize - pos.0 as isize + radius as isize;
+            let j = y as isize - pos.1 as isize + radius as isize;
+            if i < 0 || j < 0 || i > 2 * radius as isize || j > 2 * radius as isize {
+                false
+            } else {
+                match surface {
+                    Surface::Ground => ground[i as usize][j as usize],
+                    Surface::Bridge => deck[i as usize][j as usize],
+                }
+            }
+        })
+    }
+
+    /// Computes a "visibility" matrix (see `visible_from`), where `can_see`
+    /// tells whether the line of sight can go from a tile to an adjacent one.
+    fn visible_from_with(&self,
+                         pos: (usize, usize),
+                         radius: usize,
+                         can_see: &dyn Fn((usize, usize), (usize, usize)) -> bool) -> Vec<Vec<bool>> {
+        let to_usize = |(x, y): (isize, isize)| {
+            if x < 0 || y < 0 {
+                None
+            } else {
+                Some((x as usize, y as usize))
+            }
+        };
+        visibility::cast((pos.0 as isize, pos.1 as isize), radius, &|start, end| {
+            match (to_usize(start), to_usize(end)) {
+                (Some(start), Some(end)) => can_see(start, end),
+                _ => false,
+            }
+        })
     }
 
     /// Text representation of a level, mostly for debugging purposes
@@ -398,6 +1057,135 @@ impl<FT:Default+Clone,
         let i = self.get_index(x, y);
         &self.floor_data[i]
     }
+
+    /// Changes the dimensions of the level.
+    ///
+    /// `anchor` is the part of the level that stays in place. New tiles are at
+    /// height `fill_z`, have `fill_data` as floor data and no walls.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use isometric::{Level, Anchor};
+    ///
+    /// let mut level: Level = Level::new(10, 10, 0.0);
+    /// level.set_z(0, 0, 1.0);
+    /// level.resize(12, 8, Anchor::TopRight, 0.0, ());
+    /// assert_eq!(level.width(), 12);
+    /// assert_eq!(level.depth(), 8);
+    /// assert_eq!(level.z(2, 0), 0.0);
+    /// ```
+    pub fn resize(&mut self, width: usize, depth: usize, anchor: Anchor, fill_z: f32, fill_data: FT) {
+        let dx = width as isize - self.width as isize;
+        let dy = depth as isize - self.depth as isize;
+        let offset = match anchor {
+            Anchor::BottomLeft => (0, 0),
+            Anchor::BottomRight => (dx, 0),
+            Anchor::TopLeft => (0, dy),
+            Anchor::TopRight => (dx, dy),
+            Anchor::Center => (dx / 2, dy / 2),
+        };
+        let mut level = Level::new(width, depth, fill_z);
+        for data in &mut level.floor_data {
+            *data = fill_data.clone();
+        }
+        level.blit(self, offset);
+        level.deep_water = self.deep_water;
+        *self = level;
+    }
+
+    /// Returns a new level containing a region of this one, including the
+    /// walls on the region's edges.
+    ///
+    /// `rect` must be inside the level's bounds.
+    pub fn sub_level(&self, rect: Rect) -> Level<FT, WT> {
+        debug_assert!(rect.x + rect.width <= self.width && rect.y + rect.depth <= self.depth,
+                      "rect must be in level's bounds");
+        let mut level = Level::new(rect.width, rect.depth, 0.0);
+        level.blit(self, (-(rect.x as isize), -(rect.y as isize)));
+        level.deep_water = self.deep_water;
+        level
+    }
+
+    /// Copies another level into this one, tile (0, 0) of `other` being copied
+    /// at `offset`. Parts of `other` that fall outside of this level are ignored.
+    ///
+    /// Tiles' height, floor data, shape, bridge and water are copied, as well
+    /// as the walls between copied tiles. On the edges of the copied region,
+    /// walls of `other` are added, but existing walls of this level are kept if
+    /// `other` doesn't have one there.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use isometric::{Level, WallPosition};
+    ///
+    /// let mut room: Level = Level::new(3, 3, 1.0);
+    /// room.add_border_walls(());
+    ///
+    /// let mut level: Level = Level::new(10, 10, 0.0);
+    /// level.blit(&room, (4, 4));
+    /// assert_eq!(level.z(5, 5), 1.0);
+    /// assert!(level.wall(4, 5, WallPosition::Left).is_some());
+    /// assert!(level.wall(3, 5, WallPosition::Right).is_some());
+    /// assert!(level.wall(5, 5, WallPosition::Left).is_none());
+    /// ```
+    pub fn blit(&mut self, other: &Level<FT, WT>, offset: (isize, isize)) {
+        self.blit_masked(other, offset, &|_, _| true);
+    }
+
+    /// Same as `blit`, but only tiles (x, y) of `other` for which `mask(x, y)`
+    /// is true are copied; walls between a copied tile and an ignored one are
+    /// treated as edges of the copied region.
+    pub fn blit_masked(&mut self, other: &Level<FT, WT>, offset: (isize, isize),
+                       mask: &dyn Fn(usize, usize) -> bool) {
+        // Returns the position in self of a tile of other, if it is in bounds
+        let width = self.width as isize;
+        let depth = self.depth as isize;
+        let target = |x: isize, y: isize| {
+            let x = x + offset.0;
+            let y = y + offset.1;
+            if x < 0 || y < 0 || x >= width || y >= depth {
+                None
+            } else {
+                Some((x as usize, y as usize))
+            }
+        };
+        let sides = [(WallPosition::Left, -1, 0),
+                     (WallPosition::Right, 1, 0),
+                     (WallPosition::Bottom, 0, -1),
+                     (WallPosition::Top, 0, 1)];
+
+        for x in 0..other.width {
+            for y in 0..other.depth {
+                if !mask(x, y) {
+                    continue;
+                }
+                let (tx, ty) = match target(x as isize, y as isize) {
+                    Some(pos) => pos,
+                    None => continue,
+                };
+                self.set_z(tx, ty, other.z(x, y));
+                self.set_floor_data(tx, ty, other.floor_data(x, y).clone());
+                self.set_shape(tx, ty, other.shape(x, y));
+                self.set_bridge(tx, ty, other.bridge(x, y));
+                self.set_water(tx, ty, other.water(x, y));
+
+                for &(position, dx, dy) in &sides {
+                    let nx = x as isize + dx;
+                    let ny = y as isize + dy;
+                    let inside = nx >= 0 && ny >= 0
+                        && nx < other.width as isize && ny < other.depth as isize
+                        && target(nx, ny).is_some()
+                        && mask(nx as usize, ny as usize);
+                    let wall = other.wall(x, y, position);
+                    if inside || wall.is_some() {
+                        self.set_wall(tx, ty, position, wall.clone());
+                    }
+                }
+            }
+        }
+    }
 }
 
 
@@ -490,6 +1278,40 @@ fn test_cliffs() {
     assert_eq!(level.is_move_possible((2, 1), (1, 1)), false);
 }
 
+#[test]
+fn occlusion() {
+    let mut level: Level = Level::new(10, 10, 0.0);
+    level.set_z(5, 5, 1.0);
+    assert_eq!(level.corner_occlusion(5, 5, 0.0), 0.25);
+    assert_eq!(level.corner_occlusion(5, 5, 1.0), 0.0);
+    assert_eq!(level.corner_occlusion(4, 4, 0.0), 0.0);
+
+    // Walls around the level occlude like any other wall, unless they are
+    // marked as borders (`SimpleWall::Border`), which are ignored
+    level.add_border_walls(());
+    assert_eq!(level.corner_occlusion(0, 0, 0.0), 0.8);
+    let mut level: Level<(), ::wall::SimpleWall> = Level::new(10, 10, 0.0);
+    level.add_border_walls(::wall::SimpleWall::Border);
+    assert_eq!(level.corner_occlusion(0, 0, 0.0), 0.0);
+    assert_eq!(level.corner_occlusion(10, 10, 0.0), 0.0);
+}
+
+#[test]
+fn paths() {
+    let mut level: Level = Level::new(10, 10, 0.0);
+    assert_eq!(level.find_path((0, 0), (3, 3)), Some(vec![(0, 0), (1, 1), (2, 2), (3, 3)]));
+    assert_eq!(level.find_path((0, 0), (0, 0)), Some(vec![(0, 0)]));
+    assert_eq!(level.find_path((0, 0), (10, 0)), None);
+
+    // Close (1, 1) in a box
+    level.set_wall(1, 1, WallPosition::Left, Some(()));
+    level.set_wall(1, 1, WallPosition::Right, Some(()));
+    level.set_wall(1, 1, WallPosition::Top, Some(()));
+    level.set_wall(1, 1, WallPosition::Bottom, Some(()));
+    assert_eq!(level.find_path((0, 0), (1, 1)), None);
+    assert_eq!(level.find_path((0, 0), (2, 2)).unwrap().len(), 4);
+}
+
 #[test]
 fn floor_data() {
     let mut level: Level<i32, ()> = Level::new(10, 10, 0.0);
@@ -506,3 +1328,135 @@ fn floor_data() {
 //     assert_eq!(level.wall(5, 4, WallPosition::Left).unwrap(), 42);
 //     assert!(level.wall(0, 0, WallPosition::Right).is_none());
 // }
+
+#[test]
+fn bridges() {
+    use bridge::{Bridge, BridgeAxis, Surface};
+
+    let mut level: Level = Level::new(10, 10, 2.0);
+    for y in 3..6 {
+        level.set_z(4, y, 0.0);
+        level.set_bridge(4, y, Some(Bridge::new(2.0, BridgeAxis::Y)));
+    }
+    level.add_cliff_walls(1.0, ());
+
+    // Crossing the bridge
+    assert!(level.is_surface_move_possible((4, 2, Surface::Ground), (4, 3, Surface::Bridge)));
+    assert!(level.is_surface_move_possible((4, 3, Surface::Bridge), (4, 4, Surface::Bridge)));
+    assert!(level.is_surface_move_possible((4, 5, Surface::Bridge), (4, 6, Surface::Ground)));
+    assert!(!level.is_surface_move_possible((4, 4, Surface::Bridge), (5, 4, Surface::Bridge)));
+    assert!(!level.is_surface_move_possible((4, 4, Surface::Bridge), (5, 5, Surface::Ground)));
+    assert!(!level.is_surface_move_possible((4, 4, Surface::Bridge), (4, 4, Surface::Ground)));
+
+    // Walking underneath
+    assert!(level.is_surface_move_possible((4, 3, Surface::Ground), (4, 4, Surface::Ground)));
+    assert!(!level.is_surface_move_possible((4, 3, Surface::Ground), (4, 2, Surface::Ground)));
+    assert!(!level.is_surface_move_possible((4, 3, Surface::Ground), (4, 4, Surface::Bridge)));
+
+    // Cliff walls of the chasm don't block the view from the bridge
+    let f = level.surface_visibility((4, 4, Surface::Bridge), 4);
+    assert!(f(2, 4, Surface::Ground));
+    assert!(f(4, 3, Surface::Bridge));
+    assert!(!f(4, 3, Surface::Ground));
+    let f = level.surface_visibility((4, 4, Surface::Ground), 4);
+    assert!(!f(2, 4, Surface::Ground));
+    assert!(f(4, 3, Surface::Ground));
+    assert!(!f(4, 3, Surface::Bridge));
+}
+
+#[test]
+fn shapes() {
+    use shape::{TileShape, Corner};
+
+    let mut level: Level = Level::new(10, 10, 0.0);
+    for x in 5..10 {
+        for y in 0..10 {
+            level.set_z(x, y, 1.0);
+        }
+    }
+    level.set_shape(3, 3, TileShape::Flat);
+    level.set_shape(4, 4, TileShape::Ramp(WallPosition::Right, 1.0));
+    level.set_shape(4, 6, TileShape::OuterCorner(Corner::BottomRight, 1.0));
+    assert_eq!(level.corner_heights(3, 3), [0.0; 4]);
+    assert_eq!(level.corner_heights(4, 4), [0.0, 1.0, 0.0, 1.0]);
+    // Smooth tile next to the ramp joins it
+    assert_eq!(level.corner_heights(4, 5)[1], 1.0);
+
+    level.add_cliff_walls(0.5, ());
+
+    // Ramp connects both heights, other edges are cliffs
+    assert!(level.wall(4, 4, WallPosition::Right).is_none());
+    assert!(level.wall(4, 3, WallPosition::Right).is_some());
+    // Only half of the corner slope's edge matches
+    assert!(level.wall(4, 6, WallPosition::Right).is_some());
+}
+
+#[test]
+fn resize() {
+    use rect::Anchor;
+
+    let mut level: Level<i32, ()> = Level::new(4, 4, 0.0);
+    level.set_z(3, 3, 2.0);
+    level.set_wall(3, 3, WallPosition::Right, Some(()));
+    level.resize(6, 6, Anchor::Center, 1.0, 42);
+    assert_eq!(level.z(4, 4), 2.0);
+    assert!(level.wall(4, 4, WallPosition::Right).is_some());
+    assert_eq!(level.z(0, 0), 1.0);
+    assert_eq!(*level.floor_data(5, 5), 42);
+    assert_eq!(*level.floor_data(1, 1), 0);
+
+    level.resize(3, 3, Anchor::BottomLeft, 0.0, 0);
+    assert_eq!(level.width(), 3);
+    assert_eq!(level.z(0, 0), 1.0);
+}
+
+#[test]
+fn sub_level_and_blit() {
+    use rect::Rect;
+
+    let mut level: Level = Level::new(10, 10, 0.0);
+    level.set_z(3, 4, 1.0);
+    level.set_wall(2, 4, WallPosition::Right, Some(()));
+    level.set_wall(3, 4, WallPosition::Top, Some(()));
+    level.set_wall(3, 5, WallPosition::Top, Some(()));
+
+    // Edge walls are part of the region
+    let sub = level.sub_level(Rect::new(3, 4, 2, 2));
+    assert_eq!(sub.z(0, 0), 1.0);
+    assert!(sub.wall(0, 0, WallPosition::Left).is_some());
+    assert!(sub.wall(0, 0, WallPosition::Top).is_some());
+    assert!(sub.wall(0, 1, WallPosition::Top).is_some());
+    assert!(sub.wall(1, 0, WallPosition::Right).is_none());
+
+    // Walls at the seams are only added; inside, they are replaced
+    let mut other: Level = Level::new(10, 10, 0.0);
+    other.set_wall(5, 5, WallPosition::Left, Some(()));
+    other.set_wall(4, 4, WallPosition::Right, Some(()));
+    other.blit(&sub, (4, 4));
+    assert!(other.wall(4, 4, WallPosition::Left).is_some());
+    assert!(other.wall(5, 5, WallPosition::Left).is_none());
+    assert!(other.wall(4, 5, WallPosition::Top).is_some());
+    assert!(other.wall(5, 4, WallPosition::Right).is_none());
+
+    // Clipped at the level's bounds
+    other.blit(&sub, (9, 9));
+    assert_eq!(other.z(9, 9), 1.0);
+}
+
+#[test]
+fn deep_water() {
+    let mut level: Level = Level::new(5, 5, 0.0);
+    for y in 0..5 {
+        level.set_water(2, y, 1.5);
+    }
+    assert!(level.find_path((0, 0), (4, 4)).is_some());
+
+    level.set_deep_water(Some(1.0));
+    assert!(level.find_path((0, 0), (4, 4)).is_none());
+    assert!(!level.is_move_possible((1, 1), (2, 2)));
+    // Water doesn't block lines of sight
+    assert!(level.visibility((0, 2), 5)(4, 2));
+
+    level.set_water(2, 3, 0.5);
+    assert!(level.find_path((0, 0), (4, 4)).is_some());
+}
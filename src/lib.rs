@@ -13,11 +13,46 @@
 #[macro_use]
 extern crate glium;
 extern crate image;
+#[cfg(feature = "serialization")]
+extern crate serde;
+#[cfg(feature = "serialization")]
+#[macro_use]
+extern crate serde_derive;
+#[cfg(all(test, feature = "serialization"))]
+extern crate serde_json;
 
 mod level;
 mod display;
 mod wall;
 mod camera;
+mod light;
+mod shadow;
+mod fog;
+mod cutaway;
+mod astar;
+mod layered;
+mod bridge;
+mod shape;
+mod visibility;
+mod chunked;
+mod rect;
+mod prefab;
+mod rng;
+mod dungeon;
+mod cave;
+mod noise;
+mod terrain;
+mod erosion;
+mod water;
+mod wfc;
+mod entity;
+mod movement;
+mod scheduler;
+mod dijkstra;
+mod flow;
+mod hpa;
+mod cooperative;
+mod analysis;
 
 pub use level::Level;
 pub use wall::WallPosition;
@@ -25,3 +60,55 @@ pub use wall::Wall;
 pub use wall::SimpleWall;
 pub use display::Renderer;
 pub use camera::Camera;
+pub use light::Lighting;
+pub use shadow::ShadowMap;
+pub use fog::FogOfWar;
+pub use fog::TileVisibility;
+pub use cutaway::Cutaway;
+pub use cutaway::CutawayMode;
+pub use layered::LayeredLevel;
+pub use layered::Connector;
+pub use layered::ConnectorKind;
+pub use bridge::Bridge;
+pub use bridge::BridgeAxis;
+pub use bridge::Surface;
+pub use shape::TileShape;
+pub use shape::Corner;
+pub use chunked::ChunkedLevel;
+pub use chunked::ChunkLoader;
+pub use rect::Rect;
+pub use rect::Anchor;
+pub use prefab::Prefab;
+pub use prefab::PrefabLibrary;
+pub use prefab::PrefabError;
+pub use dungeon::BspGenerator;
+pub use dungeon::Dungeon;
+pub use dungeon::Room;
+pub use dungeon::Doorway;
+pub use cave::CaveGenerator;
+pub use cave::Cave;
+pub use cave::Connectivity;
+pub use noise::NoiseKind;
+pub use terrain::TerrainGenerator;
+pub use terrain::TerrainMask;
+pub use erosion::ThermalErosion;
+pub use erosion::HydraulicErosion;
+pub use erosion::ErosionStats;
+pub use erosion::HeightStats;
+pub use water::RiverGenerator;
+pub use wfc::WfcGenerator;
+pub use wfc::WfcError;
+pub use entity::Entities;
+pub use entity::EntityId;
+pub use entity::EntityError;
+pub use entity::Occupancy;
+pub use movement::Movement;
+pub use movement::MovementEvent;
+pub use scheduler::Scheduler;
+pub use dijkstra::DijkstraMap;
+pub use flow::FlowField;
+pub use hpa::HpaGraph;
+pub use cooperative::CooperativePlanner;
+pub use analysis::Regions;
+pub use analysis::Chokepoints;
+pub use analysis::DetectedRoom;
@@ -0,0 +1,208 @@
+// (C) 2017, Élisabeth Henry
+//
+// Licensed under either of
+// 
+// Apache License, Version 2.0: http://www.apache.org/licenses/LICENSE-2.0
+// MIT license: http://opensource.org/licenses/MIT
+// at your option.
+//
+// Unless you explicitly state otherwise, any contribution intentionally submitted
+// for inclusion in the work by you, as defined in the Apache-2.0 license, shall be
+// dual licensed as above, without any additional terms or conditions.
+
+use std::default::Default;
+use std::f32;
+
+/// Sun, ambient and clear colours for a given hour:
+/// (hour, sun colour, ambient colour, clear colour)
+type Keyframe = (f32, [f32; 3], [f32; 3], [f32; 3]);
+
+/// Keyframes used by `from_time_of_day`
+const KEYFRAMES: [Keyframe; 6] = [
+    (0.0, [0.15, 0.15, 0.3], [0.05, 0.05, 0.12], [0.02, 0.02, 0.08]),
+    (5.0, [0.2, 0.2, 0.35], [0.1, 0.1, 0.18], [0.05, 0.05, 0.15]),
+    (7.0, [1.0, 0.6, 0.4], [0.35, 0.3, 0.35], [0.6, 0.35, 0.3]),
+    (12.0, [1.0, 1.0, 0.95], [0.5, 0.5, 0.5], [0.45, 0.65, 0.9]),
+    (17.0, [1.0, 0.55, 0.35], [0.35, 0.28, 0.3], [0.65, 0.35, 0.25]),
+    (19.0, [0.2, 0.2, 0.35], [0.1, 0.1, 0.18], [0.05, 0.05, 0.15]),
+];
+
+/// Lighting of a scene: a directional light (the sun), an ambient colour
+/// and the colour the screen is cleared with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lighting {
+    sun_direction: [f32; 3],
+    sun_color: [f32; 3],
+    ambient_color: [f32; 3],
+    clear_color: [f32; 4],
+}
+
+impl Lighting {
+    /// Creates a new lighting configuration with default settings
+    pub fn new() -> Lighting {
+        Lighting {
+            sun_direction: [1.0, 0.0, 0.0],
+            sun_color: [1.0, 1.0, 1.0],
+            ambient_color: [0.5, 0.5, 0.5],
+            clear_color: [0.1, 0.0, 0.0, 1.0],
+        }
+    }
+
+    /// Computes the lighting corresponding to a time of day.
+    ///
+    /// `hours` goes from 0.0 (midnight) to 24.0 (midnight again); values outside
+    /// this range wrap around. The sun rises at 6.0 in the -x direction, is at its
+    /// highest at 12.0 and sets at 18.0 in the +x direction. At night, the light
+    /// comes from the opposite direction (the moon) and is dim and bluish.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use isometric::Lighting;
+    ///
+    /// let noon = Lighting::from_time_of_day(12.0);
+    /// let midnight = Lighting::from_time_of_day(0.0);
+    ///
+    /// // Sun is high at noon
+    /// assert!(noon.sun_direction()[2] > 0.9);
+    /// // and brighter than the moon
+    /// assert!(noon.sun_color()[0] > midnight.sun_color()[0]);
+    /// ```
+    pub fn from_time_of_day(hours: f32) -> Lighting {
+        let hours = hours % 24.0;
+        let hours = if hours < 0.0 { hours + 24.0 } else { hours };
+
+        // Angle of the sun: 0 at sunrise, PI at sunset
+        let angle = (hours - 6.0) / 12.0 * f32::consts::PI;
+        let sun_direction = if angle.sin() >= 0.0 {
+            [-angle.cos(), 0.0, angle.sin()]
+        } else {
+            // Moon
+            [angle.cos(), 0.0, -angle.sin()]
+        };
+
+        // Find the two keyframes surrounding the current hour
+        let mut i = 0;
+        while i + 1 < KEYFRAMES.len() && KEYFRAMES[i + 1].0 <= hours {
+            i += 1;
+        }
+        let (start, sun_a, ambient_a, clear_a) = KEYFRAMES[i];
+        let (end, sun_b, ambient_b, clear_b) = if i + 1 < KEYFRAMES.len() {
+            KEYFRAMES[i + 1]
+        } else {
+            let (h, s, a, c) = KEYFRAMES[0];
+            (h + 24.0, s, a, c)
+        };
+        let t = (hours - start) / (end - start);
+
+        let clear = mix(clear_a, clear_b, t);
+        Lighting {
+            sun_direction,
+            sun_color: mix(sun_a, sun_b, t),
+            ambient_color: mix(ambient_a, ambient_b, t),
+            clear_color: [clear[0], clear[1], clear[2], 1.0],
+        }
+    }
+
+    /// Returns the direction the sun light comes from
+    pub fn sun_direction(&self) -> [f32; 3] {
+        self.sun_direction
+    }
+
+    /// Sets the direction the sun light comes from.
+    ///
+    /// The vector is normalized; a null vector is ignored.
+    pub fn set_sun_direction(&mut self, x: f32, y: f32, z: f32) -> &mut Self {
+        let norm = (x * x + y * y + z * z).sqrt();
+        if norm > 0.0 {
+            self.sun_direction = [x / norm, y / norm, z / norm];
+        }
+        self
+    }
+
+    /// Returns the colour of the sun light
+    pub fn sun_color(&self) -> [f32; 3] {
+        self.sun_color
+    }
+
+    /// Sets the colour of the sun light
+    pub fn set_sun_color(&mut self, r: f32, g: f32, b: f32) -> &mut Self {
+        self.sun_color = [r, g, b];
+        self
+    }
+
+    /// Returns the ambient colour, used for surfaces that don't face the sun
+    pub fn ambient_color(&self) -> [f32; 3] {
+        self.ambient_color
+    }
+
+    /// Sets the ambient colour, used for surfaces that don't face the sun
+    pub fn set_ambient_color(&mut self, r: f32, g: f32, b: f32) -> &mut Self {
+        self.ambient_color = [r, g, b];
+        self
+    }
+
+    /// Returns the colour the screen is cleared with before rendering
+    pub fn clear_color(&self) -> [f32; 4] {
+        self.clear_color
+    }
+
+    /// Sets the colour the screen is cleared with before rendering
+    pub fn set_clear_color(&mut self, r: f32, g: f32, b: f32, a: f32) -> &mut Self {
+        self.clear_color = [r, g, b, a];
+        self
+    }
+}
+
+impl Default for Lighting {
+    fn default() -> Self {
+        Lighting::new()
+    }
+}
+
+/// Linear interpolation between two colours
+fn mix(a: [f32; 3], b: [f32; 3], t: f32) -> [f32; 3] {
+    [a[0] + (b[0] - a[0]) * t,
+     a[1] + (b[1] - a[1]) * t,
+     a[2] + (b[2] - a[2]) * t]
+}
+
+
+#[test]
+fn time_of_day_wraps() {
+    assert_eq!(Lighting::from_time_of_day(0.0), Lighting::from_time_of_day(24.0));
+    assert_eq!(Lighting::from_time_of_day(3.0), Lighting::from_time_of_day(-21.0));
+}
+
+#[test]
+fn sun_course() {
+    // Sun rises on one side and sets on the other
+    let morning = Lighting::from_time_of_day(8.0);
+    let evening = Lighting::from_time_of_day(16.0);
+    assert!(morning.sun_direction()[0] < 0.0);
+    assert!(evening.sun_direction()[0] > 0.0);
+
+    // Light always comes from above
+    for h in 0..24 {
+        assert!(Lighting::from_time_of_day(h as f32).sun_direction()[2] >= 0.0);
+    }
+}
+
+#[test]
+fn dawn_to_night() {
+    let dawn = Lighting::from_time_of_day(7.0);
+    let noon = Lighting::from_time_of_day(12.0);
+    let night = Lighting::from_time_of_day(22.0);
+    assert!(noon.ambient_color()[1] > dawn.ambient_color()[1]);
+    assert!(dawn.ambient_color()[1] > night.ambient_color()[1]);
+    assert!(noon.clear_color()[2] > night.clear_color()[2]);
+}
+
+#[test]
+fn sun_direction_normalized() {
+    let mut lighting = Lighting::new();
+    lighting.set_sun_direction(3.0, 0.0, 4.0);
+    assert_eq!(lighting.sun_direction(), [0.6, 0.0, 0.8]);
+    lighting.set_sun_direction(0.0, 0.0, 0.0);
+    assert_eq!(lighting.sun_direction(), [0.6, 0.0, 0.8]);
+}
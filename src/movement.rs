@@ -0,0 +1,206 @@
+// (C) 2017, Élisabeth Henry
+//
+// Licensed under either of
+// 
+// Apache License, Version 2.0: http://www.apache.org/licenses/LICENSE-2.0
+// MIT license: http://opensource.org/licenses/MIT
+// at your option.
+//
+// Unless you explicitly state otherwise, any contribution intentionally submitted
+// for inclusion in the work by you, as defined in the Apache-2.0 license, shall be
+// dual licensed as above, without any additional terms or conditions.
+
+use level::Level;
+use wall::Wall;
+
+use std::collections::VecDeque;
+use std::default::Default;
+
+/// Something that happened during `Movement::update`
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum MovementEvent {
+    /// Reached a tile, and there are other steps after it
+    Step((usize, usize)),
+    /// Reached the last tile of the path
+    Arrived((usize, usize)),
+}
+
+/// Smooth movement of a unit from tile to tile, for display purposes.
+///
+/// The unit's tile changes in discrete steps, but its world position is
+/// interpolated between tiles, following the floor (see `Level::z_at`).
+/// Time only passes when `update` is called, so the movement is fully
+/// deterministic.
+///
+/// # Example
+///
+/// ```
+/// use isometric::{Level, Movement, MovementEvent};
+///
+/// let mut level: Level = Level::new(10, 10, 0.0);
+/// level.set_z(2, 0, 1.0);
+///
+/// // Two tiles per second
+/// let mut movement = Movement::new((0, 0), 2.0);
+/// movement.set_path(level.find_path((0, 0), (2, 0)).unwrap());
+///
+/// assert_eq!(movement.update(0.25), vec![]);
+/// assert_eq!(movement.world_position(&level)[0], 1.0);
+/// assert_eq!(movement.update(0.75), vec![MovementEvent::Step((1, 0)),
+///                                        MovementEvent::Arrived((2, 0))]);
+///
+/// // Standing in the middle of the tile, on the floor
+/// let pos = movement.world_position(&level);
+/// assert_eq!((pos[0], pos[1]), (2.5, 0.5));
+/// assert_eq!(pos[2], level.z_at(2.5, 0.5));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Movement {
+    tile: (usize, usize),
+    path: VecDeque<(usize, usize)>,
+    // Part of the way to the next tile already done, between 0.0 and 1.0
+    progress: f32,
+    speed: f32,
+}
+
+impl Movement {
+    /// Creates a new movement for a unit standing on `tile`, moving at
+    /// `speed` tiles per second
+    pub fn new(tile: (usize, usize), speed: f32) -> Movement {
+        Movement {
+            tile,
+            path: VecDeque::new(),
+            progress: 0.0,
+            speed,
+        }
+    }
+
+    /// Returns the speed, in tiles per second
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    /// Sets the speed, in tiles per second
+    pub fn set_speed(&mut self, speed: f32) -> &mut Self {
+        debug_assert!(speed > 0.0, "speed must be positive");
+        self.speed = speed;
+        self
+    }
+
+    /// Returns the last tile the unit reached
+    pub fn tile(&self) -> (usize, usize) {
+        self.tile
+    }
+
+    /// Returns the tile the unit is walking to, if it is moving
+    pub fn next_tile(&self) -> Option<(usize, usize)> {
+        self.path.front().cloned()
+    }
+
+    /// Returns true if the unit is moving
+    pub fn is_moving(&self) -> bool {
+        !self.path.is_empty()
+    }
+
+    /// Adds a tile to go to after the current path
+    pub fn push_step(&mut self, tile: (usize, usize)) -> &mut Self {
+        self.path.push_back(tile);
+        self
+    }
+
+    /// Replaces the tiles to go to. If the unit is between two tiles, it
+    /// first ends its current step.
+    ///
+    /// The path may start with the current tile (as returned by
+    /// `Level::find_path`), which is then ignored.
+    pub fn set_path(&mut self, path: Vec<(usize, usize)>) -> &mut Self {
+        let current = if self.progress > 0.0 { self.path.pop_front() } else { None };
+        self.path.clear();
+        self.path.extend(current);
+        let last = current.unwrap_or(self.tile);
+        let skip = if path.first() == Some(&last) { 1 } else { 0 };
+        self.path.extend(path.into_iter().skip(skip));
+        self
+    }
+
+    /// Stops at the next tile (or right away if the unit is on a tile)
+    pub fn stop(&mut self) -> &mut Self {
+        let current = if self.progress > 0.0 { self.path.pop_front() } else { None };
+        self.path.clear();
+        self.path.extend(current);
+        self
+    }
+
+    /// Makes `dt` seconds pass, and returns the tiles that were reached
+    pub fn update(&mut self, dt: f32) -> Vec<MovementEvent> {
+        let mut events = vec![];
+        let mut distance = dt * self.speed;
+        while let Some(next) = self.path.front().cloned() {
+            if self.progress + distance < 1.0 {
+                self.progress += distance;
+                break;
+            }
+            distance -= 1.0 - self.progress;
+            self.progress = 0.0;
+            self.tile = next;
+            self.path.pop_front();
+            events.push(if self.path.is_empty() {
+                MovementEvent::Arrived(next)
+            } else {
+                MovementEvent::Step(next)
+            });
+        }
+        events
+    }
+
+    /// Returns the position of the unit in the world: the center of its tile,
+    /// or somewhere on the way to the next one, at the height of the floor
+    pub fn world_position<FT: Default + Clone, WT: Wall>(&self, level: &Level<FT, WT>) -> [f32; 3] {
+        let (x, y) = match self.path.front() {
+            Some(&(next_x, next_y)) => {
+                let t = self.progress;
+                (self.tile.0 as f32 + (next_x as f32 - self.tile.0 as f32) * t,
+                 self.tile.1 as f32 + (next_y as f32 - self.tile.1 as f32) * t)
+            },
+            None => (self.tile.0 as f32, self.tile.1 as f32),
+        };
+        let (x, y) = (x + 0.5, y + 0.5);
+        [x, y, level.z_at(x, y)]
+    }
+}
+
+
+#[test]
+fn movement_steps() {
+    let mut level: Level = Level::new(5, 5, 0.0);
+    level.set_z(1, 1, 2.0);
+    let mut movement = Movement::new((0, 1), 1.0);
+    movement.set_path(vec![(0, 1), (1, 1), (2, 1)]);
+    assert_eq!(movement.next_tile(), Some((1, 1)));
+
+    // Halfway between two tiles, on the edge between them
+    assert!(movement.update(0.5).is_empty());
+    let pos = movement.world_position(&level);
+    assert_eq!([pos[0], pos[1]], [1.0, 1.5]);
+    assert_eq!(pos[2], level.z_at(1.0, 1.5));
+    assert!(pos[2] > 0.0 && pos[2] < 2.0);
+
+    // Changing the path finishes the current step first
+    movement.set_path(vec![(1, 1), (1, 2)]);
+    assert_eq!(movement.update(0.5), vec![MovementEvent::Step((1, 1))]);
+    assert_eq!(movement.world_position(&level)[2], level.z_at(1.5, 1.5));
+
+    movement.stop();
+    assert!(!movement.is_moving());
+    assert!(movement.update(10.0).is_empty());
+    assert_eq!(movement.tile(), (1, 1));
+
+    // Same result whatever the time steps
+    let mut a = Movement::new((0, 0), 3.0);
+    a.set_path(vec![(1, 0), (2, 0), (3, 0), (4, 0)]);
+    let mut b = a.clone();
+    let events: Vec<_> = (0..8).flat_map(|_| a.update(0.125)).collect();
+    assert_eq!(events, b.update(1.0));
+    assert_eq!(a.tile(), b.tile());
+    assert_eq!(a.world_position(&level), b.world_position(&level));
+}
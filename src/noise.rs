@@ -0,0 +1,124 @@
+// (C) 2017, Élisabeth Henry
+//
+// Licensed under either of
+// 
+// Apache License, Version 2.0: http://www.apache.org/licenses/LICENSE-2.0
+// MIT license: http://opensource.org/licenses/MIT
+// at your option.
+//
+// Unless you explicitly state otherwise, any contribution intentionally submitted
+// for inclusion in the work by you, as defined in the Apache-2.0 license, shall be
+// dual licensed as above, without any additional terms or conditions.
+
+use std::f32;
+
+/// Kind of noise used to generate terrain
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum NoiseKind {
+    /// Random values at integer coordinates, smoothly interpolated: blobby
+    Value,
+    /// Random gradients at integer coordinates (Perlin noise): less regular
+    Perlin,
+}
+
+/// Hashes integer coordinates to a pseudo-random number
+fn hash(x: i64, y: i64, seed: u64) -> u64 {
+    let mut h = seed ^ (x as u64).wrapping_mul(0x9E3779B97F4A7C15)
+        ^ (y as u64).wrapping_mul(0xC2B2AE3D27D4EB4F);
+    h = (h ^ (h >> 33)).wrapping_mul(0xFF51AFD7ED558CCD);
+    h = (h ^ (h >> 33)).wrapping_mul(0xC4CEB9FE1A85EC53);
+    h ^ (h >> 33)
+}
+
+/// Smoothstep-like curve so there is no visible grid
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+/// Returns the noise at point (x, y), between 0.0 and 1.0
+pub fn noise(kind: NoiseKind, x: f32, y: f32, seed: u64) -> f32 {
+    let x0 = x.floor();
+    let y0 = y.floor();
+    let (fx, fy) = (x - x0, y - y0);
+    let (ix, iy) = (x0 as i64, y0 as i64);
+
+    let corner = |dx: i64, dy: i64| {
+        let h = hash(ix + dx, iy + dy, seed);
+        match kind {
+            NoiseKind::Value => (h >> 40) as f32 / (1u64 << 24) as f32,
+            NoiseKind::Perlin => {
+                // One of 8 gradients, dotted with the offset to this corner
+                let d = f32::consts::FRAC_1_SQRT_2;
+                let (gx, gy) = match h % 8 {
+                    0 => (1.0, 0.0),
+                    1 => (-1.0, 0.0),
+                    2 => (0.0, 1.0),
+                    3 => (0.0, -1.0),
+                    4 => (d, d),
+                    5 => (-d, d),
+                    6 => (d, -d),
+                    _ => (-d, -d),
+                };
+                gx * (fx - dx as f32) + gy * (fy - dy as f32)
+            },
+        }
+    };
+    let (u, v) = (fade(fx), fade(fy));
+    let value = lerp(lerp(corner(0, 0), corner(1, 0), u),
+                     lerp(corner(0, 1), corner(1, 1), u),
+                     v);
+    match kind {
+        NoiseKind::Value => value,
+        // Perlin noise is roughly between -0.7 and 0.7
+        NoiseKind::Perlin => (value / 1.4 + 0.5).max(0.0).min(1.0),
+    }
+}
+
+/// Fractal noise: sum of `octaves` layers of noise, each one with twice the
+/// frequency and `persistence` times the amplitude of the previous one.
+/// Returns a value between 0.0 and 1.0.
+pub fn fractal(kind: NoiseKind, x: f32, y: f32, seed: u64, octaves: usize, persistence: f32) -> f32 {
+    let mut sum = 0.0;
+    let mut total = 0.0;
+    let mut amplitude = 1.0;
+    let mut frequency = 1.0;
+    for octave in 0..octaves {
+        // Each octave has its own seed so they don't line up
+        sum += amplitude * noise(kind, x * frequency, y * frequency, seed.wrapping_add(octave as u64));
+        total += amplitude;
+        amplitude *= persistence;
+        frequency *= 2.0;
+    }
+    if total > 0.0 { sum / total } else { 0.0 }
+}
+
+
+#[test]
+fn noise_range() {
+    for &kind in &[NoiseKind::Value, NoiseKind::Perlin] {
+        for i in 0..500 {
+            let x = i as f32 * 0.37 - 50.0;
+            let y = i as f32 * 0.71;
+            let n = fractal(kind, x, y, 3, 4, 0.5);
+            assert!((0.0..=1.0).contains(&n));
+            assert_eq!(n, fractal(kind, x, y, 3, 4, 0.5));
+        }
+    }
+}
+
+#[test]
+fn noise_continuous() {
+    // Close points have close values
+    for &kind in &[NoiseKind::Value, NoiseKind::Perlin] {
+        for i in 0..100 {
+            let x = i as f32 * 0.5;
+            let a = noise(kind, x, 1.3, 0);
+            let b = noise(kind, x + 0.01, 1.3, 0);
+            assert!((a - b).abs() < 0.1);
+        }
+    }
+}
@@ -0,0 +1,528 @@
+// (C) 2017, Élisabeth Henry
+//
+// Licensed under either of
+// 
+// Apache License, Version 2.0: http://www.apache.org/licenses/LICENSE-2.0
+// MIT license: http://opensource.org/licenses/MIT
+// at your option.
+//
+// Unless you explicitly state otherwise, any contribution intentionally submitted
+// for inclusion in the work by you, as defined in the Apache-2.0 license, shall be
+// dual licensed as above, without any additional terms or conditions.
+
+use level::Level;
+use wall::Wall;
+use wall::WallPosition;
+use bridge::Bridge;
+use bridge::BridgeAxis;
+use shape::TileShape;
+
+use std::collections::HashMap;
+use std::default::Default;
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+
+/// A reusable fragment of level (e.g. a room), that can be stamped into a
+/// `Level`.
+///
+/// Some tiles of a prefab can be "don't care" tiles: they are not copied when
+/// stamping it, so the level keeps its own tiles there.
+///
+/// # Example
+///
+/// ```
+/// use isometric::{Level, Prefab, WallPosition};
+///
+/// let mut room: Level = Level::new(3, 2, 0.0);
+/// room.add_border_walls(());
+/// let room = Prefab::new(room);
+///
+/// // Rotated a quarter turn, the room is 2 tiles wide and 3 tiles deep
+/// let rotated = room.rotated(1);
+/// assert_eq!((rotated.width(), rotated.depth()), (2, 3));
+///
+/// let mut level: Level = Level::new(10, 10, 0.0);
+/// assert!(rotated.fits(&level, (8, 7)));
+/// assert!(!rotated.fits(&level, (9, 7)));
+/// rotated.stamp(&mut level, (8, 7));
+/// assert!(level.wall(8, 7, WallPosition::Bottom).is_some());
+/// assert!(level.wall(9, 9, WallPosition::Right).is_some());
+/// ```
+#[derive(Debug)]
+pub struct Prefab<FT=(), WT=()> {
+    level: Level<FT, WT>,
+    used: Vec<bool>,
+}
+
+impl<FT: Default + Clone,
+     WT: Wall> Prefab<FT, WT> {
+    /// Creates a new prefab from a level, where all tiles are used
+    pub fn new(level: Level<FT, WT>) -> Prefab<FT, WT> {
+        let used = vec![true; level.width() * level.depth()];
+        Prefab {
+            level,
+            used,
+        }
+    }
+
+    /// Returns the width of the prefab
+    pub fn width(&self) -> usize {
+        self.level.width()
+    }
+
+    /// Returns the depth of the prefab
+    pub fn depth(&self) -> usize {
+        self.level.depth()
+    }
+
+    /// Get access to the content of the prefab
+    pub fn level(&self) -> &Level<FT, WT> {
+        &self.level
+    }
+
+    /// Get mutable access to the content of the prefab
+    pub fn level_mut(&mut self) -> &mut Level<FT, WT> {
+        &mut self.level
+    }
+
+    /// Returns false if tile (x, y) is a "don't care" tile
+    pub fn is_used(&self, x: usize, y: usize) -> bool {
+        debug_assert!(x < self.width() && y < self.depth(), "x and y must be in prefab's bounds");
+        self.used[y * self.width() + x]
+    }
+
+    /// Sets whether tile (x, y) is used, or is a "don't care" tile
+    pub fn set_used(&mut self, x: usize, y: usize, used: bool) -> &mut Self {
+        debug_assert!(x < self.width() && y < self.depth(), "x and y must be in prefab's bounds");
+        let width = self.width();
+        self.used[y * width + x] = used;
+        self
+    }
+
+    /// Returns a copy of this prefab where each tile has been moved by `f`, its
+    /// walls by `wall` and its shape by `shape`; `swap_axis` tells whether
+    /// bridges must be turned.
+    fn transform(&self, width: usize, depth: usize,
+                 f: &dyn Fn(usize, usize) -> (usize, usize),
+                 wall: &dyn Fn(WallPosition) -> WallPosition,
+                 shape: &dyn Fn(TileShape) -> TileShape,
+                 swap_axis: bool) -> Prefab<FT, WT> {
+        let mut level = Level::new(width, depth, 0.0);
+        let mut used = vec![false; width * depth];
+        for x in 0..self.width() {
+            for y in 0..self.depth() {
+                let (new_x, new_y) = f(x, y);
+                used[new_y * width + new_x] = self.is_used(x, y);
+                level.set_z(new_x, new_y, self.level.z(x, y));
+                level.set_floor_data(new_x, new_y, self.level.floor_data(x, y).clone());
+                level.set_shape(new_x, new_y, shape(self.level.shape(x, y)));
+                level.set_water(new_x, new_y, self.level.water(x, y));
+                level.set_bridge(new_x, new_y, self.level.bridge(x, y).map(|bridge| {
+                    let axis = match bridge.axis {
+                        BridgeAxis::X if swap_axis => BridgeAxis::Y,
+                        BridgeAxis::Y if swap_axis => BridgeAxis::X,
+                        axis => axis,
+                    };
+                    Bridge::new(bridge.z, axis)
+                }));
+                for &position in &[WallPosition::Left, WallPosition::Right,
+                                   WallPosition::Bottom, WallPosition::Top] {
+                    if let Some(ref data) = *self.level.wall(x, y, position) {
+                        level.set_wall(new_x, new_y, wall(position), Some(data.clone()));
+                    }
+                }
+            }
+        }
+        Prefab {
+            level,
+            used,
+        }
+    }
+
+    /// Returns a copy of this prefab, rotated counterclockwise by the given
+    /// number of quarter turns
+    pub fn rotated(&self, quarter_turns: usize) -> Prefab<FT, WT> {
+        let mut res = self.transform(self.width(), self.depth(), &|x, y| (x, y),
+                                     &|position| position, &|shape| shape, false);
+        for _ in 0..(quarter_turns % 4) {
+            let depth = res.depth();
+            res = res.transform(depth, res.width(), &|x, y| (depth - 1 - y, x),
+                                &|position| position.rotated(), &|shape| shape.rotated(), true);
+        }
+        res
+    }
+
+    /// Returns a copy of this prefab, mirrored along the x axis (left and
+    /// right are swapped)
+    pub fn mirrored(&self) -> Prefab<FT, WT> {
+        let width = self.width();
+        self.transform(width, self.depth(), &|x, y| (width - 1 - x, y),
+                       &|position| position.mirrored(), &|shape| shape.mirrored(), false)
+    }
+
+    /// Returns true if this prefab can be stamped at `pos` (position of its
+    /// tile (0, 0)), i.e. its used tiles are inside the level.
+    pub fn fits(&self, level: &Level<FT, WT>, pos: (usize, usize)) -> bool {
+        self.fits_where(level, pos, &|_, _| true)
+    }
+
+    /// Same as `fits`, but `can_stamp(x, y)` must also be true for each tile of
+    /// the level that would be overwritten by a used tile of the prefab (e.g. to
+    /// check that it doesn't overlap another room).
+    pub fn fits_where(&self, level: &Level<FT, WT>, pos: (usize, usize),
+                      can_stamp: &dyn Fn(usize, usize) -> bool) -> bool {
+        for x in 0..self.width() {
+            for y in 0..self.depth() {
+                if !self.is_used(x, y) {
+                    continue;
+                }
+                let target = (pos.0 + x, pos.1 + y);
+                if target.0 >= level.width() || target.1 >= level.depth()
+                    || !can_stamp(target.0, target.1) {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// Copies the used tiles of this prefab into a level, its tile (0, 0)
+    /// being at `pos` (see `Level::blit`).
+    pub fn stamp(&self, level: &mut Level<FT, WT>, pos: (usize, usize)) {
+        debug_assert!(self.fits(level, pos), "prefab must fit in the level");
+        level.blit_masked(&self.level, (pos.0 as isize, pos.1 as isize),
+                          &|x, y| self.is_used(x, y));
+    }
+}
+
+/// Error while loading a prefab library
+#[derive(Debug)]
+pub enum PrefabError {
+    /// Error while reading the file
+    Io(io::Error),
+    /// The file isn't in the right format
+    Syntax {
+        /// Line of the error (starting at 1)
+        line: usize,
+        /// Description of the error
+        message: String,
+    },
+}
+
+impl fmt::Display for PrefabError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PrefabError::Io(ref err) => write!(f, "error reading prefabs: {}", err),
+            PrefabError::Syntax { line, ref message } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl error::Error for PrefabError {
+    fn description(&self) -> &str {
+        match *self {
+            PrefabError::Io(_) => "error reading prefabs",
+            PrefabError::Syntax { .. } => "invalid prefab syntax",
+        }
+    }
+}
+
+impl From<io::Error> for PrefabError {
+    fn from(err: io::Error) -> PrefabError {
+        PrefabError::Io(err)
+    }
+}
+
+/// A collection of named prefabs.
+///
+/// Libraries can be loaded from a text format, where each prefab looks like:
+///
+/// ```text
+/// # Comments start with '#'
+/// prefab corridor_end
+/// +-+-+
+/// |1 .|
+/// + + +
+/// |? ?
+/// +-+-+
+/// end
+/// ```
+///
+/// The lines between `prefab <name>` and `end` are a grid where `+` are the
+/// corners of the tiles, `-` and `|` are walls, and tiles are either `.`
+/// (height 0), a digit (the tile's height) or `?` (a "don't care" tile).
+/// The first line of the grid is the bottom edge of the prefab (y = 0), as
+/// in `Level::to_ascii`. Walls and floor data are set to their default values.
+/// Grids may be indented: the indentation common to all their lines is ignored.
+///
+/// # Example
+///
+/// ```
+/// use isometric::{PrefabLibrary, WallPosition};
+///
+/// let library: PrefabLibrary = PrefabLibrary::parse("
+/// prefab closet
+/// +-+
+/// |.|
+/// + +
+/// end
+/// ").unwrap();
+/// let closet = library.get("closet").unwrap();
+/// assert!(closet.level().wall(0, 0, WallPosition::Left).is_some());
+/// assert!(closet.level().wall(0, 0, WallPosition::Top).is_none());
+/// ```
+#[derive(Debug)]
+pub struct PrefabLibrary<FT=(), WT=()> {
+    prefabs: HashMap<String, Prefab<FT, WT>>,
+}
+
+impl<FT: Default + Clone,
+     WT: Wall> Default for PrefabLibrary<FT, WT> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<FT: Default + Clone,
+     WT: Wall> PrefabLibrary<FT, WT> {
+    /// Creates an empty library
+    pub fn new() -> PrefabLibrary<FT, WT> {
+        PrefabLibrary {
+            prefabs: HashMap::new(),
+        }
+    }
+
+    /// Loads a library from a file (see the format above)
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<PrefabLibrary<FT, WT>, PrefabError> {
+        let mut text = String::new();
+        let mut file = File::open(path)?;
+        file.read_to_string(&mut text)?;
+        PrefabLibrary::parse(&text)
+    }
+
+    /// Parses a library (see the format above)
+    pub fn parse(text: &str) -> Result<PrefabLibrary<FT, WT>, PrefabError> {
+        let mut library = PrefabLibrary::new();
+        let mut lines = text.lines().enumerate();
+        while let Some((i, line)) = lines.next() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if !line.starts_with("prefab ") {
+                return Err(PrefabError::Syntax {
+                    line: i + 1,
+                    message: format!("expected 'prefab <name>', found '{}'", line),
+                });
+            }
+            let name = line["prefab ".len()..].trim().to_owned();
+            let mut grid = vec![];
+            // Line numbers of the lines of the grid
+            let mut numbers = vec![];
+            loop {
+                match lines.next() {
+                    Some((_, line)) if line.trim() == "end" => break,
+                    Some((j, line)) => {
+                        grid.push(line);
+                        numbers.push(j + 1);
+                    },
+                    None => return Err(PrefabError::Syntax {
+                        line: i + 1,
+                        message: format!("prefab '{}' has no 'end'", name),
+                    }),
+                }
+            }
+            let prefab = parse_grid(&dedent(&grid)).map_err(|(row, message)| PrefabError::Syntax {
+                line: row.map_or(i + 1, |row| numbers[row]),
+                message: format!("prefab '{}': {}", name, message),
+            })?;
+            library.insert(name, prefab);
+        }
+        Ok(library)
+    }
+
+    /// Adds a prefab to the library, replacing any prefab with the same name
+    pub fn insert(&mut self, name: String, prefab: Prefab<FT, WT>) -> &mut Self {
+        self.prefabs.insert(name, prefab);
+        self
+    }
+
+    /// Returns the prefab with this name, if any
+    pub fn get(&self, name: &str) -> Option<&Prefab<FT, WT>> {
+        self.prefabs.get(name)
+    }
+
+    /// Returns the names of the prefabs of the library
+    pub fn names(&self) -> Vec<&str> {
+        self.prefabs.keys().map(|name| name.as_str()).collect()
+    }
+}
+
+/// Removes the indentation common to all the lines of a grid, so prefabs can
+/// be indented in a file. Blank lines are ignored to compute it.
+fn dedent<'a>(grid: &[&'a str]) -> Vec<&'a str> {
+    let indent = grid.iter()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.chars().take_while(|c| c.is_whitespace()).count())
+        .min()
+        .unwrap_or(0);
+    grid.iter()
+        .map(|line| match line.char_indices().nth(indent) {
+            Some((i, _)) => &line[i..],
+            None => "",
+        })
+        .collect()
+}
+
+/// Parses the grid of a prefab (see `PrefabLibrary`). Errors come with the
+/// row of the grid where they are, or `None` if they are about the whole grid.
+fn parse_grid<FT: Default + Clone, WT: Wall>(grid: &[&str])
+                                             -> Result<Prefab<FT, WT>, (Option<usize>, String)> {
+    if grid.len() < 3 || grid.len() % 2 == 0 {
+        return Err((None, format!("grid must have an odd number of lines, at least 3 (found {})",
+                                  grid.len())));
+    }
+    let rows: Vec<Vec<char>> = grid.iter().map(|line| line.chars().collect()).collect();
+    // Width is given by the corners of the first line
+    let width = rows[0].iter().rposition(|&c| c == '+').unwrap_or(0) / 2;
+    if width == 0 {
+        return Err((Some(0), String::from("first line must contain the corners of the tiles")));
+    }
+    let depth = (rows.len() - 1) / 2;
+    // Trailing spaces may have been removed (or added)
+    let at = |i: usize, j: usize| *rows[j].get(i).unwrap_or(&' ');
+
+    let mut prefab = Prefab::new(Level::new(width, depth, 0.0));
+    for y in 0..depth {
+        for x in 0..width {
+            match at(2 * x + 1, 2 * y + 1) {
+                '.' => (),
+                '?' | ' ' => {
+                    prefab.set_used(x, y, false);
+                },
+                c if c.is_digit(10) => {
+                    prefab.level_mut().set_z(x, y, c.to_digit(10).unwrap() as f32);
+                },
+                c => return Err((Some(2 * y + 1), format!("invalid tile '{}' at ({}, {})", c, x, y))),
+            }
+            if at(2 * x, 2 * y + 1) == '|' {
+                prefab.level_mut().set_wall(x, y, WallPosition::Left, Some(WT::default()));
+            }
+            if at(2 * x + 2, 2 * y + 1) == '|' {
+                prefab.level_mut().set_wall(x, y, WallPosition::Right, Some(WT::default()));
+            }
+            if at(2 * x + 1, 2 * y) == '-' {
+                prefab.level_mut().set_wall(x, y, WallPosition::Bottom, Some(WT::default()));
+            }
+            if at(2 * x + 1, 2 * y + 2) == '-' {
+                prefab.level_mut().set_wall(x, y, WallPosition::Top, Some(WT::default()));
+            }
+        }
+    }
+    Ok(prefab)
+}
+
+
+#[test]
+fn prefab_rotate_mirror() {
+    let mut level: Level = Level::new(3, 2, 0.0);
+    level.set_z(2, 0, 1.0);
+    level.set_wall(2, 0, WallPosition::Right, Some(()));
+    let mut prefab = Prefab::new(level);
+    prefab.set_used(0, 1, false);
+
+    let rotated = prefab.rotated(1);
+    assert_eq!(rotated.level().z(1, 2), 1.0);
+    assert!(rotated.level().wall(1, 2, WallPosition::Top).is_some());
+    assert!(!rotated.is_used(0, 0));
+
+    assert_eq!(prefab.rotated(4).level().z(2, 0), 1.0);
+    assert_eq!(prefab.rotated(2).level().z(0, 1), 1.0);
+
+    let mirrored = prefab.mirrored();
+    assert_eq!(mirrored.level().z(0, 0), 1.0);
+    assert!(mirrored.level().wall(0, 0, WallPosition::Left).is_some());
+    assert!(!mirrored.is_used(2, 1));
+}
+
+#[test]
+fn prefab_stamp() {
+    let mut room: Level = Level::new(2, 2, 2.0);
+    room.add_border_walls(());
+    let mut prefab = Prefab::new(room);
+    prefab.set_used(1, 1, false);
+
+    let mut level: Level = Level::new(5, 5, 0.0);
+    level.set_z(4, 4, 3.0);
+    level.set_z(2, 3, 3.0);
+    assert!(prefab.fits(&level, (3, 3)));
+    // (4, 4) is a "don't care" tile
+    assert!(prefab.fits_where(&level, (3, 3), &|x, y| level.z(x, y) == 0.0));
+    assert!(!prefab.fits_where(&level, (2, 2), &|x, y| level.z(x, y) == 0.0));
+    assert!(!prefab.fits(&level, (4, 3)));
+
+    prefab.stamp(&mut level, (3, 3));
+    assert_eq!(level.z(3, 3), 2.0);
+    assert_eq!(level.z(4, 4), 3.0);
+    assert!(level.wall(3, 4, WallPosition::Top).is_some());
+    assert!(level.wall(4, 4, WallPosition::Top).is_none());
+}
+
+#[test]
+fn prefab_library() {
+    let library: PrefabLibrary = PrefabLibrary::parse("
+# Two prefabs
+prefab a
++-+-+
+|1 ?
++ + +
+|. .|
++-+-+
+end
+
+prefab b
++ +
+|.|
++-+
+end
+").unwrap();
+    assert_eq!(library.names().len(), 2);
+    let a = library.get("a").unwrap();
+    assert_eq!((a.width(), a.depth()), (2, 2));
+    assert_eq!(a.level().z(0, 0), 1.0);
+    assert!(!a.is_used(1, 0));
+    assert!(a.level().wall(0, 0, WallPosition::Bottom).is_some());
+    assert!(a.level().wall(1, 1, WallPosition::Right).is_some());
+    assert!(a.level().wall(1, 0, WallPosition::Right).is_none());
+
+    // Indented blocks
+    let indented: PrefabLibrary = PrefabLibrary::parse("
+    prefab a
+        +-+-+
+        |1 ?
+        + + +
+        |. .|
+        +-+-+
+    end
+").unwrap();
+    let b = indented.get("a").unwrap();
+    assert_eq!((b.width(), b.depth()), (2, 2));
+    assert_eq!(b.level().z(0, 0), 1.0);
+    assert!(!b.is_used(1, 0));
+    assert!(b.level().wall(0, 0, WallPosition::Left).is_some());
+    assert!(b.level().wall(1, 1, WallPosition::Right).is_some());
+    assert!(b.level().wall(1, 0, WallPosition::Right).is_none());
+
+    match PrefabLibrary::<(), ()>::parse("prefab c\n+-+\n|x|\n+-+\nend") {
+        Err(PrefabError::Syntax { line, .. }) => assert_eq!(line, 3),
+        _ => panic!("invalid tile should be an error"),
+    }
+    match PrefabLibrary::<(), ()>::parse("\nprefab c\n+-+\n|.|\nend") {
+        Err(PrefabError::Syntax { line, .. }) => assert_eq!(line, 2),
+        _ => panic!("even number of lines should be an error"),
+    }
+}
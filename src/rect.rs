@@ -0,0 +1,57 @@
+// (C) 2017, Élisabeth Henry
+//
+// Licensed under either of
+// 
+// Apache License, Version 2.0: http://www.apache.org/licenses/LICENSE-2.0
+// MIT license: http://opensource.org/licenses/MIT
+// at your option.
+//
+// Unless you explicitly state otherwise, any contribution intentionally submitted
+// for inclusion in the work by you, as defined in the Apache-2.0 license, shall be
+// dual licensed as above, without any additional terms or conditions.
+
+/// A rectangular region of tiles, from (x, y) to (x + width - 1, y + depth - 1)
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Rect {
+    /// x coordinate of the bottom left tile
+    pub x: usize,
+    /// y coordinate of the bottom left tile
+    pub y: usize,
+    /// Number of tiles along the x axis
+    pub width: usize,
+    /// Number of tiles along the y axis
+    pub depth: usize,
+}
+
+impl Rect {
+    /// Creates a new rectangle
+    pub fn new(x: usize, y: usize, width: usize, depth: usize) -> Rect {
+        Rect {
+            x,
+            y,
+            width,
+            depth,
+        }
+    }
+
+    /// Returns true if the tile (x, y) is inside the rectangle
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        x >= self.x && y >= self.y && x < self.x + self.width && y < self.y + self.depth
+    }
+}
+
+/// Part of a level that stays in place when it is resized
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Anchor {
+    /// Tile (0, 0) stays in place: tiles are added or removed at the top and
+    /// at the right
+    BottomLeft,
+    /// Bottom right corner stays in place
+    BottomRight,
+    /// Top left corner stays in place
+    TopLeft,
+    /// Top right corner stays in place
+    TopRight,
+    /// Tiles are added or removed evenly on all sides
+    Center,
+}
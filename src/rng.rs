@@ -0,0 +1,81 @@
+// (C) 2017, Élisabeth Henry
+//
+// Licensed under either of
+// 
+// Apache License, Version 2.0: http://www.apache.org/licenses/LICENSE-2.0
+// MIT license: http://opensource.org/licenses/MIT
+// at your option.
+//
+// Unless you explicitly state otherwise, any contribution intentionally submitted
+// for inclusion in the work by you, as defined in the Apache-2.0 license, shall be
+// dual licensed as above, without any additional terms or conditions.
+
+/// Small pseudo-random number generator (xorshift64*), so generators give the
+/// same output for a given seed on every platform.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    /// Creates a new generator from a seed
+    pub fn new(seed: u64) -> Rng {
+        // Scramble the seed (splitmix64) so close seeds give different sequences,
+        // and make sure the state is never zero
+        let mut z = seed.wrapping_add(0x9E3779B97F4A7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z = z ^ (z >> 31);
+        Rng {
+            state: if z == 0 { 0x9E3779B97F4A7C15 } else { z },
+        }
+    }
+
+    /// Returns the next random number
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545F4914F6CDD1D)
+    }
+
+    /// Returns a number between `min` (included) and `max` (excluded)
+    pub fn range(&mut self, min: usize, max: usize) -> usize {
+        debug_assert!(min < max, "range must not be empty");
+        min + (self.next_u64() % (max - min) as u64) as usize
+    }
+
+    /// Returns a number between 0.0 (included) and 1.0 (excluded)
+    pub fn float(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// Returns true with the given probability
+    pub fn chance(&mut self, probability: f32) -> bool {
+        self.float() < probability
+    }
+}
+
+
+#[test]
+fn rng_deterministic() {
+    let mut a = Rng::new(42);
+    let mut b = Rng::new(42);
+    let mut c = Rng::new(43);
+    let a: Vec<_> = (0..10).map(|_| a.next_u64()).collect();
+    let b: Vec<_> = (0..10).map(|_| b.next_u64()).collect();
+    let c: Vec<_> = (0..10).map(|_| c.next_u64()).collect();
+    assert_eq!(a, b);
+    assert!(a != c);
+}
+
+#[test]
+fn rng_ranges() {
+    let mut rng = Rng::new(0);
+    for _ in 0..1000 {
+        let n = rng.range(3, 7);
+        assert!((3..7).contains(&n));
+        let f = rng.float();
+        assert!((0.0..1.0).contains(&f));
+    }
+}
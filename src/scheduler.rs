@@ -0,0 +1,292 @@
+// (C) 2017, Élisabeth Henry
+//
+// Licensed under either of
+// 
+// Apache License, Version 2.0: http://www.apache.org/licenses/LICENSE-2.0
+// MIT license: http://opensource.org/licenses/MIT
+// at your option.
+//
+// Unless you explicitly state otherwise, any contribution intentionally submitted
+// for inclusion in the work by you, as defined in the Apache-2.0 license, shall be
+// dual licensed as above, without any additional terms or conditions.
+
+use entity::Entities;
+use entity::EntityId;
+
+/// Energy an actor needs to act
+const THRESHOLD: i64 = 100;
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Serialize, Deserialize))]
+struct Actor<K> {
+    key: K,
+    speed: u32,
+    energy: i64,
+}
+
+/// Energy-based turn scheduler.
+///
+/// At each tick, every actor gains as much energy as its speed. An actor can
+/// act when it has at least 100 energy, and acting costs energy (a standard
+/// action costs 100). So an actor with speed 100 acts once per tick, one with
+/// speed 50 every other tick, etc.
+///
+/// Actors are identified by a key, typically an `EntityId`. The order only
+/// depends on the calls that were made, so the scheduler is deterministic; it
+/// can be serialized with the `serialization` feature.
+///
+/// # Example
+///
+/// ```
+/// use isometric::Scheduler;
+///
+/// let mut scheduler = Scheduler::new();
+/// scheduler.insert("hero", 100);
+/// scheduler.insert("snail", 50);
+///
+/// let mut turns = vec![];
+/// for _ in 0..6 {
+///     let actor = scheduler.next_actor().unwrap();
+///     turns.push(actor);
+///     scheduler.spend(actor, 100);
+/// }
+/// assert_eq!(turns, ["hero", "hero", "snail", "hero", "hero", "snail"]);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Serialize, Deserialize))]
+pub struct Scheduler<K> {
+    // In insertion order, which breaks ties
+    actors: Vec<Actor<K>>,
+    time: u64,
+}
+
+impl<K: Copy + PartialEq> Default for Scheduler<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Copy + PartialEq> Scheduler<K> {
+    /// Creates an empty scheduler
+    pub fn new() -> Scheduler<K> {
+        Scheduler {
+            actors: vec![],
+            time: 0,
+        }
+    }
+
+    /// Returns the number of ticks elapsed since the scheduler was created
+    pub fn time(&self) -> u64 {
+        self.time
+    }
+
+    /// Returns the number of actors
+    pub fn len(&self) -> usize {
+        self.actors.len()
+    }
+
+    /// Returns true if there are no actors
+    pub fn is_empty(&self) -> bool {
+        self.actors.is_empty()
+    }
+
+    fn actor(&self, key: K) -> Option<&Actor<K>> {
+        self.actors.iter().find(|actor| actor.key == key)
+    }
+
+    fn actor_mut(&mut self, key: K) -> Option<&mut Actor<K>> {
+        self.actors.iter_mut().find(|actor| actor.key == key)
+    }
+
+    /// Adds an actor with no energy. If it is already there, its speed is
+    /// updated instead.
+    pub fn insert(&mut self, key: K, speed: u32) -> &mut Self {
+        if let Some(actor) = self.actor_mut(key) {
+            actor.speed = speed;
+            return self;
+        }
+        self.actors.push(Actor {
+            key,
+            speed,
+            energy: 0,
+        });
+        self
+    }
+
+    /// Removes an actor. Returns false if it wasn't there.
+    pub fn remove(&mut self, key: K) -> bool {
+        let len = self.actors.len();
+        self.actors.retain(|actor| actor.key != key);
+        self.actors.len() != len
+    }
+
+    /// Only keeps the actors for which `f` returns true
+    pub fn retain<F: Fn(K) -> bool>(&mut self, f: F) {
+        self.actors.retain(|actor| f(actor.key));
+    }
+
+    /// Returns true if the actor is in the scheduler
+    pub fn contains(&self, key: K) -> bool {
+        self.actor(key).is_some()
+    }
+
+    /// Returns the speed of an actor
+    pub fn speed(&self, key: K) -> Option<u32> {
+        self.actor(key).map(|actor| actor.speed)
+    }
+
+    /// Returns the energy of an actor
+    pub fn energy(&self, key: K) -> Option<i64> {
+        self.actor(key).map(|actor| actor.energy)
+    }
+
+    /// Removes energy from an actor, after it did an action of the given cost.
+    /// Returns false if the actor isn't there.
+    pub fn spend(&mut self, key: K, cost: u32) -> bool {
+        match self.actor_mut(key) {
+            Some(actor) => {
+                actor.energy -= cost as i64;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Delays an actor by the given number of ticks, as if it had been
+    /// stunned for that long. Returns false if the actor isn't there.
+    pub fn delay(&mut self, key: K, ticks: u32) -> bool {
+        match self.actor_mut(key) {
+            Some(actor) => {
+                actor.energy -= actor.speed as i64 * ticks as i64;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Returns the actor that must act now, letting time pass if nobody can.
+    ///
+    /// The actor with the most energy acts first (ties are broken by order of
+    /// insertion). Its energy is only removed by `spend`: if it isn't, the same
+    /// actor is returned again. Returns `None` if no actor can ever act.
+    pub fn next_actor(&mut self) -> Option<K> {
+        let mut wait = None;
+        for actor in &self.actors {
+            if actor.speed == 0 && actor.energy < THRESHOLD {
+                continue;
+            }
+            let missing = THRESHOLD - actor.energy;
+            let ticks = if missing <= 0 {
+                0
+            } else {
+                (missing + actor.speed as i64 - 1) / actor.speed as i64
+            };
+            wait = Some(wait.map_or(ticks, |wait: i64| if ticks < wait { ticks } else { wait }));
+        }
+        let wait = wait?;
+        if wait > 0 {
+            self.time += wait as u64;
+            for actor in &mut self.actors {
+                actor.energy += actor.speed as i64 * wait;
+            }
+        }
+
+        let mut best: Option<&Actor<K>> = None;
+        for actor in &self.actors {
+            if actor.energy >= THRESHOLD && best.map_or(true, |best| actor.energy > best.energy) {
+                best = Some(actor);
+            }
+        }
+        best.map(|actor| actor.key)
+    }
+}
+
+impl Scheduler<EntityId> {
+    /// Same as `next_actor`, but first drops the actors whose entity was removed
+    pub fn next_entity<T>(&mut self, entities: &Entities<T>) -> Option<EntityId> {
+        self.retain(|id| entities.contains(id));
+        self.next_actor()
+    }
+}
+
+
+#[test]
+fn scheduler_costs_and_delays() {
+    let mut scheduler = Scheduler::new();
+    scheduler.insert(0, 100).insert(1, 100).insert(2, 0);
+
+    // Ties are broken by order of insertion
+    assert_eq!(scheduler.next_actor(), Some(0));
+    assert_eq!(scheduler.next_actor(), Some(0));
+    assert_eq!(scheduler.time(), 1);
+    scheduler.spend(0, 50);
+    assert_eq!(scheduler.next_actor(), Some(1));
+    scheduler.spend(1, 200);
+    // 0 has 50 energy left, so it acts next tick before 1
+    assert_eq!(scheduler.next_actor(), Some(0));
+    assert_eq!(scheduler.time(), 2);
+    assert_eq!(scheduler.energy(0), Some(150));
+
+    scheduler.spend(0, 150);
+    scheduler.delay(0, 3);
+    // 1 is back to 0 energy: it acts at time 3, while 0 waits until time 6
+    assert_eq!(scheduler.next_actor(), Some(1));
+    assert_eq!(scheduler.time(), 3);
+    assert!(scheduler.remove(1));
+    assert!(!scheduler.remove(1));
+    assert_eq!(scheduler.next_actor(), Some(0));
+    assert_eq!(scheduler.time(), 6);
+
+    assert!(scheduler.remove(0));
+    // Actors with no speed never act
+    assert_eq!(scheduler.next_actor(), None);
+}
+
+#[test]
+fn scheduler_entities() {
+    let mut entities = Entities::new(5, 5);
+    let a = entities.insert((0, 0), true, ()).unwrap();
+    let b = entities.insert((1, 0), true, ()).unwrap();
+    let mut scheduler = Scheduler::new();
+    scheduler.insert(a, 100).insert(b, 100);
+
+    assert_eq!(scheduler.next_entity(&entities), Some(a));
+    entities.remove(a);
+    assert_eq!(scheduler.next_entity(&entities), Some(b));
+    assert!(!scheduler.contains(a));
+
+    // Same calls, same result
+    let copy = scheduler.clone();
+    scheduler.spend(b, 100);
+    let mut other = copy.clone();
+    other.spend(b, 100);
+    assert_eq!(scheduler.next_actor(), other.next_actor());
+    assert_eq!(scheduler, other);
+}
+
+#[cfg(feature = "serialization")]
+#[test]
+fn scheduler_serialization() {
+    use serde_json;
+
+    let mut entities = Entities::new(5, 5);
+    let a = entities.insert((0, 0), true, ()).unwrap();
+    let b = entities.insert((1, 0), true, ()).unwrap();
+    let mut scheduler = Scheduler::new();
+    scheduler.insert(a, 100).insert(b, 70);
+    let actor = scheduler.next_actor().unwrap();
+    scheduler.spend(actor, 100);
+    scheduler.delay(b, 2);
+
+    let json = serde_json::to_string(&scheduler).unwrap();
+    let mut loaded: Scheduler<EntityId> = serde_json::from_str(&json).unwrap();
+    assert_eq!(loaded, scheduler);
+    // A restored game goes on exactly the same way
+    for _ in 0..10 {
+        let actor = scheduler.next_actor();
+        assert_eq!(loaded.next_actor(), actor);
+        scheduler.spend(actor.unwrap(), 100);
+        loaded.spend(actor.unwrap(), 100);
+    }
+    assert_eq!(loaded.time(), scheduler.time());
+}
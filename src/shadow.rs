@@ -0,0 +1,229 @@
+// (C) 2017, Élisabeth Henry
+//
+// Licensed under either of
+// 
+// Apache License, Version 2.0: http://www.apache.org/licenses/LICENSE-2.0
+// MIT license: http://opensource.org/licenses/MIT
+// at your option.
+//
+// Unless you explicitly state otherwise, any contribution intentionally submitted
+// for inclusion in the work by you, as defined in the Apache-2.0 license, shall be
+// dual licensed as above, without any additional terms or conditions.
+
+use level::Level;
+use wall::Wall;
+use wall::WallPosition;
+
+use std::default::Default;
+use std::f32;
+
+/// Length of a step when marching towards the light
+const STEP: f32 = 0.1;
+
+/// Shadows cast by the terrain and the walls of a level for a directional light.
+///
+/// Shadows are computed on the CPU, once per tile: a tile is in the shadow if a
+/// ray going from its center towards the light hits a higher tile or a wall.
+#[derive(Debug, Clone)]
+pub struct ShadowMap {
+    width: usize,
+    depth: usize,
+    shadowed: Vec<bool>,
+}
+
+impl ShadowMap {
+    /// Computes the shadows of a level for a light coming from `sun_direction`
+    /// (e.g. `Lighting::sun_direction()`).
+    ///
+    /// If the light comes from below the horizon, every tile is in the shadow.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use isometric::{Level, ShadowMap};
+    ///
+    /// let mut level: Level = Level::new(10, 10, 0.0);
+    /// level.set_z(5, 5, 3.0);
+    ///
+    /// // Light comes from the right, 45° above the horizon
+    /// let shadows = ShadowMap::new(&level, [1.0, 0.0, 1.0]);
+    /// assert_eq!(shadows.is_shadowed(4, 5), true);
+    /// assert_eq!(shadows.is_shadowed(6, 5), false);
+    /// ```
+    pub fn new<FT: Default + Clone, WT: Wall>(level: &Level<FT, WT>, sun_direction: [f32; 3]) -> ShadowMap {
+        let width = level.width();
+        let depth = level.depth();
+        let mut shadowed = vec![false; width * depth];
+
+        let horizontal = (sun_direction[0] * sun_direction[0] + sun_direction[1] * sun_direction[1]).sqrt();
+        if sun_direction[2] <= 0.0 {
+            // Night, or at least the sun is below the horizon
+            for s in &mut shadowed {
+                *s = true;
+            }
+        } else if horizontal > 1e-6 {
+            // If the light comes from right above there are no shadows, else march
+            // towards the light for each tile
+            let dir = [sun_direction[0] / horizontal, sun_direction[1] / horizontal];
+            let slope = sun_direction[2] / horizontal;
+
+            // Highest obstacle of the level, so we know when to stop marching
+            let mut max_z = f32::MIN;
+            for x in 0..width {
+                for y in 0..depth {
+                    let z = level.z(x, y);
+                    let mut top = z;
+                    for &position in &[WallPosition::Left, WallPosition::Right,
+                                       WallPosition::Top, WallPosition::Bottom] {
+                        if let Some(data) = level.wall(x, y, position) {
+                            if !data.is_cliff() && !data.is_border() {
+                                top = top.max(z + data.height());
+                            }
+                        }
+                    }
+                    max_z = max_z.max(top);
+                }
+            }
+
+            for x in 0..width {
+                for y in 0..depth {
+                    shadowed[y * width + x] = is_blocked(level, (x, y), dir, slope, max_z);
+                }
+            }
+        }
+
+        ShadowMap {
+            width,
+            depth,
+            shadowed,
+        }
+    }
+
+    /// Returns true if the tile is in the shadow
+    ///
+    /// x must be strictly less than level's width and
+    /// y must be strictly less than level's height
+    pub fn is_shadowed(&self, x: usize, y: usize) -> bool {
+        debug_assert!(x < self.width && y < self.depth, "x and y must be in level's bounds");
+        self.shadowed[y * self.width + x]
+    }
+}
+
+/// Returns the height of the top of the wall between two adjacent tiles, if any.
+///
+/// Border walls don't cast shadows, since they go downward.
+fn wall_top<FT: Default + Clone, WT: Wall>(level: &Level<FT, WT>,
+                                           from: (usize, usize),
+                                           to: (usize, usize)) -> Option<f32> {
+    let position = if to.0 > from.0 {
+        WallPosition::Right
+    } else if to.0 < from.0 {
+        WallPosition::Left
+    } else if to.1 > from.1 {
+        WallPosition::Top
+    } else {
+        WallPosition::Bottom
+    };
+    match *level.wall(from.0, from.1, position) {
+        Some(ref data) if !data.is_border() => {
+            let z = level.z(from.0, from.1).max(level.z(to.0, to.1));
+            if data.is_cliff() {
+                Some(z)
+            } else {
+                Some(z + data.height())
+            }
+        },
+        _ => None,
+    }
+}
+
+/// Marches from the center of a tile towards the light and returns true if
+/// something blocks the ray.
+fn is_blocked<FT: Default + Clone, WT: Wall>(level: &Level<FT, WT>,
+                                             pos: (usize, usize),
+                                             dir: [f32; 2],
+                                             slope: f32,
+                                             max_z: f32) -> bool {
+    let start_x = pos.0 as f32 + 0.5;
+    let start_y = pos.1 as f32 + 0.5;
+    let start_z = level.z(pos.0, pos.1);
+    let mut prev = pos;
+    let mut t = 0.0;
+
+    loop {
+        t += STEP;
+        let ray_z = start_z + slope * t;
+        if ray_z > max_z {
+            return false;
+        }
+        let x = start_x + dir[0] * t;
+        let y = start_y + dir[1] * t;
+        if x < 0.0 || y < 0.0 {
+            return false;
+        }
+        let current = (x as usize, y as usize);
+        if current.0 >= level.width() || current.1 >= level.depth() {
+            return false;
+        }
+        if current == prev {
+            continue;
+        }
+
+        // We went through one edge, or two if it's a diagonal step
+        if current.0 != prev.0 && current.1 != prev.1 {
+            let intermediate = (current.0, prev.1);
+            if wall_top(level, prev, intermediate).map_or(false, |top| top > ray_z) ||
+                wall_top(level, intermediate, current).map_or(false, |top| top > ray_z) {
+                return true;
+            }
+        } else if wall_top(level, prev, current).map_or(false, |top| top > ray_z) {
+            return true;
+        }
+        if level.z(current.0, current.1) > ray_z {
+            return true;
+        }
+        prev = current;
+    }
+}
+
+
+#[test]
+fn flat_no_shadows() {
+    let level: Level = Level::new(10, 10, 0.0);
+    let shadows = ShadowMap::new(&level, [1.0, 1.0, 1.0]);
+    for x in 0..10 {
+        for y in 0..10 {
+            assert!(!shadows.is_shadowed(x, y));
+        }
+    }
+}
+
+#[test]
+fn night_shadows() {
+    let level: Level = Level::new(10, 10, 0.0);
+    let shadows = ShadowMap::new(&level, [1.0, 0.0, -0.5]);
+    assert!(shadows.is_shadowed(3, 3));
+}
+
+#[test]
+fn terrain_shadows() {
+    let mut level: Level = Level::new(10, 10, 0.0);
+    level.set_z(5, 5, 3.0);
+    let shadows = ShadowMap::new(&level, [1.0, 0.0, 1.0]);
+    assert!(shadows.is_shadowed(4, 5));
+    assert!(shadows.is_shadowed(3, 5));
+    assert!(!shadows.is_shadowed(1, 5));
+    assert!(!shadows.is_shadowed(5, 5));
+    assert!(!shadows.is_shadowed(6, 5));
+    assert!(!shadows.is_shadowed(4, 6));
+}
+
+#[test]
+fn wall_shadows() {
+    let mut level: Level = Level::new(10, 10, 0.0);
+    level.set_wall(2, 2, WallPosition::Right, Some(()));
+    let shadows = ShadowMap::new(&level, [1.0, 0.0, 1.0]);
+    assert!(shadows.is_shadowed(2, 2));
+    assert!(!shadows.is_shadowed(1, 2));
+    assert!(!shadows.is_shadowed(3, 2));
+}
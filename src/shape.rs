@@ -0,0 +1,146 @@
+// (C) 2017, Élisabeth Henry
+//
+// Licensed under either of
+// 
+// Apache License, Version 2.0: http://www.apache.org/licenses/LICENSE-2.0
+// MIT license: http://opensource.org/licenses/MIT
+// at your option.
+//
+// Unless you explicitly state otherwise, any contribution intentionally submitted
+// for inclusion in the work by you, as defined in the Apache-2.0 license, shall be
+// dual licensed as above, without any additional terms or conditions.
+
+use wall::WallPosition;
+
+/// Represents a corner of a tile
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Corner {
+    /// Corner (x, y)
+    BottomLeft,
+    /// Corner (x + 1, y)
+    BottomRight,
+    /// Corner (x, y + 1)
+    TopLeft,
+    /// Corner (x + 1, y + 1)
+    TopRight,
+}
+
+impl Corner {
+    /// Returns the index of the corner in the array returned by
+    /// `Level::corner_heights`
+    pub fn index(&self) -> usize {
+        match *self {
+            Corner::BottomLeft => 0,
+            Corner::BottomRight => 1,
+            Corner::TopLeft => 2,
+            Corner::TopRight => 3,
+        }
+    }
+
+    /// Returns this corner after a quarter turn of its tile, counterclockwise
+    pub fn rotated(&self) -> Corner {
+        match *self {
+            Corner::BottomLeft => Corner::BottomRight,
+            Corner::BottomRight => Corner::TopRight,
+            Corner::TopRight => Corner::TopLeft,
+            Corner::TopLeft => Corner::BottomLeft,
+        }
+    }
+
+    /// Returns this corner if its tile was mirrored along the x axis
+    pub fn mirrored(&self) -> Corner {
+        match *self {
+            Corner::BottomLeft => Corner::BottomRight,
+            Corner::BottomRight => Corner::BottomLeft,
+            Corner::TopRight => Corner::TopLeft,
+            Corner::TopLeft => Corner::TopRight,
+        }
+    }
+}
+
+/// Shape of the floor of a tile.
+///
+/// Except for `Smooth`, the height of the corners of the tile are entirely
+/// determined by its shape and its z, which is the height of its lowest corners.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TileShape {
+    /// Height of each corner is averaged from the neighbouring tiles (default)
+    Smooth,
+    /// All corners are at the tile's height
+    Flat,
+    /// Ramp rising by the given height towards a side
+    Ramp(WallPosition, f32),
+    /// Only the given corner is raised by the given height
+    OuterCorner(Corner, f32),
+    /// All corners but the opposite of the given one are raised by the given
+    /// height
+    InnerCorner(Corner, f32),
+}
+
+impl TileShape {
+    /// Returns the height of the corners of a tile of this shape, relatively to
+    /// its z, or `None` if it is `Smooth`.
+    ///
+    /// Corners are ordered as in `Corner::index`.
+    pub fn corner_offsets(&self) -> Option<[f32; 4]> {
+        match *self {
+            TileShape::Smooth => None,
+            TileShape::Flat => Some([0.0; 4]),
+            TileShape::Ramp(side, rise) => Some(match side {
+                WallPosition::Bottom => [rise, rise, 0.0, 0.0],
+                WallPosition::Top => [0.0, 0.0, rise, rise],
+                WallPosition::Left => [rise, 0.0, rise, 0.0],
+                WallPosition::Right => [0.0, rise, 0.0, rise],
+            }),
+            TileShape::OuterCorner(corner, rise) => {
+                let mut res = [0.0; 4];
+                res[corner.index()] = rise;
+                Some(res)
+            },
+            TileShape::InnerCorner(corner, rise) => {
+                let mut res = [rise; 4];
+                res[3 - corner.index()] = 0.0;
+                Some(res)
+            },
+        }
+    }
+
+    /// Returns this shape after a quarter turn of its tile, counterclockwise
+    pub fn rotated(&self) -> TileShape {
+        match *self {
+            TileShape::Ramp(side, rise) => TileShape::Ramp(side.rotated(), rise),
+            TileShape::OuterCorner(corner, rise) => TileShape::OuterCorner(corner.rotated(), rise),
+            TileShape::InnerCorner(corner, rise) => TileShape::InnerCorner(corner.rotated(), rise),
+            other => other,
+        }
+    }
+
+    /// Returns this shape if its tile was mirrored along the x axis
+    pub fn mirrored(&self) -> TileShape {
+        match *self {
+            TileShape::Ramp(side, rise) => TileShape::Ramp(side.mirrored(), rise),
+            TileShape::OuterCorner(corner, rise) => TileShape::OuterCorner(corner.mirrored(), rise),
+            TileShape::InnerCorner(corner, rise) => TileShape::InnerCorner(corner.mirrored(), rise),
+            other => other,
+        }
+    }
+}
+
+impl Default for TileShape {
+    fn default() -> Self {
+        TileShape::Smooth
+    }
+}
+
+
+#[test]
+fn shape_offsets() {
+    assert_eq!(TileShape::Smooth.corner_offsets(), None);
+    assert_eq!(TileShape::Ramp(WallPosition::Right, 1.0).corner_offsets(),
+               Some([0.0, 1.0, 0.0, 1.0]));
+    assert_eq!(TileShape::OuterCorner(Corner::TopLeft, 0.5).corner_offsets(),
+               Some([0.0, 0.0, 0.5, 0.0]));
+    // Opposite of bottom left is top right
+    assert_eq!(TileShape::InnerCorner(Corner::BottomLeft, 0.5).corner_offsets(),
+               Some([0.5, 0.5, 0.5, 0.0]));
+}
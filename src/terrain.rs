@@ -0,0 +1,292 @@
+// (C) 2017, Élisabeth Henry
+//
+// Licensed under either of
+// 
+// Apache License, Version 2.0: http://www.apache.org/licenses/LICENSE-2.0
+// MIT license: http://opensource.org/licenses/MIT
+// at your option.
+//
+// Unless you explicitly state otherwise, any contribution intentionally submitted
+// for inclusion in the work by you, as defined in the Apache-2.0 license, shall be
+// dual licensed as above, without any additional terms or conditions.
+
+use level::Level;
+use wall::Wall;
+use shape::TileShape;
+use noise;
+use noise::NoiseKind;
+
+use std::default::Default;
+
+/// Shapes the whole terrain, on top of the noise
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TerrainMask {
+    /// Noise only
+    None,
+    /// Terrain goes down to the lowest height on the borders of the level
+    Island,
+    /// Terrain goes down to the lowest height along the middle of the level
+    /// (a line parallel to the y axis)
+    Valley,
+}
+
+/// Generates terrain from fractal noise.
+///
+/// # Example
+///
+/// ```
+/// use isometric::{TerrainGenerator, TerrainMask};
+///
+/// let mut generator = TerrainGenerator::new(64, 64, 3);
+/// generator.set_heights(0.0, 6.0)
+///     .set_terraces(Some(1.0))
+///     .set_mask(TerrainMask::Island)
+///     .set_cliff_threshold(Some(2.0));
+/// let level = generator.generate::<(), ()>(());
+///
+/// // Borders of an island are at sea level
+/// assert_eq!(level.z(0, 0), 0.0);
+/// // Terraces are at round heights
+/// assert_eq!(level.z(32, 32), level.z(32, 32).floor());
+/// ```
+#[derive(Debug, Clone)]
+pub struct TerrainGenerator {
+    width: usize,
+    depth: usize,
+    seed: u64,
+    noise: NoiseKind,
+    scale: f32,
+    octaves: usize,
+    persistence: f32,
+    min_z: f32,
+    max_z: f32,
+    terraces: Option<f32>,
+    mask: TerrainMask,
+    cliff_threshold: Option<f32>,
+}
+
+impl TerrainGenerator {
+    /// Creates a new generator for levels of the given dimensions.
+    ///
+    /// By default, it uses 4 octaves of Perlin noise with a persistence of 0.5
+    /// and features of about 16 tiles, heights go from 0.0 to 4.0, and there
+    /// are no terraces, mask nor cliff walls.
+    pub fn new(width: usize, depth: usize, seed: u64) -> TerrainGenerator {
+        TerrainGenerator {
+            width,
+            depth,
+            seed,
+            noise: NoiseKind::Perlin,
+            scale: 16.0,
+            octaves: 4,
+            persistence: 0.5,
+            min_z: 0.0,
+            max_z: 4.0,
+            terraces: None,
+            mask: TerrainMask::None,
+            cliff_threshold: None,
+        }
+    }
+
+    /// Returns the seed
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Sets the seed
+    pub fn set_seed(&mut self, seed: u64) -> &mut Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Returns the kind of noise
+    pub fn noise(&self) -> NoiseKind {
+        self.noise
+    }
+
+    /// Sets the kind of noise
+    pub fn set_noise(&mut self, noise: NoiseKind) -> &mut Self {
+        self.noise = noise;
+        self
+    }
+
+    /// Returns the size, in tiles, of the largest features of the terrain
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    /// Sets the size, in tiles, of the largest features of the terrain
+    pub fn set_scale(&mut self, scale: f32) -> &mut Self {
+        debug_assert!(scale > 0.0, "scale must be positive");
+        self.scale = scale;
+        self
+    }
+
+    /// Returns the number of octaves and the persistence of the noise
+    pub fn octaves(&self) -> (usize, f32) {
+        (self.octaves, self.persistence)
+    }
+
+    /// Sets the number of octaves of noise (each one adds smaller details), and
+    /// the persistence (how much each octave counts compared to the previous one)
+    pub fn set_octaves(&mut self, octaves: usize, persistence: f32) -> &mut Self {
+        self.octaves = octaves;
+        self.persistence = persistence;
+        self
+    }
+
+    /// Returns the lowest and highest possible heights
+    pub fn heights(&self) -> (f32, f32) {
+        (self.min_z, self.max_z)
+    }
+
+    /// Sets the lowest and highest possible heights
+    pub fn set_heights(&mut self, min_z: f32, max_z: f32) -> &mut Self {
+        self.min_z = min_z;
+        self.max_z = max_z;
+        self
+    }
+
+    /// Returns the height of terraces, if heights are quantized
+    pub fn terraces(&self) -> Option<f32> {
+        self.terraces
+    }
+
+    /// Sets the height of terraces. If set, heights are rounded down to a
+    /// multiple of it (above the lowest height) and tiles are flat.
+    pub fn set_terraces(&mut self, terraces: Option<f32>) -> &mut Self {
+        self.terraces = terraces;
+        self
+    }
+
+    /// Returns the mask applied to the terrain
+    pub fn mask(&self) -> TerrainMask {
+        self.mask
+    }
+
+    /// Sets the mask applied to the terrain
+    pub fn set_mask(&mut self, mask: TerrainMask) -> &mut Self {
+        self.mask = mask;
+        self
+    }
+
+    /// Returns the threshold above which cliff walls are added
+    pub fn cliff_threshold(&self) -> Option<f32> {
+        self.cliff_threshold
+    }
+
+    /// Sets the threshold above which cliff walls are added (see
+    /// `Level::add_cliff_walls`), or `None` to add none
+    pub fn set_cliff_threshold(&mut self, threshold: Option<f32>) -> &mut Self {
+        self.cliff_threshold = threshold;
+        self
+    }
+
+    /// Returns the height of tile (x, y)
+    pub fn z(&self, x: usize, y: usize) -> f32 {
+        let mut h = noise::fractal(self.noise,
+                                   x as f32 / self.scale,
+                                   y as f32 / self.scale,
+                                   self.seed,
+                                   self.octaves,
+                                   self.persistence);
+        // Distance to the center (or the middle line), 0.0 to 1.0
+        let dx = ((x as f32 + 0.5) / self.width as f32 * 2.0 - 1.0).abs();
+        let dy = ((y as f32 + 0.5) / self.depth as f32 * 2.0 - 1.0).abs();
+        h *= match self.mask {
+            TerrainMask::None => 1.0,
+            TerrainMask::Island => {
+                let d = dx.max(dy);
+                (1.0 - d * d * d).max(0.0)
+            },
+            TerrainMask::Valley => dx.sqrt(),
+        };
+        let mut z = self.min_z + h * (self.max_z - self.min_z);
+        if let Some(step) = self.terraces {
+            z = self.min_z + ((z - self.min_z) / step).floor() * step;
+        }
+        z
+    }
+
+    /// Sets the heights of an existing level, which must have the same
+    /// dimensions as the generator
+    pub fn apply<FT: Default + Clone, WT: Wall>(&self, level: &mut Level<FT, WT>, cliff: WT) {
+        debug_assert!(level.width() == self.width && level.depth() == self.depth,
+                      "level must have the generator's dimensions");
+        for x in 0..self.width {
+            for y in 0..self.depth {
+                level.set_z(x, y, self.z(x, y));
+                if self.terraces.is_some() {
+                    level.set_shape(x, y, TileShape::Flat);
+                }
+            }
+        }
+        if let Some(threshold) = self.cliff_threshold {
+            level.add_cliff_walls(threshold, cliff);
+        }
+    }
+
+    /// Generates a new level, using `cliff` for cliff walls
+    pub fn generate<FT: Default + Clone, WT: Wall>(&self, cliff: WT) -> Level<FT, WT> {
+        let mut level = Level::new(self.width, self.depth, self.min_z);
+        self.apply(&mut level, cliff);
+        level
+    }
+}
+
+
+#[test]
+fn terrain_heights() {
+    use std::f32;
+
+    let mut generator = TerrainGenerator::new(50, 50, 1);
+    generator.set_heights(-2.0, 3.0);
+    let level: Level = generator.generate(());
+    let mut min = f32::INFINITY;
+    let mut max = f32::NEG_INFINITY;
+    for x in 0..50 {
+        for y in 0..50 {
+            let z = level.z(x, y);
+            assert!((-2.0..=3.0).contains(&z));
+            min = min.min(z);
+            max = max.max(z);
+        }
+    }
+    // Noise isn't flat
+    assert!(max - min > 1.0);
+
+    generator.set_terraces(Some(0.5));
+    let terraced: Level = generator.generate(());
+    for x in 0..50 {
+        let z = terraced.z(x, 10) + 2.0;
+        assert_eq!(z, (z / 0.5).floor() * 0.5);
+        assert_eq!(terraced.shape(x, 10), TileShape::Flat);
+    }
+}
+
+#[test]
+fn terrain_masks_and_cliffs() {
+    use wall::WallPosition;
+
+    let mut generator = TerrainGenerator::new(40, 40, 9);
+    generator.set_mask(TerrainMask::Valley);
+    let level: Level = generator.generate(());
+    assert!(level.z(20, 20) < 0.5);
+
+    generator.set_mask(TerrainMask::Island)
+        .set_heights(0.0, 10.0)
+        .set_terraces(Some(2.0))
+        .set_cliff_threshold(Some(2.0));
+    let level: Level = generator.generate(());
+    let mut cliffs = 0;
+    for x in 0..39 {
+        for y in 0..39 {
+            let wall = level.wall(x, y, WallPosition::Right).is_some();
+            assert_eq!(wall, (level.z(x, y) - level.z(x + 1, y)).abs() >= 2.0);
+            if wall {
+                cliffs += 1;
+            }
+        }
+    }
+    assert!(cliffs > 0);
+}
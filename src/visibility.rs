@@ -0,0 +1,76 @@
+// (C) 2017, Élisabeth Henry
+//
+// Licensed under either of
+// 
+// Apache License, Version 2.0: http://www.apache.org/licenses/LICENSE-2.0
+// MIT license: http://opensource.org/licenses/MIT
+// at your option.
+//
+// Unless you explicitly state otherwise, any contribution intentionally submitted
+// for inclusion in the work by you, as defined in the Apache-2.0 license, shall be
+// dual licensed as above, without any additional terms or conditions.
+
+use std::f32;
+
+/// Returns a "visibility" matrix of bools centered on `pos`, casting rays
+/// around it.
+///
+/// `can_see` tells whether a line of sight can go from a tile to an adjacent
+/// one. Center ((0, 0) in relative position compared to pos) is at index
+/// `radius`.
+pub fn cast(pos: (isize, isize),
+            radius: usize,
+            can_see: &dyn Fn((isize, isize), (isize, isize)) -> bool) -> Vec<Vec<bool>> {
+    let mut res = vec![];
+    for _ in 0..(2*radius + 1) {
+        res.push(vec![false; 2 * radius + 1]);
+    }
+    // Special case of tile of the POV
+    res[radius][radius] = true;
+
+    let steps = 10 * radius;
+    let dtheta: f32 = 2.0 * f32::consts::PI / (steps as f32);
+    let two_pies = 2.0 * f32::consts::PI;
+
+    let mut theta: f32 = 0.0;
+
+    while theta <= two_pies {
+        let dx = theta.cos();
+        let dy = theta.sin();
+
+        let max_dist = (radius * radius) as f32;
+        let mut x = 0.0;
+        let mut y = 0.0;
+        let mut prev_x = pos.0;
+        let mut prev_y = pos.1;
+
+        while x * x + y * y  < max_dist {
+            x += dx;
+            y += dy;
+            let abs_x = (x + pos.0 as f32).round() as isize;
+            let abs_y = (y + pos.1 as f32).round() as isize;
+
+            if abs_x == prev_x && abs_y == prev_y {
+                // nothing to do, we already computed this tile
+                continue;
+            }
+            if can_see((prev_x, prev_y), (abs_x, abs_y)) {
+                let i = x.round() as isize + radius as isize;
+                let j = y.round() as isize + radius as isize;
+                if i < 0 || i > 2 * (radius as isize) {
+                    continue;
+                }
+                if j < 0 || j > 2 * (radius as isize) {
+                    continue;
+                }
+                res[i as usize][j as usize] = true;
+                prev_x = abs_x;
+                prev_y = abs_y;
+            } else {
+                break;
+            }
+        }
+        theta += dtheta;
+    }
+    res
+}
@@ -25,6 +25,29 @@ pub enum WallPosition {
     Bottom,
 }
 
+impl WallPosition {
+    /// Returns the position this wall would have after a quarter turn of its
+    /// tile, counterclockwise (e.g. `Right` becomes `Top`)
+    pub fn rotated(&self) -> WallPosition {
+        match *self {
+            WallPosition::Right => WallPosition::Top,
+            WallPosition::Top => WallPosition::Left,
+            WallPosition::Left => WallPosition::Bottom,
+            WallPosition::Bottom => WallPosition::Right,
+        }
+    }
+
+    /// Returns the position this wall would have if its tile was mirrored
+    /// along the x axis (`Left` and `Right` are swapped)
+    pub fn mirrored(&self) -> WallPosition {
+        match *self {
+            WallPosition::Right => WallPosition::Left,
+            WallPosition::Left => WallPosition::Right,
+            other => other,
+        }
+    }
+}
+
 
 /// Trait that must be implemented by Wall data.
 ///
@@ -34,6 +57,13 @@ pub trait Wall: Default + Clone {
     fn is_cliff(&self) -> bool;
     /// Should return true if the wall is a border wall
     fn is_border(&self) -> bool;
+    /// Height of the wall, above the floor of its tile.
+    ///
+    /// Only used for normal walls (cliff walls join the two tiles' heights),
+    /// defaults to 1.0.
+    fn height(&self) -> f32 {
+        1.0
+    }
 }
 
 impl Wall for () {
@@ -0,0 +1,357 @@
+// (C) 2017, Élisabeth Henry
+//
+// Licensed under either of
+// 
+// Apache License, Version 2.0: http://www.apache.org/licenses/LICENSE-2.0
+// MIT license: http://opensource.org/licenses/MIT
+// at your option.
+//
+// Unless you explicitly state otherwise, any contribution intentionally submitted
+// for inclusion in the work by you, as defined in the Apache-2.0 license, shall be
+// dual licensed as above, without any additional terms or conditions.
+
+use level::Level;
+use wall::Wall;
+use rng::Rng;
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::default::Default;
+
+/// Depressions shallower than this are ignored
+const EPSILON: f32 = 1e-4;
+
+/// Entry of the flooding queue
+struct Flooded {
+    z: f32,
+    // Insertion order, so water crosses flat areas in a straight line
+    order: usize,
+    index: usize,
+}
+
+impl PartialEq for Flooded {
+    fn eq(&self, other: &Flooded) -> bool {
+        self.z == other.z && self.order == other.order
+    }
+}
+
+impl Eq for Flooded {}
+
+impl PartialOrd for Flooded {
+    fn partial_cmp(&self, other: &Flooded) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Flooded {
+    fn cmp(&self, other: &Flooded) -> Ordering {
+        // BinaryHeap is a max-heap, and we want the lowest (then oldest) first
+        match other.z.partial_cmp(&self.z) {
+            Some(Ordering::Equal) | None => other.order.cmp(&self.order),
+            Some(ordering) => ordering,
+        }
+    }
+}
+
+/// Drainage of a level: height of the water if every depression was filled
+/// up to the point where it spills, and the tile where water goes next when
+/// it can't go down (on flat areas and filled depressions).
+struct Drainage {
+    filled: Vec<f32>,
+    downstream: Vec<Option<usize>>,
+}
+
+impl Drainage {
+    /// Floods the level from its borders, lowest tiles first
+    fn new<FT: Default + Clone, WT: Wall>(level: &Level<FT, WT>) -> Drainage {
+        let (width, depth) = (level.width(), level.depth());
+        let mut filled = vec![0.0; width * depth];
+        let mut downstream = vec![None; width * depth];
+        let mut closed = vec![false; width * depth];
+        let mut queue = BinaryHeap::new();
+        let mut order = 0;
+        for y in 0..depth {
+            for x in 0..width {
+                if x == 0 || y == 0 || x == width - 1 || y == depth - 1 {
+                    let index = y * width + x;
+                    filled[index] = level.z(x, y);
+                    closed[index] = true;
+                    queue.push(Flooded { z: filled[index], order, index });
+                    order += 1;
+                }
+            }
+        }
+        while let Some(Flooded { z, index, .. }) = queue.pop() {
+            let (x, y) = (index % width, index / width);
+            for &(dx, dy) in &[(-1, 0), (1, 0), (0, -1), (0, 1)] {
+                let nx = x as isize + dx;
+                let ny = y as isize + dy;
+                if nx < 0 || ny < 0 || nx >= width as isize || ny >= depth as isize {
+                    continue;
+                }
+                let next = ny as usize * width + nx as usize;
+                if closed[next] {
+                    continue;
+                }
+                closed[next] = true;
+                filled[next] = level.z(nx as usize, ny as usize).max(z);
+                downstream[next] = Some(index);
+                queue.push(Flooded { z: filled[next], order, index: next });
+                order += 1;
+            }
+        }
+        Drainage {
+            filled,
+            downstream,
+        }
+    }
+
+    /// Returns the tile where water goes from `index`: the lowest neighbour if
+    /// it is lower, else towards the point where the area spills
+    fn next(&self, width: usize, depth: usize, index: usize) -> Option<usize> {
+        let (x, y) = (index % width, index / width);
+        let mut lowest = None;
+        for &(dx, dy) in &[(-1, 0), (1, 0), (0, -1), (0, 1)] {
+            let nx = x as isize + dx;
+            let ny = y as isize + dy;
+            if nx < 0 || ny < 0 || nx >= width as isize || ny >= depth as isize {
+                continue;
+            }
+            let next = ny as usize * width + nx as usize;
+            if self.filled[next] < self.filled[index]
+                && lowest.map_or(true, |lowest| self.filled[next] < self.filled[lowest]) {
+                lowest = Some(next);
+            }
+        }
+        lowest.or(self.downstream[index])
+    }
+}
+
+/// Generates rivers and lakes on the terrain of a level.
+///
+/// Rivers start from sources picked among the highest tiles and flow
+/// downhill until they leave the level or join another river. When a river
+/// reaches a depression, it is filled into a lake up to the height where it
+/// spills, and the river goes on from there. Walls are ignored.
+///
+/// Water depth is recorded in the level (see `Level::water`).
+///
+/// # Example
+///
+/// ```
+/// use isometric::{RiverGenerator, TerrainGenerator};
+///
+/// let mut level = TerrainGenerator::new(40, 40, 3).generate::<(), ()>(());
+/// let rivers = RiverGenerator::new(3).apply(&mut level);
+///
+/// for river in &rivers {
+///     for &(x, y) in river {
+///         assert!(level.water(x, y) > 0.0);
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct RiverGenerator {
+    seed: u64,
+    sources: usize,
+    river_depth: f32,
+    lakes: bool,
+}
+
+impl RiverGenerator {
+    /// Creates a new generator.
+    ///
+    /// By default, there are 3 sources, rivers are 0.3 deep and lakes are
+    /// filled.
+    pub fn new(seed: u64) -> RiverGenerator {
+        RiverGenerator {
+            seed,
+            sources: 3,
+            river_depth: 0.3,
+            lakes: true,
+        }
+    }
+
+    /// Returns the seed
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Sets the seed, which decides where sources are
+    pub fn set_seed(&mut self, seed: u64) -> &mut Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Returns the number of sources
+    pub fn sources(&self) -> usize {
+        self.sources
+    }
+
+    /// Sets the number of sources. They are picked among the highest quarter
+    /// of the tiles.
+    pub fn set_sources(&mut self, sources: usize) -> &mut Self {
+        self.sources = sources;
+        self
+    }
+
+    /// Returns the depth of rivers
+    pub fn river_depth(&self) -> f32 {
+        self.river_depth
+    }
+
+    /// Sets the depth of rivers
+    pub fn set_river_depth(&mut self, depth: f32) -> &mut Self {
+        debug_assert!(depth > 0.0, "rivers must have some water");
+        self.river_depth = depth;
+        self
+    }
+
+    /// Returns true if depressions are filled into lakes
+    pub fn lakes(&self) -> bool {
+        self.lakes
+    }
+
+    /// Sets whether depressions rivers go through are filled into lakes. If
+    /// not, rivers simply cross them.
+    pub fn set_lakes(&mut self, lakes: bool) -> &mut Self {
+        self.lakes = lakes;
+        self
+    }
+
+    /// Adds rivers and lakes to the level, and returns the tiles of each
+    /// river, from its source downstream
+    pub fn apply<FT: Default + Clone, WT: Wall>(&self, level: &mut Level<FT, WT>) -> Vec<Vec<(usize, usize)>> {
+        let width = level.width();
+        let mut tiles: Vec<usize> = (0..width * level.depth()).collect();
+        // Stable sort, so the output only depends on the seed
+        tiles.sort_by(|&a, &b| {
+            let za = level.z(a % width, a / width);
+            let zb = level.z(b % width, b / width);
+            zb.partial_cmp(&za).unwrap_or(Ordering::Equal)
+        });
+        let candidates = (tiles.len() + 3) / 4;
+        tiles.truncate(candidates);
+
+        let mut rng = Rng::new(self.seed);
+        let mut rivers = vec![];
+        for _ in 0..self.sources {
+            if tiles.is_empty() {
+                break;
+            }
+            let i = rng.range(0, tiles.len());
+            let source = tiles.swap_remove(i);
+            rivers.push(self.trace(level, (source % width, source / width)));
+        }
+        rivers
+    }
+
+    /// Adds a single river starting from `source`, and returns its tiles
+    /// (including the tile where it joins another river, if it does)
+    pub fn trace<FT: Default + Clone, WT: Wall>(&self, level: &mut Level<FT, WT>,
+                                               source: (usize, usize)) -> Vec<(usize, usize)> {
+        let (width, depth) = (level.width(), level.depth());
+        let drainage = Drainage::new(level);
+        let mut wet = vec![false; width * depth];
+        for y in 0..depth {
+            for x in 0..width {
+                wet[y * width + x] = level.water(x, y) > 0.0;
+            }
+        }
+        let mut river = vec![];
+        let mut current = Some(source.1 * width + source.0);
+        while let Some(index) = current {
+            let (x, y) = (index % width, index / width);
+            river.push((x, y));
+            if river.len() > 1 && wet[index] {
+                // Joined another river or a lake that was already there
+                break;
+            }
+            if self.lakes && drainage.filled[index] > level.z(x, y) + EPSILON {
+                self.fill_lake(level, &drainage, (x, y));
+            }
+            let water = level.water(x, y).max(self.river_depth);
+            level.set_water(x, y, water);
+            current = drainage.next(width, depth, index);
+        }
+        river
+    }
+
+    /// Fills the depression containing tile (x, y) up to its spill height
+    fn fill_lake<FT: Default + Clone, WT: Wall>(&self, level: &mut Level<FT, WT>,
+                                               drainage: &Drainage, start: (usize, usize)) {
+        let (width, depth) = (level.width(), level.depth());
+        let surface = drainage.filled[start.1 * width + start.0];
+        let mut seen = vec![false; width * depth];
+        let mut stack = vec![start];
+        seen[start.1 * width + start.0] = true;
+        while let Some((x, y)) = stack.pop() {
+            let water = level.water(x, y).max(surface - level.z(x, y));
+            level.set_water(x, y, water);
+            for &(dx, dy) in &[(-1, 0), (1, 0), (0, -1), (0, 1)] {
+                let nx = x as isize + dx;
+                let ny = y as isize + dy;
+                if nx < 0 || ny < 0 || nx >= width as isize || ny >= depth as isize {
+                    continue;
+                }
+                let (nx, ny) = (nx as usize, ny as usize);
+                let index = ny * width + nx;
+                if !seen[index] && (drainage.filled[index] - surface).abs() < EPSILON
+                    && level.z(nx, ny) < surface - EPSILON {
+                    seen[index] = true;
+                    stack.push((nx, ny));
+                }
+            }
+        }
+    }
+}
+
+
+#[test]
+fn river_flows_downhill() {
+    // Slope going down towards x = 0
+    let mut level: Level = Level::new(10, 5, 0.0);
+    for x in 0..10 {
+        for y in 0..5 {
+            level.set_z(x, y, x as f32);
+        }
+    }
+    let river = RiverGenerator::new(0).trace(&mut level, (8, 2));
+    assert_eq!(river.first(), Some(&(8, 2)));
+    assert_eq!(river.last(), Some(&(0, 2)));
+    for pair in river.windows(2) {
+        assert!(level.z(pair[1].0, pair[1].1) <= level.z(pair[0].0, pair[0].1));
+    }
+    assert_eq!(level.water(5, 2), 0.3);
+    assert_eq!(level.water(5, 3), 0.0);
+
+    // A second river joins the first one
+    let other = RiverGenerator::new(0).trace(&mut level, (9, 2));
+    assert_eq!(other, vec![(9, 2), (8, 2)]);
+}
+
+#[test]
+fn lakes_fill_depressions() {
+    // Slope going down towards x = 0, with a pit at (5, 2)
+    let mut level: Level = Level::new(10, 5, 0.0);
+    for x in 0..10 {
+        for y in 0..5 {
+            level.set_z(x, y, x as f32);
+        }
+    }
+    level.set_z(5, 2, 2.0);
+    level.set_z(6, 2, 2.5);
+
+    let mut generator = RiverGenerator::new(0);
+    let river = generator.trace(&mut level, (8, 2));
+    assert_eq!(river.last().map(|pos| pos.0), Some(0));
+    // The pit spills at height 4.0, through (4, 2)
+    assert_eq!(level.water_z(5, 2), Some(4.0));
+    assert_eq!(level.water_z(6, 2), Some(4.0));
+
+    let mut level: Level = Level::new(10, 5, 0.0);
+    level.set_z(5, 2, -1.0);
+    generator.set_lakes(false);
+    generator.trace(&mut level, (5, 2));
+    assert_eq!(level.water(5, 2), 0.3);
+}
@@ -0,0 +1,388 @@
+// (C) 2017, Élisabeth Henry
+//
+// Licensed under either of
+// 
+// Apache License, Version 2.0: http://www.apache.org/licenses/LICENSE-2.0
+// MIT license: http://opensource.org/licenses/MIT
+// at your option.
+//
+// Unless you explicitly state otherwise, any contribution intentionally submitted
+// for inclusion in the work by you, as defined in the Apache-2.0 license, shall be
+// dual licensed as above, without any additional terms or conditions.
+
+use level::Level;
+use wall::Wall;
+use wall::WallPosition;
+use rng::Rng;
+
+use std::default::Default;
+use std::error;
+use std::fmt;
+
+/// Sides of a tile, in the order used to index walls and adjacency rules
+const SIDES: [(WallPosition, isize, isize); 4] = [(WallPosition::Left, -1, 0),
+                                                   (WallPosition::Right, 1, 0),
+                                                   (WallPosition::Bottom, 0, -1),
+                                                   (WallPosition::Top, 0, 1)];
+
+/// A kind of tile found in the example
+#[derive(Debug, Clone)]
+struct Pattern<FT, WT> {
+    z: f32,
+    data: FT,
+    walls: [Option<WT>; 4],
+}
+
+impl<FT: PartialEq, WT> Pattern<FT, WT> {
+    /// Two tiles are the same pattern if they have the same height, floor data
+    /// and walls on the same sides (wall data is not compared)
+    fn matches(&self, other: &Pattern<FT, WT>) -> bool {
+        self.z == other.z && self.data == other.data
+            && self.walls.iter().zip(other.walls.iter()).all(|(a, b)| a.is_some() == b.is_some())
+    }
+}
+
+/// Error when generating a level with `WfcGenerator`
+#[derive(Debug, Clone, PartialEq)]
+pub enum WfcError {
+    /// The example level has no tiles
+    EmptyExample,
+    /// No level of the requested size respects the constraints of the example
+    Unsatisfiable,
+    /// Backtracking was given up after the given number of attempts
+    TooManyBacktracks(usize),
+}
+
+impl fmt::Display for WfcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            WfcError::EmptyExample => write!(f, "example level is empty"),
+            WfcError::Unsatisfiable => write!(f, "constraints of the example can't be satisfied"),
+            WfcError::TooManyBacktracks(n) => write!(f, "gave up after backtracking {} times", n),
+        }
+    }
+}
+
+impl error::Error for WfcError {
+    fn description(&self) -> &str {
+        match *self {
+            WfcError::EmptyExample => "example level is empty",
+            WfcError::Unsatisfiable => "unsatisfiable constraints",
+            WfcError::TooManyBacktracks(_) => "too many backtracks",
+        }
+    }
+}
+
+/// Generates levels with the "wave function collapse" algorithm.
+///
+/// The generator learns from an example level which kinds of tiles (height,
+/// floor data and sides that have walls) exist, how often they appear and
+/// which ones can be next to each other. It then builds new levels where
+/// every pair of adjacent tiles could also be found in the example, picking
+/// the most constrained tile at each step and backtracking when it reaches a
+/// dead end. The output only depends on the example, the settings and the
+/// seed.
+///
+/// # Example
+///
+/// ```
+/// use isometric::{Level, WfcGenerator};
+///
+/// // Stripes of height 0 and 1, two tiles wide
+/// let mut example: Level = Level::new(8, 2, 0.0);
+/// for x in 0..8 {
+///     for y in 0..2 {
+///         example.set_z(x, y, if x % 4 < 2 { 0.0 } else { 1.0 });
+///     }
+/// }
+///
+/// let generator = WfcGenerator::new(&example, 5);
+/// let level = generator.generate(20, 10).unwrap();
+/// for x in 0..20 {
+///     assert_eq!(level.z(x, 0), level.z(x, 9));
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct WfcGenerator<FT=(), WT=()> {
+    patterns: Vec<Pattern<FT, WT>>,
+    weights: Vec<f32>,
+    // allowed[p][side][q]: pattern q can be on that side of pattern p
+    allowed: Vec<[Vec<bool>; 4]>,
+    seed: u64,
+    max_backtracks: usize,
+}
+
+impl<FT: Default + Clone + PartialEq,
+     WT: Wall> WfcGenerator<FT, WT> {
+    /// Creates a new generator, learning tiles and adjacency rules from `example`.
+    ///
+    /// By default, up to 1000 backtracks are allowed.
+    pub fn new(example: &Level<FT, WT>, seed: u64) -> WfcGenerator<FT, WT> {
+        let (width, depth) = (example.width(), example.depth());
+        let mut patterns: Vec<Pattern<FT, WT>> = vec![];
+        let mut weights = vec![];
+        let mut tiles = vec![0; width * depth];
+        for y in 0..depth {
+            for x in 0..width {
+                let pattern = Pattern {
+                    z: example.z(x, y),
+                    data: example.floor_data(x, y).clone(),
+                    walls: [example.wall(x, y, WallPosition::Left).clone(),
+                            example.wall(x, y, WallPosition::Right).clone(),
+                            example.wall(x, y, WallPosition::Bottom).clone(),
+                            example.wall(x, y, WallPosition::Top).clone()],
+                };
+                let index = match patterns.iter().position(|p| p.matches(&pattern)) {
+                    Some(index) => index,
+                    None => {
+                        patterns.push(pattern);
+                        weights.push(0.0);
+                        patterns.len() - 1
+                    }
+                };
+                weights[index] += 1.0;
+                tiles[y * width + x] = index;
+            }
+        }
+
+        let n = patterns.len();
+        let mut allowed: Vec<[Vec<bool>; 4]> = (0..n)
+            .map(|_| [vec![false; n], vec![false; n], vec![false; n], vec![false; n]])
+            .collect();
+        for y in 0..depth {
+            for x in 0..width {
+                let p = tiles[y * width + x];
+                for (side, &(_, dx, dy)) in SIDES.iter().enumerate() {
+                    let nx = x as isize + dx;
+                    let ny = y as isize + dy;
+                    if nx < 0 || ny < 0 || nx >= width as isize || ny >= depth as isize {
+                        continue;
+                    }
+                    let q = tiles[ny as usize * width + nx as usize];
+                    allowed[p][side][q] = true;
+                }
+            }
+        }
+
+        WfcGenerator {
+            patterns,
+            weights,
+            allowed,
+            seed,
+            max_backtracks: 1000,
+        }
+    }
+
+    /// Returns the number of different kinds of tiles found in the example
+    pub fn patterns(&self) -> usize {
+        self.patterns.len()
+    }
+
+    /// Returns the seed
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Sets the seed
+    pub fn set_seed(&mut self, seed: u64) -> &mut Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Returns the maximum number of backtracks
+    pub fn max_backtracks(&self) -> usize {
+        self.max_backtracks
+    }
+
+    /// Sets the maximum number of backtracks before giving up
+    pub fn set_max_backtracks(&mut self, max_backtracks: usize) -> &mut Self {
+        self.max_backtracks = max_backtracks;
+        self
+    }
+
+    /// Removes from the wave the patterns that are no longer possible, starting
+    /// from the tiles in `stack`. Returns false if a tile has no possible
+    /// pattern left.
+    fn propagate(&self, wave: &mut [Vec<bool>], width: usize, depth: usize,
+                 mut stack: Vec<usize>) -> bool {
+        let n = self.patterns.len();
+        while let Some(index) = stack.pop() {
+            let (x, y) = (index % width, index / width);
+            for (side, &(_, dx, dy)) in SIDES.iter().enumerate() {
+                let nx = x as isize + dx;
+                let ny = y as isize + dy;
+                if nx < 0 || ny < 0 || nx >= width as isize || ny >= depth as isize {
+                    continue;
+                }
+                let next = ny as usize * width + nx as usize;
+                let mut changed = false;
+                let mut possible = 0;
+                for q in 0..n {
+                    if !wave[next][q] {
+                        continue;
+                    }
+                    if (0..n).any(|p| wave[index][p] && self.allowed[p][side][q]) {
+                        possible += 1;
+                    } else {
+                        wave[next][q] = false;
+                        changed = true;
+                    }
+                }
+                if possible == 0 {
+                    return false;
+                }
+                if changed {
+                    stack.push(next);
+                }
+            }
+        }
+        true
+    }
+
+    /// Generates a new level of the given dimensions
+    pub fn generate(&self, width: usize, depth: usize) -> Result<Level<FT, WT>, WfcError> {
+        let n = self.patterns.len();
+        if n == 0 {
+            return Err(WfcError::EmptyExample);
+        }
+        let mut rng = Rng::new(self.seed);
+        let mut wave = vec![vec![true; n]; width * depth];
+        // Choices made so far, with the wave before each of them
+        let mut decisions: Vec<(Vec<Vec<bool>>, usize, usize)> = vec![];
+        let mut backtracks = 0;
+
+        let mut consistent = self.propagate(&mut wave, width, depth, (0..width * depth).collect());
+        loop {
+            if !consistent {
+                // Undo the last choice and forbid it
+                let (previous, index, pattern) = match decisions.pop() {
+                    Some(decision) => decision,
+                    None => return Err(WfcError::Unsatisfiable),
+                };
+                if backtracks == self.max_backtracks {
+                    return Err(WfcError::TooManyBacktracks(backtracks));
+                }
+                backtracks += 1;
+                wave = previous;
+                wave[index][pattern] = false;
+                consistent = wave[index].iter().any(|&p| p)
+                    && self.propagate(&mut wave, width, depth, vec![index]);
+                continue;
+            }
+
+            // Find the undecided tiles with the fewest possibilities
+            let mut fewest = n + 1;
+            let mut candidates = vec![];
+            for (index, tile) in wave.iter().enumerate() {
+                let count = tile.iter().filter(|&&p| p).count();
+                if count <= 1 || count > fewest {
+                    continue;
+                }
+                if count < fewest {
+                    fewest = count;
+                    candidates.clear();
+                }
+                candidates.push(index);
+            }
+            if candidates.is_empty() {
+                break;
+            }
+            let index = candidates[rng.range(0, candidates.len())];
+
+            // Pick one of its patterns, according to their frequency in the example
+            let total: f32 = (0..n).filter(|&p| wave[index][p]).map(|p| self.weights[p]).sum();
+            let mut target = rng.float() * total;
+            let mut pattern = n;
+            for (p, &possible) in wave[index].iter().enumerate() {
+                if possible {
+                    pattern = p;
+                    if target < self.weights[p] {
+                        break;
+                    }
+                    target -= self.weights[p];
+                }
+            }
+
+            decisions.push((wave.clone(), index, pattern));
+            for (p, possible) in wave[index].iter_mut().enumerate() {
+                *possible = p == pattern;
+            }
+            consistent = self.propagate(&mut wave, width, depth, vec![index]);
+        }
+
+        let mut level = Level::new(width, depth, 0.0);
+        for y in 0..depth {
+            for x in 0..width {
+                let p = wave[y * width + x].iter().position(|&p| p).unwrap();
+                let pattern = &self.patterns[p];
+                level.set_z(x, y, pattern.z);
+                level.set_floor_data(x, y, pattern.data.clone());
+                for (side, &(position, _, _)) in SIDES.iter().enumerate() {
+                    if pattern.walls[side].is_some() {
+                        level.set_wall(x, y, position, pattern.walls[side].clone());
+                    }
+                }
+            }
+        }
+        Ok(level)
+    }
+}
+
+
+#[test]
+fn wfc_respects_adjacency() {
+    // A room with walls around it, on a floor with some data
+    let mut example: Level<u8, ()> = Level::new(6, 6, 0.0);
+    for x in 1..4 {
+        for y in 1..4 {
+            example.set_z(x, y, 1.0);
+            example.set_floor_data(x, y, 2);
+        }
+    }
+    for i in 1..4 {
+        example.set_wall(i, 1, WallPosition::Bottom, Some(()));
+        example.set_wall(i, 3, WallPosition::Top, Some(()));
+        example.set_wall(1, i, WallPosition::Left, Some(()));
+        example.set_wall(3, i, WallPosition::Right, Some(()));
+    }
+
+    let generator = WfcGenerator::new(&example, 3);
+    let level = generator.generate(15, 12).unwrap();
+    let same = generator.generate(15, 12).unwrap();
+    for x in 0..15 {
+        for y in 0..12 {
+            assert_eq!(level.z(x, y), same.z(x, y));
+            // Raised floor is always surrounded by walls
+            if x + 1 < 15 && level.z(x, y) != level.z(x + 1, y) {
+                assert!(level.wall(x, y, WallPosition::Right).is_some());
+            }
+            if y + 1 < 12 && level.z(x, y) != level.z(x, y + 1) {
+                assert!(level.wall(x, y, WallPosition::Top).is_some());
+            }
+            assert_eq!(*level.floor_data(x, y) == 2, level.z(x, y) == 1.0);
+        }
+    }
+}
+
+#[test]
+fn wfc_errors() {
+    // A single tile: no tile can be next to another one
+    let example: Level = Level::new(1, 1, 0.0);
+    let generator = WfcGenerator::new(&example, 0);
+    assert!(generator.generate(1, 1).is_ok());
+    assert_eq!(generator.generate(2, 1).unwrap_err(), WfcError::Unsatisfiable);
+
+    // Heights 0, 1, 2 in a row: no tile can be above another one, and 0 (2)
+    // can't have any tile on its left (right)
+    let mut example: Level = Level::new(3, 1, 0.0);
+    example.set_z(1, 0, 1.0);
+    example.set_z(2, 0, 2.0);
+    let generator = WfcGenerator::new(&example, 0);
+    assert_eq!(generator.patterns(), 3);
+    assert!(generator.generate(3, 1).is_ok());
+    assert_eq!(generator.generate(4, 1).unwrap_err(), WfcError::Unsatisfiable);
+    assert_eq!(generator.generate(3, 2).unwrap_err(), WfcError::Unsatisfiable);
+
+    let empty: Level = Level::new(0, 0, 0.0);
+    assert_eq!(WfcGenerator::new(&empty, 0).generate(2, 2).unwrap_err(), WfcError::EmptyExample);
+}